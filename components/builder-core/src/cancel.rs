@@ -0,0 +1,82 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// A flag for stopping a long operation from another thread, as on Ctrl-C.
+// The operations taking one check it as they go, and give up with Cancelled
+// as soon as they see it set, leaving whatever they read as it was. Clones
+// share the flag, and a token that was never cloned is never cancelled.
+
+use std::{error,
+          fmt,
+          sync::{atomic::{AtomicBool,
+                          Ordering},
+                 Arc}};
+
+#[derive(Clone, Debug, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cancelled;
+
+impl Cancel {
+    // Sets the flag, returning whether it was already set
+    pub fn cancel(&self) -> bool { self.0.swap(true, Ordering::SeqCst) }
+
+    pub fn reset(&self) { self.0.store(false, Ordering::SeqCst) }
+
+    pub fn is_cancelled(&self) -> bool { self.0.load(Ordering::Relaxed) }
+
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Runs an operation taking a token with one nothing can cancel, for the
+// callers that have no use for stopping it
+pub fn uncancelled<T, F>(op: F) -> T
+    where F: FnOnce(&Cancel) -> Result<T, Cancelled>
+{
+    op(&Cancel::default()).expect("Unshared cancel token was cancelled!")
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "interrupted") }
+}
+
+impl error::Error for Cancelled {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_the_flag() {
+        let cancel = Cancel::default();
+        let watcher = cancel.clone();
+        assert_eq!(cancel.check(), Ok(()));
+
+        assert!(!watcher.cancel());
+        assert!(watcher.cancel());
+        assert_eq!(cancel.check(), Err(Cancelled));
+        assert_eq!(Cancelled.to_string(), "interrupted");
+
+        cancel.reset();
+        assert!(!watcher.is_cancelled());
+        assert_eq!(uncancelled(|cancel| cancel.check().map(|_| 1)), 1);
+    }
+}
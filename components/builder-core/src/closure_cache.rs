@@ -0,0 +1,169 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// A cache of computed values, such as the reverse closures of the nodes of a
+// graph, bounded both in entries and in the total weight of the values (the
+// number of nodes of a closure), evicting the least recently used entries
+// first. The owner clears it whenever what the values were computed from
+// changes. Lookups that found nothing count as misses, which the stats report
+// along with the hits.
+
+use std::{collections::HashMap,
+          hash::Hash,
+          sync::Arc};
+
+pub const DEFAULT_MAX_ENTRIES: usize = 64;
+pub const DEFAULT_MAX_WEIGHT: usize = 1_000_000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub weight:  usize,
+    pub hits:    u64,
+    pub misses:  u64,
+}
+
+struct Entry<V> {
+    value:     Arc<V>,
+    weight:    usize,
+    last_used: u64,
+}
+
+pub struct ClosureCache<K, V> {
+    max_entries: usize,
+    max_weight:  usize,
+    entries:     HashMap<K, Entry<V>>,
+    weight:      usize,
+    tick:        u64,
+    hits:        u64,
+    misses:      u64,
+}
+
+impl<K: Clone + Eq + Hash, V> Default for ClosureCache<K, V> {
+    fn default() -> Self { ClosureCache::new(DEFAULT_MAX_ENTRIES, DEFAULT_MAX_WEIGHT) }
+}
+
+impl<K: Clone + Eq + Hash, V> ClosureCache<K, V> {
+    pub fn new(max_entries: usize, max_weight: usize) -> Self {
+        ClosureCache { max_entries,
+                       max_weight,
+                       entries: HashMap::new(),
+                       weight: 0,
+                       tick: 0,
+                       hits: 0,
+                       misses: 0 }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<Arc<V>> {
+        self.tick += 1;
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                entry.last_used = self.tick;
+                self.hits += 1;
+                Some(entry.value.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    // Caches the value unless it alone weighs more than the cache may hold,
+    // evicting the least recently used entries to make room for it
+    pub fn insert(&mut self, key: K, value: V, weight: usize) -> Arc<V> {
+        let value = Arc::new(value);
+        if weight > self.max_weight || self.max_entries == 0 {
+            return value;
+        }
+        if let Some(entry) = self.entries.remove(&key) {
+            self.weight -= entry.weight;
+        }
+        while self.entries.len() >= self.max_entries || self.weight + weight > self.max_weight {
+            self.evict_least_recently_used();
+        }
+        self.tick += 1;
+        self.weight += weight;
+        self.entries.insert(key,
+                            Entry { value: value.clone(),
+                                    weight,
+                                    last_used: self.tick });
+        value
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let oldest = self.entries
+                         .iter()
+                         .min_by_key(|(_, entry)| entry.last_used)
+                         .map(|(key, _)| key.clone());
+        if let Some(entry) = oldest.and_then(|key| self.entries.remove(&key)) {
+            self.weight -= entry.weight;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.weight = 0;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats { entries: self.entries.len(),
+                     weight:  self.weight,
+                     hits:    self.hits,
+                     misses:  self.misses, }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookups_are_counted_and_cleared() {
+        let mut cache = ClosureCache::default();
+        assert!(cache.get(&1).is_none());
+        assert_eq!(*cache.insert(1, "one", 3), "one");
+        assert_eq!(cache.get(&1).as_deref(), Some(&"one"));
+        assert_eq!(cache.stats(),
+                   CacheStats { entries: 1,
+                                weight:  3,
+                                hits:    1,
+                                misses:  1, });
+
+        cache.clear();
+        assert!(cache.get(&1).is_none());
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.stats().weight, 0);
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted() {
+        let mut cache = ClosureCache::new(2, 10);
+        cache.insert(1, 1, 1);
+        cache.insert(2, 2, 1);
+        cache.get(&1);
+        cache.insert(3, 3, 1);
+        assert!(cache.get(&2).is_none());
+        assert!(cache.get(&1).is_some());
+        assert!(cache.get(&3).is_some());
+
+        // Too heavy for the rest to stay, or to be cached at all
+        cache.insert(4, 4, 10);
+        assert_eq!(cache.stats().entries, 1);
+        assert_eq!(cache.stats().weight, 10);
+        assert_eq!(*cache.insert(5, 5, 11), 5);
+        assert!(cache.get(&5).is_none());
+        assert!(cache.get(&4).is_some());
+    }
+}
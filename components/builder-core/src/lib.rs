@@ -33,6 +33,8 @@ use habitat_core as hab_core;
 pub mod access_token;
 pub mod api_client;
 pub mod build_config;
+pub mod cancel;
+pub mod closure_cache;
 pub mod error;
 pub mod http_client;
 pub mod integrations;
@@ -44,7 +46,9 @@ pub mod package_graph;
 pub mod privilege;
 pub mod rdeps;
 pub mod rpc;
+pub mod search_index;
 pub mod socket;
 pub mod target_graph;
+pub mod version;
 
 pub use crate::error::Error;
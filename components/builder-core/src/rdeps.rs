@@ -12,14 +12,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::cancel::{uncancelled,
+                    Cancel,
+                    Cancelled};
 use petgraph::{algo::{is_cyclic_directed,
                       toposort},
                graph::NodeIndex,
-               visit::{Bfs,
-                       Walker},
+               Direction,
                Graph};
 use std::collections::{HashMap,
-                       HashSet};
+                       HashSet,
+                       VecDeque};
 
 #[derive(Debug, PartialEq)]
 pub enum GraphErr {
@@ -28,7 +31,18 @@ pub enum GraphErr {
 
 pub type GType = usize;
 
-pub fn rdeps(g: &Graph<GType, GType>, n: NodeIndex) -> Result<Vec<GType>, GraphErr> {
+pub fn rdeps<E>(g: &Graph<GType, E>, n: NodeIndex) -> Result<Vec<GType>, GraphErr> {
+    let (v, _) = rdeps_pruned(g, n, &HashSet::new())?;
+    Ok(v)
+}
+
+// Like rdeps, but the traversal neither reports nor continues through the
+// pruned nodes. Also returns the number of pruned nodes that were reached.
+#[allow(clippy::implicit_hasher)]
+pub fn rdeps_pruned<E>(g: &Graph<GType, E>,
+                       n: NodeIndex,
+                       pruned: &HashSet<NodeIndex>)
+                       -> Result<(Vec<GType>, usize), GraphErr> {
     if is_cyclic_directed(&g) {
         error!("Input graph should not be cyclic!");
         return Err(GraphErr::GraphCyclic);
@@ -40,8 +54,22 @@ pub fn rdeps(g: &Graph<GType, GType>, n: NodeIndex) -> Result<Vec<GType>, GraphE
                                           .map(|k| k.index())
                                           .collect();
 
-    #[allow(clippy::redundant_closure)]
-    let bfs: Vec<GType> = Bfs::new(&g, n).iter(&g).map(|k| k.index()).collect();
+    let mut bfs_set: HashSet<GType> = HashSet::new();
+    let mut pruned_set: HashSet<GType> = HashSet::new();
+    let mut queue = VecDeque::new();
+
+    bfs_set.insert(n.index());
+    queue.push_back(n);
+
+    while let Some(node) = queue.pop_front() {
+        for next in g.neighbors_directed(node, Direction::Outgoing) {
+            if pruned.contains(&next) {
+                pruned_set.insert(next.index());
+            } else if bfs_set.insert(next.index()) {
+                queue.push_back(next);
+            }
+        }
+    }
 
     let mut topo_map: HashMap<usize, usize> = HashMap::new(); // Node -> Topo index
 
@@ -53,7 +81,6 @@ pub fn rdeps(g: &Graph<GType, GType>, n: NodeIndex) -> Result<Vec<GType>, GraphE
     let start: usize = n.index();
     let mut curr: usize = topo_map[&start]; // Where to start in topo array
 
-    let mut bfs_set: HashSet<&usize> = bfs.iter().collect();
     let mut v: Vec<GType> = Vec::new();
 
     bfs_set.remove(&start);
@@ -65,13 +92,142 @@ pub fn rdeps(g: &Graph<GType, GType>, n: NodeIndex) -> Result<Vec<GType>, GraphE
         curr += 1;
     }
 
-    Ok(v)
+    Ok((v, pruned_set.len()))
+}
+
+// Walks the reverse dependencies of a node breadth first, returning the
+// nodes newly reached at each depth (starting at depth 1). Nodes are never
+// revisited, so this is safe to use on cyclic graphs. A max_depth of 0 means
+// no limit.
+pub fn rdeps_levels<E>(g: &Graph<GType, E>, n: NodeIndex, max_depth: usize) -> Vec<Vec<GType>> {
+    let mut levels = Vec::new();
+    let mut visited: HashSet<NodeIndex> = HashSet::new();
+    let mut frontier = vec![n];
+
+    visited.insert(n);
+
+    while !frontier.is_empty() && (max_depth == 0 || levels.len() < max_depth) {
+        let mut next_frontier = Vec::new();
+
+        for node in frontier {
+            for next in g.neighbors_directed(node, Direction::Outgoing) {
+                if visited.insert(next) {
+                    next_frontier.push(next);
+                }
+            }
+        }
+
+        if next_frontier.is_empty() {
+            break;
+        }
+
+        let mut level: Vec<GType> = next_frontier.iter().map(|k| k.index()).collect();
+        level.sort();
+        levels.push(level);
+        frontier = next_frontier;
+    }
+
+    levels
+}
+
+// Returns the minimum depth of each reverse dependency of a node, 1 for its
+// direct dependents, found breadth first without reaching through the pruned
+// nodes. Nodes are never revisited, so a cycle neither loops nor changes the
+// depth of the nodes on it.
+#[allow(clippy::implicit_hasher)]
+pub fn rdeps_depths<E>(g: &Graph<GType, E>,
+                       n: NodeIndex,
+                       pruned: &HashSet<NodeIndex>)
+                       -> HashMap<GType, usize> {
+    let (depths, _) = rdeps_depths_limited(g, n, pruned, 0);
+    depths
+}
+
+// Like rdeps_depths, but the walk stops once it has reached max_nodes
+// reverse dependencies, which are then the nearest ones. Also returns whether
+// it stopped before reaching them all. A max_nodes of 0 means no limit.
+#[allow(clippy::implicit_hasher)]
+pub fn rdeps_depths_limited<E>(g: &Graph<GType, E>,
+                               n: NodeIndex,
+                               pruned: &HashSet<NodeIndex>,
+                               max_nodes: usize)
+                               -> (HashMap<GType, usize>, bool) {
+    uncancelled(|cancel| rdeps_depths_cancellable(g, n, pruned, max_nodes, cancel))
+}
+
+// Like rdeps_depths_limited, but the walk gives up once the token is
+// cancelled, which it checks before visiting each node
+#[allow(clippy::implicit_hasher)]
+pub fn rdeps_depths_cancellable<E>(g: &Graph<GType, E>,
+                                   n: NodeIndex,
+                                   pruned: &HashSet<NodeIndex>,
+                                   max_nodes: usize,
+                                   cancel: &Cancel)
+                                   -> Result<(HashMap<GType, usize>, bool), Cancelled> {
+    let mut depths: HashMap<GType, usize> = HashMap::new();
+    let mut queue = VecDeque::new();
+
+    depths.insert(n.index(), 0);
+    queue.push_back(n);
+
+    while let Some(node) = queue.pop_front() {
+        cancel.check()?;
+        let depth = depths[&node.index()] + 1;
+        for next in g.neighbors_directed(node, Direction::Outgoing) {
+            if !pruned.contains(&next) && !depths.contains_key(&next.index()) {
+                // The start node is in the map too
+                if max_nodes != 0 && depths.len() > max_nodes {
+                    depths.remove(&n.index());
+                    return Ok((depths, true));
+                }
+                depths.insert(next.index(), depth);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    depths.remove(&n.index());
+    Ok((depths, false))
+}
+
+// Counts the reverse dependencies of every node, as rdeps_depths finds them,
+// indexed by node. The walks share one record of the node each node was last
+// reached from, so nothing is allocated per walk, and the nodes without
+// dependents need none. Gives up once the token is cancelled, which it checks
+// for each node it counts and each it visits.
+pub fn rdeps_counts<E>(g: &Graph<GType, E>, cancel: &Cancel) -> Result<Vec<usize>, Cancelled> {
+    let mut counts = vec![0; g.node_count()];
+    let mut reached_from = vec![usize::max_value(); g.node_count()];
+    let mut queue = VecDeque::new();
+
+    for n in g.node_indices() {
+        cancel.check()?;
+        if g.neighbors_directed(n, Direction::Outgoing).next().is_none() {
+            continue;
+        }
+        reached_from[n.index()] = n.index();
+        queue.push_back(n);
+
+        while let Some(node) = queue.pop_front() {
+            cancel.check()?;
+            for next in g.neighbors_directed(node, Direction::Outgoing) {
+                if reached_from[next.index()] != n.index() {
+                    reached_from[next.index()] = n.index();
+                    counts[n.index()] += 1;
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+
+    Ok(counts)
 }
 
 #[cfg(test)]
 mod tests {
     use crate::rdeps::*;
     use petgraph::Graph;
+    use std::collections::HashSet;
 
     #[test]
     fn fails_with_cyclic_graph() {
@@ -122,4 +278,143 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn pruned_graph_works() {
+        let mut deps = Graph::<usize, usize>::new();
+        let a = deps.add_node(10);
+        let b = deps.add_node(11);
+        let c = deps.add_node(12);
+        let d = deps.add_node(13);
+        let e = deps.add_node(14);
+        let f = deps.add_node(15);
+
+        deps.extend_with_edges(&[(a, c), (b, c), (c, f), (c, e), (d, e), (e, f)]);
+
+        let mut pruned = HashSet::new();
+        pruned.insert(e);
+
+        match rdeps_pruned(&deps, a, &pruned) {
+            Ok((v, count)) => {
+                static EXPECTED: &[usize] = &[2, 5];
+                assert_eq!(v.as_slice(), EXPECTED);
+                assert_eq!(count, 1);
+            }
+            Err(e) => {
+                panic!("Failed with error: {:?}", e);
+            }
+        }
+
+        pruned.insert(c);
+
+        match rdeps_pruned(&deps, b, &pruned) {
+            Ok((v, count)) => {
+                assert!(v.is_empty());
+                assert_eq!(count, 1);
+            }
+            Err(e) => {
+                panic!("Failed with error: {:?}", e);
+            }
+        }
+    }
+
+    #[test]
+    fn levels_handle_cycles() {
+        let mut deps = Graph::<usize, usize>::new();
+        let a = deps.add_node(10);
+        let b = deps.add_node(11);
+        let c = deps.add_node(12);
+        let d = deps.add_node(13);
+
+        deps.extend_with_edges(&[(a, b), (a, c), (b, d), (c, d), (d, a)]);
+
+        let levels = rdeps_levels(&deps, a, 0);
+        assert_eq!(levels, vec![vec![1, 2], vec![3]]);
+
+        let levels = rdeps_levels(&deps, a, 1);
+        assert_eq!(levels, vec![vec![1, 2]]);
+    }
+
+    #[test]
+    fn depths_are_shortest_and_handle_cycles() {
+        let mut deps = Graph::<usize, usize>::new();
+        let a = deps.add_node(10);
+        let b = deps.add_node(11);
+        let c = deps.add_node(12);
+        let d = deps.add_node(13);
+        let e = deps.add_node(14);
+
+        deps.extend_with_edges(&[(a, b), (b, c), (c, d), (a, d), (d, e), (e, b)]);
+
+        let depths = rdeps_depths(&deps, a, &HashSet::new());
+        let mut depths: Vec<(usize, usize)> = depths.into_iter().collect();
+        depths.sort();
+        assert_eq!(depths, vec![(1, 1), (2, 2), (3, 1), (4, 2)]);
+
+        let mut pruned = HashSet::new();
+        pruned.insert(d);
+        let depths = rdeps_depths(&deps, a, &pruned);
+        let mut depths: Vec<(usize, usize)> = depths.into_iter().collect();
+        depths.sort();
+        assert_eq!(depths, vec![(1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn depths_stop_at_the_node_limit() {
+        let mut deps = Graph::<usize, usize>::new();
+        let a = deps.add_node(10);
+        let b = deps.add_node(11);
+        let c = deps.add_node(12);
+        let d = deps.add_node(13);
+        deps.extend_with_edges(&[(a, b), (a, c), (b, d)]);
+
+        let none = HashSet::new();
+        let (depths, truncated) = rdeps_depths_limited(&deps, a, &none, 2);
+        let mut depths: Vec<(usize, usize)> = depths.into_iter().collect();
+        depths.sort();
+        assert_eq!(depths, vec![(1, 1), (2, 1)]);
+        assert!(truncated);
+
+        let (depths, truncated) = rdeps_depths_limited(&deps, a, &none, 3);
+        assert_eq!(depths.len(), 3);
+        assert!(!truncated);
+        assert_eq!(rdeps_depths_limited(&deps, a, &none, 0),
+                   (rdeps_depths(&deps, a, &none), false));
+    }
+
+    #[test]
+    fn depths_give_up_once_cancelled() {
+        let mut deps = Graph::<usize, usize>::new();
+        let a = deps.add_node(10);
+        let b = deps.add_node(11);
+        deps.extend_with_edges(&[(a, b)]);
+
+        let none = HashSet::new();
+        let cancel = Cancel::default();
+        assert!(rdeps_depths_cancellable(&deps, a, &none, 0, &cancel).is_ok());
+        cancel.cancel();
+        assert_eq!(rdeps_depths_cancellable(&deps, a, &none, 0, &cancel), Err(Cancelled));
+    }
+
+    #[test]
+    fn counts_match_the_depths_and_handle_cycles() {
+        let mut deps = Graph::<usize, usize>::new();
+        let a = deps.add_node(10);
+        let b = deps.add_node(11);
+        let c = deps.add_node(12);
+        let d = deps.add_node(13);
+        let e = deps.add_node(14);
+        deps.extend_with_edges(&[(a, b), (a, c), (b, d), (c, d), (d, b), (e, e)]);
+
+        let none = HashSet::new();
+        let cancel = Cancel::default();
+        let counts = rdeps_counts(&deps, &cancel).unwrap();
+        assert_eq!(counts, vec![3, 1, 2, 1, 0]);
+        for n in deps.node_indices() {
+            assert_eq!(counts[n.index()], rdeps_depths(&deps, n, &none).len());
+        }
+
+        cancel.cancel();
+        assert_eq!(rdeps_counts(&deps, &cancel), Err(Cancelled));
+    }
 }
@@ -0,0 +1,170 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// An index of the short names of a graph for searching them without scanning
+// every name: the names in sorted order, for prefix and exact lookups, and the
+// names by each three byte sequence of their lowercase form, for the names
+// that may contain a phrase. Everything it returns is sorted by name, and the
+// candidates it gives for a phrase are a superset of the names containing it,
+// so the caller still checks each one.
+
+use std::{collections::{BTreeSet,
+                        HashMap},
+          ops::Bound,
+          sync::Arc,
+          time::{Duration,
+                 Instant}};
+
+use crate::package_graph::{btree_bytes,
+                           table_bytes};
+
+type Trigram = [u8; 3];
+
+#[derive(Default)]
+pub struct SearchIndex {
+    names:      BTreeSet<Arc<str>>,
+    trigrams:   HashMap<Trigram, BTreeSet<Arc<str>>>,
+    build_time: Duration,
+}
+
+fn trigrams(folded: &str) -> BTreeSet<Trigram> {
+    folded.as_bytes()
+          .windows(3)
+          .map(|w| [w[0], w[1], w[2]])
+          .collect()
+}
+
+impl SearchIndex {
+    pub fn insert(&mut self, name: Arc<str>) {
+        let start = Instant::now();
+        for trigram in trigrams(&name.to_lowercase()) {
+            self.trigrams
+                .entry(trigram)
+                .or_default()
+                .insert(name.clone());
+        }
+        self.names.insert(name);
+        self.build_time += start.elapsed();
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        for trigram in trigrams(&name.to_lowercase()) {
+            let emptied = match self.trigrams.get_mut(&trigram) {
+                Some(names) => {
+                    names.remove(name);
+                    names.is_empty()
+                }
+                None => false,
+            };
+            if emptied {
+                self.trigrams.remove(&trigram);
+            }
+        }
+        self.names.remove(name);
+    }
+
+    // An estimate of the heap bytes of the index, without the names, which
+    // the graph holds anyway
+    pub fn heap_bytes(&self) -> usize {
+        btree_bytes::<Arc<str>>(self.names.len())
+        + table_bytes::<(Trigram, BTreeSet<Arc<str>>)>(self.trigrams.capacity())
+        + self.trigrams
+              .values()
+              .map(|names| btree_bytes::<Arc<str>>(names.len()))
+              .sum::<usize>()
+    }
+
+    // The total time spent adding names to the index
+    pub fn build_time(&self) -> Duration { self.build_time }
+
+    pub fn names(&self) -> impl Iterator<Item = &Arc<str>> { self.names.iter() }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<str>> { self.names.get(name) }
+
+    pub fn with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a Arc<str>> {
+        self.names
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |name| name.starts_with(prefix))
+    }
+
+    // The names whose lowercase form has every three byte sequence of the
+    // lowercase phrase, which includes every name that contains it in any
+    // case. None if the phrase is too short to narrow the names down.
+    pub fn candidates(&self, folded_phrase: &str) -> Option<Vec<&Arc<str>>> {
+        let wanted = trigrams(folded_phrase);
+        if wanted.is_empty() {
+            return None;
+        }
+
+        let mut postings = Vec::with_capacity(wanted.len());
+        for trigram in &wanted {
+            match self.trigrams.get(trigram) {
+                Some(names) => postings.push(names),
+                None => return Some(Vec::new()),
+            }
+        }
+        postings.sort_by_key(|names| names.len());
+
+        let (smallest, rest) = postings.split_first()?;
+        Some(smallest.iter()
+                     .filter(|name| rest.iter().all(|names| names.contains(*name)))
+                     .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(names: &[&str]) -> SearchIndex {
+        let mut index = SearchIndex::default();
+        for name in names {
+            index.insert(Arc::from(*name));
+        }
+        index
+    }
+
+    fn strings<'a, I: IntoIterator<Item = &'a Arc<str>>>(names: I) -> Vec<String> {
+        names.into_iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn names_are_sorted_and_found_by_prefix() {
+        let index = index(&["core/zlib", "core/openssl", "acme/open", "core/openssl11"]);
+        assert_eq!(strings(index.names()),
+                   vec!["acme/open", "core/openssl", "core/openssl11", "core/zlib"]);
+        assert_eq!(strings(index.with_prefix("core/open")),
+                   vec!["core/openssl", "core/openssl11"]);
+        assert!(index.with_prefix("core/q").next().is_none());
+        assert!(index.get("core/zlib").is_some());
+        assert!(index.get("core/zli").is_none());
+    }
+
+    #[test]
+    fn candidates_contain_every_trigram() {
+        let mut index = index(&["core/OpenSSL", "core/openssh", "acme/ssl-tools"]);
+        assert_eq!(strings(index.candidates("ssl").unwrap()),
+                   vec!["acme/ssl-tools", "core/OpenSSL"]);
+        assert_eq!(strings(index.candidates("openss").unwrap()),
+                   vec!["core/OpenSSL", "core/openssh"]);
+        assert!(index.candidates("nothing").unwrap().is_empty());
+        assert!(index.candidates("ss").is_none());
+
+        index.remove("core/OpenSSL");
+        assert_eq!(strings(index.candidates("ssl").unwrap()), vec!["acme/ssl-tools"]);
+        index.remove("acme/ssl-tools");
+        assert!(index.candidates("ssl").unwrap().is_empty());
+        assert!(!index.trigrams.contains_key(b"ssl"));
+    }
+}
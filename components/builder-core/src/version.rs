@@ -0,0 +1,164 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The order of the versions and releases of a package, by which the latest
+// one is chosen. A version is compared as Habitat compares it: the dotted
+// numbers at its start one by one, as numbers, so 1.10 is newer than 1.9 and
+// 1.2.1 newer than 1.2, then whatever follows them, taken as a pre-release,
+// so 2.0a and 2.0-rc1 are older than 2.0. Releases are timestamps, compared
+// as numbers when both are, so a longer one is the newer.
+
+use std::cmp::Ordering;
+
+use crate::hab_core::package::PackageIdent;
+
+// Orders two versions of a package, the newer one greater
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (a_parts, a_extension) = split_version(a);
+    let (b_parts, b_extension) = split_version(b);
+
+    let mut b_parts = b_parts.iter();
+    for a_part in &a_parts {
+        match b_parts.next() {
+            Some(b_part) => {
+                match compare_numbers(a_part, b_part) {
+                    Ordering::Equal => (),
+                    ordering => return ordering,
+                }
+            }
+            None => return Ordering::Greater,
+        }
+    }
+    if b_parts.next().is_some() {
+        return Ordering::Less;
+    }
+
+    match (a_extension, b_extension) {
+        ("", "") => Ordering::Equal,
+        ("", _) => Ordering::Greater,
+        (_, "") => Ordering::Less,
+        (a_extension, b_extension) => a_extension.cmp(b_extension),
+    }
+}
+
+// Orders two releases of a version, the newer one greater. Releases that are
+// numbers are newer than those that are not.
+pub fn compare_releases(a: &str, b: &str) -> Ordering {
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|c| c.is_ascii_digit());
+    match (is_number(a), is_number(b)) {
+        (true, true) => compare_numbers(a, b),
+        (false, false) => a.cmp(b),
+        (a, b) => a.cmp(&b),
+    }
+}
+
+// Orders two idents of a package by version, then by release when both have
+// one. An ident without a version is older than one with a version.
+pub fn compare_idents(a: &PackageIdent, b: &PackageIdent) -> Ordering {
+    let by_version = match (&a.version, &b.version) {
+        (Some(a), Some(b)) => compare_versions(a, b),
+        (a, b) => a.is_some().cmp(&b.is_some()),
+    };
+    by_version.then_with(|| {
+                  match (&a.release, &b.release) {
+                      (Some(a), Some(b)) => compare_releases(a, b),
+                      _ => Ordering::Equal,
+                  }
+              })
+}
+
+// The dotted numbers the version starts with, and what follows them, with
+// the dash before it left out
+fn split_version(version: &str) -> (Vec<&str>, &str) {
+    let end = version.find(|c: char| !c.is_ascii_digit() && c != '.')
+                     .unwrap_or_else(|| version.len());
+    let (numbers, extension) = version.split_at(end);
+    let parts = numbers.split('.').filter(|part| !part.is_empty()).collect();
+    let extension = if extension.len() > 1 && extension.starts_with('-') {
+        &extension[1..]
+    } else {
+        extension
+    };
+    (parts, extension)
+}
+
+// Orders two strings of digits as the numbers they stand for, whatever their
+// size
+fn compare_numbers(a: &str, b: &str) -> Ordering {
+    let a = a.trim_start_matches('0');
+    let b = b.trim_start_matches('0');
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn versions_are_compared_by_number() {
+        let cases = [("1.10", "1.9", Ordering::Greater),
+                     ("1.10.0", "1.9.2", Ordering::Greater),
+                     ("1.9", "1.9", Ordering::Equal),
+                     ("1.9.0", "1.9", Ordering::Greater),
+                     ("10", "9", Ordering::Greater),
+                     ("2", "2", Ordering::Equal),
+                     ("01.2", "1.2", Ordering::Equal),
+                     ("2.0", "2.0a", Ordering::Greater),
+                     ("2.0-rc1", "2.0", Ordering::Less),
+                     ("2.0-rc2", "2.0rc1", Ordering::Greater),
+                     ("1.0.2t", "1.0.2s", Ordering::Greater),
+                     ("2.0a", "1.10", Ordering::Greater),
+                     ("master", "0.1", Ordering::Less),
+                     ("123456789012345678901", "123456789012345678900", Ordering::Greater)];
+        for &(a, b, ordering) in &cases {
+            assert_eq!(compare_versions(a, b), ordering, "{} against {}", a, b);
+            assert_eq!(compare_versions(b, a), ordering.reverse(), "{} against {}", b, a);
+        }
+    }
+
+    #[test]
+    fn releases_are_compared_by_number() {
+        let cases = [("20190115003728", "20190115003727", Ordering::Greater),
+                     ("20190115003728", "2019011500372", Ordering::Greater),
+                     ("9", "10", Ordering::Less),
+                     ("1", "1", Ordering::Equal),
+                     ("b", "a", Ordering::Greater),
+                     ("1", "a", Ordering::Greater)];
+        for &(a, b, ordering) in &cases {
+            assert_eq!(compare_releases(a, b), ordering, "{} against {}", a, b);
+            assert_eq!(compare_releases(b, a), ordering.reverse(), "{} against {}", b, a);
+        }
+    }
+
+    #[test]
+    fn idents_are_compared_by_version_then_release() {
+        let ident = |s: &str| PackageIdent::from_str(s).unwrap();
+        let cases = [("core/foo/1.10.0/1", "core/foo/1.9.2/2", Ordering::Greater),
+                     ("core/foo/2.0/1", "core/foo/2.0a/2", Ordering::Greater),
+                     ("core/foo/1/20190115003728", "core/foo/1/20180608041157", Ordering::Greater),
+                     ("core/foo/1.0/10", "core/foo/1.0/9", Ordering::Greater),
+                     ("core/foo/7/1", "core/foo/10/1", Ordering::Less),
+                     ("core/foo/1.0", "core/foo/1.0/1", Ordering::Equal),
+                     ("core/foo", "core/foo/0.1", Ordering::Less)];
+        for &(a, b, ordering) in &cases {
+            assert_eq!(compare_idents(&ident(a), &ident(b)), ordering, "{} against {}", a, b);
+            assert_eq!(compare_idents(&ident(b), &ident(a)),
+                       ordering.reverse(),
+                       "{} against {}",
+                       b,
+                       a);
+        }
+    }
+}
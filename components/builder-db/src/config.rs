@@ -37,10 +37,19 @@ pub struct DataStoreCfg {
     pub connection_test: bool,
     /// Number of database connections to start in pool.
     pub pool_size: u32,
+    /// TLS mode, using the libpq names: `disable`, `prefer`, `require`, `verify-ca` or
+    /// `verify-full`. Unset is the same as `disable`. `allow` is not supported.
     pub ssl_mode: Option<String>,
+    /// Client certificate (PEM) presented to the server, if it requires one
     pub ssl_cert: Option<String>,
+    /// Private key (PEM) of the client certificate
     pub ssl_key: Option<String>,
+    /// CA certificate (PEM) used to verify the server in the `verify-*` modes
     pub ssl_root_cert: Option<String>,
+    /// If sessions default to read-only transactions. Unset leaves the server default.
+    pub read_only: Option<bool>,
+    /// Timeout for each statement, in seconds. Unset leaves the server default.
+    pub statement_timeout_sec: Option<u64>,
 }
 
 impl Default for DataStoreCfg {
@@ -57,7 +66,9 @@ impl Default for DataStoreCfg {
                        ssl_mode:               None,
                        ssl_cert:               None,
                        ssl_key:                None,
-                       ssl_root_cert:          None, }
+                       ssl_root_cert:          None,
+                       read_only:              None,
+                       statement_timeout_sec:  None, }
     }
 }
 
@@ -105,6 +116,13 @@ impl<'a> IntoConnectParams for &'a DataStoreCfg {
         builder.port(self.port);
         builder.user(&self.user, self.password.as_ref().map(|p| &**p));
         builder.database(&self.database);
+        if let Some(read_only) = self.read_only {
+            builder.option("default_transaction_read_only",
+                           if read_only { "on" } else { "off" });
+        }
+        if let Some(timeout) = self.statement_timeout_sec {
+            builder.option("statement_timeout", &format!("{}s", timeout));
+        }
         Ok(builder.build(Host::Tcp(self.host.to_string())))
     }
 }
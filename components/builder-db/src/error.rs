@@ -41,6 +41,7 @@ pub enum Error {
     SchemaDrop(postgres::error::Error),
     SchemaSwitch(postgres::error::Error),
     SetSearchPath(postgres::error::Error),
+    Tls(String),
     TransactionCreate(postgres::error::Error),
     TransactionCommit(postgres::error::Error),
 }
@@ -83,6 +84,7 @@ impl fmt::Display for Error {
             Error::SchemaDrop(ref e) => format!("Error dropping schema: {}", e),
             Error::SchemaSwitch(ref e) => format!("Error switching schema: {}", e),
             Error::SetSearchPath(ref e) => format!("Error setting local search path: {}", e),
+            Error::Tls(ref e) => format!("Postgres TLS error: {}", e),
             Error::TransactionCreate(ref e) => format!("Error creating transaction: {}", e),
             Error::TransactionCommit(ref e) => format!("Error committing transaction: {}", e),
         };
@@ -113,6 +115,7 @@ impl error::Error for Error {
             Error::SchemaDrop(_) => "Error dropping a schema",
             Error::SchemaSwitch(_) => "Error switching schema",
             Error::SetSearchPath(_) => "Error setting local search path",
+            Error::Tls(_) => "Error setting up a TLS connection to Postgres",
             Error::TransactionCreate(_) => "Error creating a transaction",
             Error::TransactionCommit(_) => "Error committing a transaction",
         }
@@ -18,6 +18,12 @@ use std::{fmt,
           thread,
           time::Duration};
 
+use openssl::ssl::{SslConnector,
+                   SslFiletype,
+                   SslMethod,
+                   SslVerifyMode};
+use postgres::{self,
+               tls::openssl::OpenSsl};
 use r2d2;
 use r2d2_postgres::{self,
                     PostgresConnectionManager,
@@ -27,6 +33,9 @@ use crate::{config::DataStoreCfg,
             error::{Error,
                     Result}};
 
+// How postgres describes a connection that failed while negotiating TLS
+const TLS_HANDSHAKE_ERROR: &str = "error performing TLS handshake";
+
 #[derive(Clone)]
 pub struct Pool {
     inner: r2d2::Pool<PostgresConnectionManager>,
@@ -42,9 +51,14 @@ impl Pool {
     pub fn new(config: &DataStoreCfg) -> Self {
         debug!("Creating new Pool, config: {:?}", config);
         loop {
+            // The services sharing this pool ignored ssl_mode before it was supported, so
+            // settings that cannot be used fall back to a plain connection rather than failing
+            let tls = tls_mode(config).unwrap_or_else(|e| {
+                                          error!("{}, connecting to Postgres without TLS", e);
+                                          TlsMode::None
+                                      });
             let manager =
-                PostgresConnectionManager::new(config, TlsMode::None).expect("Failed to connect \
-                                                                              to Postgres");
+                PostgresConnectionManager::new(config, tls).expect("Failed to connect to Postgres");
             match r2d2::Pool::builder()
                 .max_size(config.pool_size)
                 .connection_timeout(Duration::from_secs(config.connection_timeout_sec))
@@ -60,12 +74,96 @@ impl Pool {
         }
     }
 
+    /// Makes a single attempt at creating the pool, for callers that handle retries themselves.
+    pub fn try_new(config: &DataStoreCfg) -> Result<Self> {
+        debug!("Creating new Pool, config: {:?}", config);
+        let manager = PostgresConnectionManager::new(config, tls_mode(config)?)
+            .map_err(Error::PostgresConnect)?;
+        let pool = r2d2::Pool::builder()
+            .max_size(config.pool_size)
+            .connection_timeout(Duration::from_secs(config.connection_timeout_sec))
+            .build(manager)
+            .map_err(|e| tls_failure(config).unwrap_or(Error::ConnectionTimeout(e)))?;
+        Ok(Pool { inner: pool })
+    }
+
     pub fn get(&self) -> Result<r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager>> {
         let conn = self.inner.get().map_err(Error::ConnectionTimeout)?;
         Ok(conn)
     }
 }
 
+// The TLS negotiator for the configured ssl_mode, and whether TLS is required rather than only
+// preferred. None for plain connections.
+fn tls_negotiator(config: &DataStoreCfg) -> Result<Option<(OpenSsl, bool)>> {
+    let (required, verify_ca, verify_host) = match config.ssl_mode.as_ref().map(String::as_str) {
+        None | Some("disable") => return Ok(None),
+        // postgres can only try TLS first, not the plain connection first that libpq's allow does
+        Some("allow") => {
+            let msg = "ssl_mode 'allow' is not supported, use 'disable' or 'prefer'";
+            return Err(Error::Tls(msg.to_string()));
+        }
+        Some("prefer") => (false, false, false),
+        Some("require") => (true, false, false),
+        Some("verify-ca") => (true, true, false),
+        Some("verify-full") => (true, true, true),
+        Some(mode) => return Err(Error::Tls(format!("unknown ssl_mode '{}'", mode))),
+    };
+
+    let mut builder = SslConnector::builder(SslMethod::tls())
+        .map_err(|e| Error::Tls(format!("unable to set up TLS: {}", e)))?;
+    if verify_ca {
+        if let Some(ref path) = config.ssl_root_cert {
+            builder.set_ca_file(path).map_err(|e| {
+                                         Error::Tls(format!("unable to load ssl_root_cert {}: {}",
+                                                            path, e))
+                                     })?;
+        }
+    } else {
+        builder.set_verify(SslVerifyMode::NONE);
+    }
+    if let Some(ref path) = config.ssl_cert {
+        builder.set_certificate_file(path, SslFiletype::PEM)
+               .map_err(|e| Error::Tls(format!("unable to load ssl_cert {}: {}", path, e)))?;
+    }
+    if let Some(ref path) = config.ssl_key {
+        builder.set_private_key_file(path, SslFiletype::PEM)
+               .map_err(|e| Error::Tls(format!("unable to load ssl_key {}: {}", path, e)))?;
+    }
+
+    let mut negotiator = OpenSsl::from(builder.build());
+    negotiator.danger_disable_hostname_verification(!verify_host);
+    Ok(Some((negotiator, required)))
+}
+
+fn tls_mode(config: &DataStoreCfg) -> Result<TlsMode> {
+    Ok(match tls_negotiator(config)? {
+           None => TlsMode::None,
+           Some((negotiator, true)) => TlsMode::Require(Box::new(negotiator)),
+           Some((negotiator, false)) => TlsMode::Prefer(Box::new(negotiator)),
+       })
+}
+
+// The pool only reports that it timed out, so when TLS is configured a single connection is
+// made to find out whether the handshake is what failed. Returns the TLS error if it was.
+fn tls_failure(config: &DataStoreCfg) -> Option<Error> {
+    let (negotiator, required) = tls_negotiator(config).ok()??;
+    let mode = if required {
+        postgres::TlsMode::Require(&negotiator)
+    } else {
+        postgres::TlsMode::Prefer(&negotiator)
+    };
+    match postgres::Connection::connect(config, mode) {
+        Err(ref e) if e.to_string().starts_with(TLS_HANDSHAKE_ERROR) => {
+            let reason = e.to_string()[TLS_HANDSHAKE_ERROR.len()..].trim_start_matches(": ")
+                                                                   .to_string();
+            Some(Error::Tls(format!("TLS handshake with {}:{} failed, {}",
+                                    config.host, config.port, reason)))
+        }
+        _ => None,
+    }
+}
+
 impl Deref for Pool {
     type Target = r2d2::Pool<PostgresConnectionManager>;
 
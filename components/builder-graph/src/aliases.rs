@@ -0,0 +1,147 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Renamed packages, read from a file of 'old/name new/name' lines or from the
+// [aliases] section of the config file. Blank lines and lines starting with
+// '#' are skipped. Each old name maps directly to the name it was renamed to,
+// so an alias map with a chain (a -> b, b -> c) or a cycle is rejected.
+
+use std::{collections::BTreeMap,
+          fs};
+
+use crate::{error::{Error,
+                    Result},
+            export::check_short_name};
+
+#[derive(Debug, Default)]
+pub struct Aliases {
+    aliases:    BTreeMap<String, String>,
+    pub source: Option<String>,
+}
+
+impl Aliases {
+    pub fn load(filename: &str) -> Result<Aliases> {
+        let mut aliases = Aliases::parse(&fs::read_to_string(filename)?)?;
+        aliases.source = Some(filename.to_string());
+        Ok(aliases)
+    }
+
+    fn parse(content: &str) -> Result<Aliases> {
+        let mut aliases = BTreeMap::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |msg: &str| Error::InvalidAliases(format!("line {}: {}", i + 1, msg));
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 2 {
+                return Err(invalid("expected 'old/name new/name'"));
+            }
+            let old = fields[0].to_lowercase();
+            if aliases.insert(old.clone(), fields[1].to_lowercase()).is_some() {
+                return Err(invalid(&format!("{} is aliased more than once", old)));
+            }
+        }
+        Aliases::from_map(aliases)
+    }
+
+    // Checks the aliases of the config file, or of a parsed file
+    pub fn from_map(aliases: BTreeMap<String, String>) -> Result<Aliases> {
+        let invalid = |msg: String| Error::InvalidAliases(msg);
+        for (old, new) in &aliases {
+            for name in &[old, new] {
+                check_short_name(name).map_err(|_| {
+                                          invalid(format!("'{}' is not in origin/name format",
+                                                          name))
+                                      })?;
+            }
+            if old == new {
+                return Err(invalid(format!("{} is an alias of itself", old)));
+            }
+            if aliases.contains_key(new) {
+                let mut chain = vec![old.as_str(), new.as_str()];
+                let mut next = new;
+                while let Some(name) = aliases.get(next) {
+                    if chain.contains(&name.as_str()) {
+                        chain.push(name);
+                        return Err(invalid(format!("alias cycle: {}", chain.join(" -> "))));
+                    }
+                    chain.push(name);
+                    next = name;
+                }
+                return Err(invalid(format!("chained aliases: {}, alias {} to {} directly",
+                                           chain.join(" -> "),
+                                           old,
+                                           next)));
+            }
+        }
+        Ok(Aliases { aliases,
+                     source: None })
+    }
+
+    pub fn len(&self) -> usize { self.aliases.len() }
+
+    pub fn is_empty(&self) -> bool { self.aliases.is_empty() }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.aliases
+            .iter()
+            .map(|(old, new)| (old.as_str(), new.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_of(content: &str) -> String {
+        match Aliases::parse(content) {
+            Err(Error::InvalidAliases(msg)) => msg,
+            other => panic!("expected invalid aliases, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn aliases_are_parsed() {
+        let aliases = Aliases::parse("# renames\ncore/openssl core/openssl11\n\n  Core/Gcc   \
+                                      core/gcc9  \n").unwrap();
+        assert_eq!(aliases.iter().collect::<Vec<_>>(),
+                   vec![("core/gcc", "core/gcc9"), ("core/openssl", "core/openssl11")]);
+
+        assert_eq!(error_of("core/openssl"), "line 1: expected 'old/name new/name'");
+        assert_eq!(error_of("core/a core/b\ncore/a core/c"),
+                   "line 2: core/a is aliased more than once");
+        assert_eq!(error_of("openssl core/openssl11"),
+                   "'openssl' is not in origin/name format");
+    }
+
+    #[test]
+    fn chained_aliases_are_rejected() {
+        assert_eq!(error_of("core/a core/b\ncore/b core/c"),
+                   "chained aliases: core/a -> core/b -> core/c, alias core/a to core/c directly");
+        assert_eq!(error_of("core/a core/b\ncore/b core/c\ncore/c core/d"),
+                   "chained aliases: core/a -> core/b -> core/c -> core/d, alias core/a to \
+                    core/d directly");
+    }
+
+    #[test]
+    fn alias_cycles_are_rejected() {
+        assert_eq!(error_of("core/a core/a"), "core/a is an alias of itself");
+        assert_eq!(error_of("core/a core/b\ncore/b core/a"),
+                   "alias cycle: core/a -> core/b -> core/a");
+        assert_eq!(error_of("core/a core/b\ncore/b core/c\ncore/c core/a"),
+                   "alias cycle: core/a -> core/b -> core/c -> core/a");
+    }
+}
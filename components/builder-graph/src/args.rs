@@ -0,0 +1,40 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The arguments the commands of the session are given, other than package
+// names and idents. A command given an argument it cannot use prints its
+// usage and leaves the session as it was, so the next command runs as usual.
+
+// Parses the max given to a command that lists packages, which has to be a
+// whole number of at least 1, since none of them has a use for listing
+// nothing. Unlike the default it has no upper bound, as max_output_rows
+// caps what they print.
+pub fn parse_max(arg: &str) -> Option<usize> { arg.parse::<usize>().ok().filter(|max| *max > 0) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_arguments_are_counts_of_at_least_one() {
+        assert_eq!(parse_max("1"), Some(1));
+        assert_eq!(parse_max("250000"), Some(250_000));
+        assert_eq!(parse_max(&usize::max_value().to_string()), Some(usize::max_value()));
+        for arg in &["0", "abc", "many", "xyz", "-1", "-0", "1.5", "1e3", "", " 5",
+                     "99999999999999999999999999"]
+        {
+            assert_eq!(parse_max(arg), None, "{:?}", arg);
+        }
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// A fixed workload of graph operations, timed to catch performance
+// regressions: resolve and search on names sampled from the graph, rdeps on
+// the packages with the most direct dependents, top and stats. The names are
+// sampled with a generator of our own with a fixed seed, so that two runs on
+// the same graph do the same work whatever the version of any crate.
+
+use std::time::{Duration,
+                Instant};
+
+use crate::bldr_core::package_graph::{EdgeFilter,
+                                      PackageGraph};
+
+pub const DEFAULT_ITERATIONS: usize = 1000;
+
+// Packages rdeps is run on, and the runs of top and stats, which are too slow
+// to run once per iteration
+const RDEPS_PACKAGES: usize = 5;
+const HEAVY_RUNS: usize = 3;
+const TOP_COUNT: usize = 100;
+
+const SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+    pub iterations: usize,
+    pub node_count: usize,
+    pub edge_count: usize,
+    pub operations: Vec<OperationTimings>,
+    // The sum of the medians of the operations, in ms. Lower is better.
+    pub score:      f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OperationTimings {
+    pub name:      String,
+    pub runs:      usize,
+    pub median_ms: f64,
+    pub p95_ms:    f64,
+}
+
+impl OperationTimings {
+    fn new(name: &str, mut samples: Vec<Duration>) -> Self {
+        samples.sort();
+        OperationTimings { name:      name.to_string(),
+                           runs:      samples.len(),
+                           median_ms: as_ms(percentile(&samples, 50)),
+                           p95_ms:    as_ms(percentile(&samples, 95)), }
+    }
+}
+
+// A xorshift64* generator, which is plenty for picking names
+struct Sampler(u64);
+
+impl Sampler {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn pick<'a>(&mut self, names: &'a [String]) -> &'a str {
+        &names[(self.next() % names.len() as u64) as usize]
+    }
+}
+
+// The names resolve and search are run on, drawn from the sorted node names
+fn sample_names(names: &[String], count: usize) -> Vec<String> {
+    if names.is_empty() {
+        return Vec::new();
+    }
+    let mut sampler = Sampler(SEED);
+    (0..count).map(|_| sampler.pick(names).to_string()).collect()
+}
+
+// A search phrase from a name: the first three characters of the part after
+// the origin
+fn search_phrase(name: &str) -> String {
+    let short = name.splitn(2, '/').nth(1).unwrap_or(name);
+    short.chars().take(3).collect()
+}
+
+// The sample at the percentile of the sorted samples, by the nearest rank
+fn percentile(sorted: &[Duration], percentile: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::default();
+    }
+    let rank = (sorted.len() * percentile + 99) / 100;
+    sorted[rank.max(1) - 1]
+}
+
+fn as_ms(duration: Duration) -> f64 { duration.as_secs_f64() * 1000.0 }
+
+fn time<F: FnMut()>(mut op: F) -> Duration {
+    let start = Instant::now();
+    op();
+    start.elapsed()
+}
+
+pub fn run(graph: &PackageGraph, iterations: usize) -> BenchReport {
+    let names = graph.node_names();
+    let sampled = sample_names(&names, iterations);
+
+    let resolve = sampled.iter()
+                         .map(|name| time(|| drop(graph.resolve(name))))
+                         .collect();
+    let search = sampled.iter()
+                        .map(|name| {
+                            let phrase = search_phrase(name);
+                            time(|| drop(graph.search(&phrase)))
+                        })
+                        .collect();
+
+    let mut most_dependents: Vec<(usize, &String)> =
+        names.iter()
+             .map(|name| (graph.dependent_count(name).unwrap_or(0), name))
+             .collect();
+    most_dependents.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+    let rdeps = most_dependents.iter()
+                               .take(RDEPS_PACKAGES)
+                               .map(|(_, name)| {
+                                   time(|| {
+                                       drop(graph.rdeps_with_depth(name,
+                                                                   &Default::default(),
+                                                                   EdgeFilter::All))
+                                   })
+                               })
+                               .collect();
+
+    let top = (0..HEAVY_RUNS).map(|_| time(|| drop(graph.top(TOP_COUNT, EdgeFilter::All))))
+                             .collect();
+    let stats = (0..HEAVY_RUNS).map(|_| time(|| drop(graph.stats())))
+                               .collect();
+
+    let operations = vec![OperationTimings::new("resolve", resolve),
+                          OperationTimings::new("search", search),
+                          OperationTimings::new("rdeps", rdeps),
+                          OperationTimings::new("top", top),
+                          OperationTimings::new("stats", stats),];
+    let (node_count, edge_count) = graph.counts();
+    BenchReport { iterations,
+                  node_count,
+                  edge_count,
+                  score: operations.iter().map(|op| op.median_ms).sum(),
+                  operations }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration { Duration::from_millis(ms) }
+
+    #[test]
+    fn sampling_is_deterministic() {
+        let names: Vec<String> = (0..50).map(|i| format!("core/pkg{}", i)).collect();
+        let sampled = sample_names(&names, 20);
+        assert_eq!(sampled.len(), 20);
+        assert_eq!(sampled, sample_names(&names, 20));
+        assert!(sampled.iter().any(|name| *name != sampled[0]));
+        assert!(sample_names(&[], 20).is_empty());
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let samples: Vec<Duration> = (1..=20).map(ms).collect();
+        assert_eq!(percentile(&samples, 50), ms(10));
+        assert_eq!(percentile(&samples, 95), ms(19));
+        assert_eq!(percentile(&[ms(4)], 95), ms(4));
+        assert_eq!(percentile(&[], 50), ms(0));
+
+        let timings = OperationTimings::new("top", vec![ms(3), ms(1), ms(2)]);
+        assert_eq!(timings.runs, 3);
+        assert!((timings.median_ms - 2.0).abs() < 1e-9);
+        assert!((timings.p95_ms - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn search_phrases_skip_the_origin() {
+        assert_eq!(search_phrase("core/openssl"), "ope");
+        assert_eq!(search_phrase("core/rq"), "rq");
+        assert_eq!(search_phrase("nameonly"), "nam");
+    }
+}
@@ -0,0 +1,256 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Binary graph cache. The file starts with a magic marker and a format
+// version, followed by the creation time and the source database, then the
+// nodes (short name, latest ident and the dependencies it lists, if known)
+// and the edges (as pairs of node indices). Integers are little-endian,
+// strings are length-prefixed UTF-8, and lists are count-prefixed.
+
+use std::{collections::HashMap,
+          fs::File,
+          io::{BufReader,
+               BufWriter,
+               Read,
+               Write},
+          str::FromStr};
+
+use chrono::Utc;
+
+use crate::{bldr_core::package_graph::{PackageDeps,
+                                       PackageGraph},
+            error::{Error,
+                    Result},
+            export::check_short_name,
+            hab_core::package::PackageIdent};
+
+const CACHE_MAGIC: &[u8; 8] = b"BLDRGRPH";
+const CACHE_FORMAT_VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub struct CacheInfo {
+    pub created_at: i64,
+    pub source:     String,
+}
+
+// Writes the graph to the cache file. Returns the number of nodes and edges.
+pub fn save_cache(graph: &PackageGraph, filename: &str, source: &str) -> Result<(usize, usize)> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    let counts = write_cache(graph, &mut writer, source)?;
+    writer.flush()?;
+    Ok(counts)
+}
+
+pub fn load_cache(filename: &str) -> Result<(PackageGraph, CacheInfo)> {
+    read_cache(&mut BufReader::new(File::open(filename)?))
+}
+
+fn write_cache<W: Write>(graph: &PackageGraph,
+                         writer: &mut W,
+                         source: &str)
+                         -> Result<(usize, usize)> {
+    writer.write_all(CACHE_MAGIC)?;
+    write_u32(writer, CACHE_FORMAT_VERSION)?;
+    writer.write_all(&Utc::now().timestamp().to_le_bytes())?;
+    write_str(writer, source)?;
+
+    let names = graph.node_names();
+    let mut indices = HashMap::new();
+    write_u32(writer, names.len() as u32)?;
+    for (i, name) in names.iter().enumerate() {
+        write_str(writer, name)?;
+        write_str(writer, &graph.resolve(name).unwrap_or_default())?;
+        match graph.deps_of(name) {
+            Some(deps) => {
+                write_u32(writer, 1)?;
+                write_list(writer, &deps.deps)?;
+                write_list(writer, &deps.build_deps)?;
+            }
+            None => write_u32(writer, 0)?,
+        }
+        indices.insert(name.as_str(), i as u32);
+    }
+
+    let edges = graph.edges();
+    write_u32(writer, edges.len() as u32)?;
+    for (from, to) in &edges {
+        write_u32(writer, indices[from.as_str()])?;
+        write_u32(writer, indices[to.as_str()])?;
+    }
+
+    Ok((names.len(), edges.len()))
+}
+
+fn read_cache<R: Read>(reader: &mut R) -> Result<(PackageGraph, CacheInfo)> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)
+          .map_err(|_| invalid("not a graph cache file"))?;
+    if magic != *CACHE_MAGIC {
+        return Err(invalid("not a graph cache file"));
+    }
+
+    let version = read_u32(reader)?;
+    if version != CACHE_FORMAT_VERSION {
+        return Err(Error::CacheFormatVersion(version, CACHE_FORMAT_VERSION));
+    }
+
+    let mut created_at = [0; 8];
+    reader.read_exact(&mut created_at)
+          .map_err(|_| invalid("truncated header"))?;
+    let info = CacheInfo { created_at: i64::from_le_bytes(created_at),
+                           source:     read_str(reader)?, };
+
+    let node_count = read_u32(reader)? as usize;
+    let mut nodes = Vec::with_capacity(node_count);
+    let mut listed = Vec::new();
+    for _ in 0..node_count {
+        let name = read_str(reader)?;
+        check_short_name(&name)?;
+        let ident = read_str(reader)?;
+        let ident = if ident.is_empty() {
+            None
+        } else {
+            Some(PackageIdent::from_str(&ident)?)
+        };
+        if read_u32(reader)? != 0 {
+            let ident = ident.as_ref()
+                             .ok_or_else(|| invalid("dependencies listed without an ident"))?;
+            listed.push(PackageDeps { ident:      ident.to_string(),
+                                      deps:       read_idents(reader)?,
+                                      build_deps: read_idents(reader)?, });
+        }
+        nodes.push((name, ident));
+    }
+
+    let edge_count = read_u32(reader)? as usize;
+    let mut edges = Vec::with_capacity(edge_count);
+    for _ in 0..edge_count {
+        let from = read_u32(reader)? as usize;
+        let to = read_u32(reader)? as usize;
+        if from >= node_count || to >= node_count {
+            return Err(invalid("edge refers to an unknown node"));
+        }
+        edges.push((nodes[from].0.clone(), nodes[to].0.clone()));
+    }
+
+    let mut graph = PackageGraph::new();
+    graph.build_from_edges(nodes.into_iter(), edges.into_iter());
+    for deps in &listed {
+        graph.set_deps(deps);
+    }
+
+    Ok((graph, info))
+}
+
+fn invalid(msg: &str) -> Error { Error::InvalidGraphFile(msg.to_string()) }
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_str<W: Write>(writer: &mut W, value: &str) -> Result<()> {
+    write_u32(writer, value.len() as u32)?;
+    writer.write_all(value.as_bytes())?;
+    Ok(())
+}
+
+fn write_list<W: Write>(writer: &mut W, values: &[String]) -> Result<()> {
+    write_u32(writer, values.len() as u32)?;
+    for value in values {
+        write_str(writer, value)?;
+    }
+    Ok(())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)
+          .map_err(|_| invalid("unexpected end of file"))?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_str<R: Read>(reader: &mut R) -> Result<String> {
+    let len = read_u32(reader)? as usize;
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf)
+          .map_err(|_| invalid("unexpected end of file"))?;
+    String::from_utf8(buf).map_err(|_| invalid("string is not valid UTF-8"))
+}
+
+fn read_idents<R: Read>(reader: &mut R) -> Result<Vec<String>> {
+    let count = read_u32(reader)? as usize;
+    let mut idents = Vec::with_capacity(count);
+    for _ in 0..count {
+        let ident = read_str(reader)?;
+        PackageIdent::from_str(&ident)?;
+        idents.push(ident);
+    }
+    Ok(idents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_graph() -> PackageGraph {
+        let ident = |s: &str| Some(PackageIdent::from_str(s).unwrap());
+        let nodes = vec![("foo/bar".to_string(), ident("foo/bar/1/2")),
+                         ("foo/baz".to_string(), ident("foo/baz/1/3")),
+                         ("bar/xyz".to_string(), None),];
+        let edges = vec![("foo/bar".to_string(), "foo/baz".to_string()),
+                         ("foo/baz".to_string(), "bar/xyz".to_string()),];
+
+        let mut graph = PackageGraph::new();
+        graph.build_from_edges(nodes.into_iter(), edges.into_iter());
+        graph.set_deps(&PackageDeps { ident:      "foo/bar/1/2".to_string(),
+                                      deps:       vec!["foo/baz/1/3".to_string()],
+                                      build_deps: vec!["bar/xyz/1/1".to_string()], });
+        graph
+    }
+
+    #[test]
+    fn cache_round_trips() {
+        let graph = make_graph();
+        let mut buf = Vec::new();
+        assert_eq!(write_cache(&graph, &mut buf, "hab@localhost:5432/builder").unwrap(),
+                   (3, 2));
+
+        let (loaded, info) = read_cache(&mut buf.as_slice()).unwrap();
+        assert_eq!(info.source, "hab@localhost:5432/builder");
+        assert_eq!(loaded.node_names(), graph.node_names());
+        assert_eq!(loaded.edges(), graph.edges());
+        assert_eq!(loaded.resolve("foo/baz"), Some("foo/baz/1/3".to_string()));
+        assert_eq!(loaded.resolve("bar/xyz"), None);
+        assert_eq!(loaded.deps_of("foo/bar"), graph.deps_of("foo/bar"));
+        assert_eq!(loaded.deps_of("foo/baz"), None);
+    }
+
+    #[test]
+    fn cache_rejects_other_versions() {
+        let mut buf = Vec::new();
+        write_cache(&make_graph(), &mut buf, "").unwrap();
+        buf[8..12].copy_from_slice(&(CACHE_FORMAT_VERSION + 1).to_le_bytes());
+
+        match read_cache(&mut buf.as_slice()) {
+            Err(Error::CacheFormatVersion(found, expected)) => {
+                assert_eq!(found, CACHE_FORMAT_VERSION + 1);
+                assert_eq!(expected, CACHE_FORMAT_VERSION);
+            }
+            _ => panic!("expected a format version error"),
+        }
+
+        assert!(read_cache(&mut &b"garbage"[..]).is_err());
+    }
+}
@@ -0,0 +1,754 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{cmp::Ordering,
+          collections::{BTreeMap,
+                        HashMap,
+                        HashSet},
+          str::FromStr};
+
+use petgraph::{algo::tarjan_scc,
+               Graph};
+
+use crate::{bldr_core::{cancel::Cancel,
+                        package_graph::{matches_filter,
+                                        PackageGraph},
+                        version::compare_idents},
+            config::LimitsCfg,
+            data_store::PackageStore,
+            error::{Error,
+                    Result},
+            hab_core::package::PackageIdent,
+            idents::{resolve_name,
+                     short_name}};
+
+// Bump when the structure of the report changes
+pub const REPORT_VERSION: u32 = 7;
+
+// The levels of dependencies check follows when max_check_depth sets no
+// lower limit. Each package is only followed once, so a check ends anyway,
+// and this only stops one going on down a chain of thousands of packages.
+pub const MAX_CHECK_DEPTH: usize = 256;
+
+#[derive(Serialize)]
+pub struct CheckReport {
+    pub report_version: u32,
+    pub package:        String,
+    pub filter:         Option<String>,
+    pub dependencies:   Vec<DependencyUpdate>,
+    pub conflicts:      Vec<Conflict>,
+    pub missing:        Vec<String>,
+    pub cycles:         Vec<Vec<String>>,
+    pub malformed:      Vec<MalformedDep>,
+    pub truncated:      Option<Truncation>,
+    pub queries:        usize,
+}
+
+// A dependency whose ident is malformed, with the package that lists it,
+// which the check skips
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MalformedDep {
+    pub ident:         String,
+    pub referenced_by: String,
+}
+
+// Where the check stopped following the dependencies because of a limit: the
+// packages whose dependencies were left unchecked at max_depth, which is
+// max_check_depth unless MAX_CHECK_DEPTH is lower, or whether it stopped after
+// visiting max_traversal_nodes packages
+#[derive(Default, Serialize)]
+pub struct Truncation {
+    pub depth_limited: Vec<String>,
+    pub max_depth:     usize,
+    pub node_limited:  bool,
+}
+
+// A direct dependency of the checked package, with the ident it would be
+// updated to, and the old name it was found under if it was renamed
+#[derive(Serialize)]
+pub struct DependencyUpdate {
+    pub ident:  String,
+    pub latest: String,
+    pub alias:  Option<String>,
+}
+
+// The different idents of the same package reached through the dependencies,
+// newest first
+#[derive(Serialize)]
+pub struct Conflict {
+    pub name:    String,
+    pub reached: Vec<ReachedIdent>,
+}
+
+// An ident along with the chain of packages, starting at the checked package,
+// whose dependencies led to it: the shortest such chain, and of those the
+// first in order
+#[derive(Serialize)]
+pub struct ReachedIdent {
+    pub ident: String,
+    pub path:  Vec<String>,
+}
+
+// Validates the latest dependencies for the package: each direct dependency
+// is updated to its latest version, and the transitive dependencies of those
+// are checked for conflicting versions, as deep and as far as the limits
+// allow. The dependencies are followed a level at a time, reading the
+// packages of each level from the datastore in one query, and the dependencies
+// of each package only once, so a dependency cycle is followed once around
+// and reported with its members. A dependency with a malformed ident is
+// skipped and reported. Returns None if the package could not be found in
+// the datastore, and an error if the name is malformed or not in the graph,
+// the datastore could not be read or the token was cancelled, which is
+// checked before visiting each package.
+pub fn check(datastore: &dyn PackageStore,
+             graph: &PackageGraph,
+             name: &str,
+             filter: &str,
+             limits: &LimitsCfg,
+             cancel: &Cancel)
+             -> Result<Option<CheckReport>> {
+    let package = match datastore.find_job_graph_package(&resolve_name(graph, name)?) {
+        Ok(package) => package,
+        Err(Error::UnknownJobGraphPackage) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let ident = package.get_ident().to_string();
+
+    let report_filter = Some(filter.to_string()).filter(|f| !f.is_empty());
+    let report = CheckReport { report_version: REPORT_VERSION,
+                               package:        ident.clone(),
+                               filter:         report_filter,
+                               dependencies:   Vec::new(),
+                               conflicts:      Vec::new(),
+                               missing:        Vec::new(),
+                               cycles:         Vec::new(),
+                               malformed:      Vec::new(),
+                               truncated:      None,
+                               queries:        1, };
+    let mut checker = Checker { datastore,
+                                graph,
+                                filter,
+                                limits,
+                                cancel,
+                                observed: BTreeMap::new(),
+                                fetched: HashMap::new(),
+                                followed: HashSet::new(),
+                                visited: 0,
+                                truncation: Truncation::default(),
+                                report };
+
+    let mut deps = Vec::new();
+    let mut renames = Vec::new();
+    for dep in package.get_deps().iter().map(|dep| dep.to_string()) {
+        if !matches_filter(&dep, filter) {
+            continue;
+        }
+        match renamed(graph, &dep) {
+            Ok(rename) => {
+                deps.push(dep);
+                renames.push(rename);
+            }
+            Err(_) => {
+                checker.report
+                       .malformed
+                       .push(MalformedDep { ident:         dep,
+                                            referenced_by: ident.clone(), })
+            }
+        }
+    }
+    let names: Vec<&str> = renames.iter().map(|(name, _)| name.as_str()).collect();
+    let latest = graph.resolve_many(&names);
+    for ((dep, (dep_name, alias)), dep_latest) in deps.into_iter().zip(renames).zip(latest) {
+        let dep_latest = dep_latest.unwrap_or_else(|| dep_name.clone());
+        observe(&mut checker.observed, dep_name, &dep_latest, &[ident.clone()]);
+        checker.report
+               .dependencies
+               .push(DependencyUpdate { ident: dep,
+                                        latest: dep_latest,
+                                        alias });
+    }
+
+    let mut level: Vec<Vec<String>> = checker.report
+                                             .dependencies
+                                             .iter()
+                                             .map(|d| vec![ident.clone(), d.latest.clone()])
+                                             .collect();
+    while !level.is_empty() {
+        level = checker.check_level(level)?;
+    }
+
+    checker.report.conflicts = checker.conflicts();
+    checker.report.cycles = checker.cycles();
+    let mut report = checker.report;
+    let truncation = checker.truncation;
+    if truncation.node_limited || !truncation.depth_limited.is_empty() {
+        report.truncated = Some(truncation);
+    }
+    Ok(Some(report))
+}
+
+// The short name of the ident, or the new name if the package was renamed,
+// along with the old name
+fn renamed(graph: &PackageGraph, ident: &str) -> Result<(String, Option<String>)> {
+    let name = short_name(ident)?;
+    match graph.alias_of(&name) {
+        Some(new_name) => Ok((new_name, Some(name))),
+        None => Ok((name, None)),
+    }
+}
+
+// The idents reached of each package, by name, with the path to each
+type Observed = BTreeMap<String, BTreeMap<String, Vec<String>>>;
+
+// The state of a check as it follows the dependencies: every ident seen so
+// far of each package, with the path it was first reached by, the
+// dependencies of each package read from the datastore (None if it has no
+// such package), the packages whose dependencies were followed, and how many
+// packages were visited. A package reached by several paths is only visited
+// by the first. Each level is followed in the order of its paths, so the
+// first path to reach a package is the shortest, and of those the first in
+// order, however the datastore lists the dependencies.
+struct Checker<'a> {
+    datastore:  &'a dyn PackageStore,
+    graph:      &'a PackageGraph,
+    filter:     &'a str,
+    limits:     &'a LimitsCfg,
+    cancel:     &'a Cancel,
+    observed:   Observed,
+    fetched:    HashMap<String, Option<Vec<String>>>,
+    followed:   HashSet<String>,
+    visited:    usize,
+    truncation: Truncation,
+    report:     CheckReport,
+}
+
+impl<'a> Checker<'a> {
+    // Records the idents of the dependencies of the last package of each
+    // path, all of the same length, returning the paths to those
+    // dependencies: the next level. A renamed package is the same package as
+    // its new name, so their versions conflict. The paths to packages whose
+    // dependencies were already followed end there. The dependencies of a
+    // package max_depth levels below the checked one are not followed, and
+    // nothing more is visited once max_traversal_nodes packages have been.
+    fn check_level(&mut self, mut paths: Vec<Vec<String>>) -> Result<Vec<Vec<String>>> {
+        let max_depth = match self.limits.max_check_depth {
+            0 => MAX_CHECK_DEPTH,
+            depth => depth.min(MAX_CHECK_DEPTH),
+        };
+        let max_nodes = self.limits.max_traversal_nodes;
+        paths.sort();
+        paths.dedup();
+        let mut visiting = Vec::new();
+        for path in paths {
+            if self.followed.contains(&path[path.len() - 1]) {
+                continue;
+            } else if path.len() > max_depth {
+                self.truncation.depth_limited.push(path[path.len() - 1].clone());
+                self.truncation.max_depth = max_depth;
+            } else if max_nodes != 0 && self.visited >= max_nodes {
+                self.truncation.node_limited = true;
+            } else {
+                self.visited += 1;
+                self.followed.insert(path[path.len() - 1].clone());
+                visiting.push(path);
+            }
+        }
+        self.fetch(&visiting)?;
+
+        let filter = self.filter;
+        let mut next = Vec::new();
+        for path in visiting {
+            self.cancel.check()?;
+            let ident = &path[path.len() - 1];
+            let deps = match self.fetched[ident] {
+                Some(ref deps) => deps,
+                None => {
+                    self.report.missing.push(ident.clone());
+                    continue;
+                }
+            };
+            for dep_ident in deps.iter().filter(|dep| matches_filter(dep, filter)) {
+                let name = match renamed(self.graph, dep_ident) {
+                    Ok((name, _)) => name,
+                    Err(_) => {
+                        let malformed = MalformedDep { ident:         dep_ident.clone(),
+                                                       referenced_by: ident.clone(), };
+                        self.report.malformed.push(malformed);
+                        continue;
+                    }
+                };
+                observe(&mut self.observed, name, dep_ident, &path);
+
+                let mut dep_path = path.clone();
+                dep_path.push(dep_ident.clone());
+                next.push(dep_path);
+            }
+        }
+        Ok(next)
+    }
+
+    // The packages reached at more than one ident, by name, each with every
+    // ident reached, newest first
+    fn conflicts(&self) -> Vec<Conflict> {
+        self.observed
+            .iter()
+            .filter(|(_, idents)| idents.len() > 1)
+            .map(|(name, idents)| {
+                let mut reached: Vec<ReachedIdent> =
+                    idents.iter()
+                          .map(|(ident, path)| {
+                              ReachedIdent { ident: ident.clone(),
+                                             path:  path.clone(), }
+                          })
+                          .collect();
+                reached.sort_by(|a, b| newest_first(&a.ident, &b.ident));
+                Conflict { name: name.clone(),
+                           reached }
+            })
+            .collect()
+    }
+
+    // Reads the packages at the ends of the paths that were not read yet, in
+    // a single query
+    fn fetch(&mut self, paths: &[Vec<String>]) -> Result<()> {
+        self.cancel.check()?;
+        let mut idents: Vec<String> = paths.iter()
+                                           .map(|path| &path[path.len() - 1])
+                                           .filter(|ident| !self.fetched.contains_key(*ident))
+                                           .cloned()
+                                           .collect();
+        idents.sort();
+        idents.dedup();
+        if idents.is_empty() {
+            return Ok(());
+        }
+
+        self.report.queries += 1;
+        for package in self.datastore.get_job_graph_packages_by_idents(&idents)? {
+            let deps = package.get_deps().iter().map(|dep| dep.to_string()).collect();
+            self.fetched.insert(package.get_ident().to_string(), Some(deps));
+        }
+        for ident in idents {
+            self.fetched.entry(ident).or_insert(None);
+        }
+        Ok(())
+    }
+
+    // The cycles among the dependencies that were followed, each with its
+    // members sorted, ordered by their first member
+    fn cycles(&self) -> Vec<Vec<String>> {
+        let mut graph = Graph::<&str, ()>::new();
+        let mut nodes = HashMap::new();
+        let filter = self.filter;
+        for (ident, deps) in &self.fetched {
+            for dep in deps.iter().flatten().filter(|dep| matches_filter(dep, filter)) {
+                let from = *nodes.entry(ident).or_insert_with(|| graph.add_node(ident));
+                let to = *nodes.entry(dep).or_insert_with(|| graph.add_node(dep));
+                graph.add_edge(from, to, ());
+            }
+        }
+
+        let mut cycles: Vec<Vec<String>> =
+            tarjan_scc(&graph).into_iter()
+                              .filter(|component| {
+                                  component.len() > 1
+                                  || graph.contains_edge(component[0], component[0])
+                              })
+                              .map(|component| {
+                                  let mut members: Vec<String> =
+                                      component.iter().map(|n| graph[*n].to_string()).collect();
+                                  members.sort();
+                                  members
+                              })
+                              .collect();
+        cycles.sort();
+        cycles
+    }
+}
+
+// Records the ident of the package as reached by the path, unless it was
+// reached before
+fn observe(observed: &mut Observed, name: String, ident: &str, path: &[String]) {
+    observed.entry(name)
+            .or_default()
+            .entry(ident.to_string())
+            .or_insert_with(|| path.to_vec());
+}
+
+// Orders the idents of a package by version and release, newest first. The
+// idents of a renamed package and of its new name are ordered as strings.
+fn newest_first(a: &str, b: &str) -> Ordering {
+    let by_version = match (PackageIdent::from_str(a), PackageIdent::from_str(b)) {
+        (Ok(a), Ok(b)) if a.origin == b.origin && a.name == b.name => compare_idents(&b, &a),
+        _ => Ordering::Equal,
+    };
+    by_version.then_with(|| a.cmp(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{data_store::{GraphPackage,
+                             GraphScope,
+                             JobRecord,
+                             OriginPackageCounts},
+                protocol::originsrv};
+    use protobuf::RepeatedField;
+    use std::{cell::RefCell,
+              str::FromStr};
+
+    // The packages of a check, recording the idents each query asked for
+    #[derive(Default)]
+    struct Store {
+        packages: Vec<originsrv::OriginPackage>,
+        queries:  RefCell<Vec<Vec<String>>>,
+    }
+
+    impl PackageStore for Store {
+        fn origins(&self) -> &[String] { &[] }
+
+        fn get_job_graph_packages_page(&self,
+                                       _: &str,
+                                       _: &GraphScope,
+                                       _: &[String],
+                                       _: i64)
+                                       -> Result<Vec<GraphPackage>> {
+            unimplemented!()
+        }
+
+        fn get_graph_package_counts(&self, _: &GraphScope) -> Result<Vec<OriginPackageCounts>> {
+            unimplemented!()
+        }
+
+        fn get_job_graph_package(&self, _: &str) -> Result<originsrv::OriginPackage> {
+            unimplemented!()
+        }
+
+        fn find_job_graph_package(&self, ident: &str) -> Result<originsrv::OriginPackage> {
+            self.packages
+                .iter()
+                .find(|package| package.get_ident().to_string() == ident)
+                .cloned()
+                .ok_or(Error::UnknownJobGraphPackage)
+        }
+
+        fn get_job_graph_packages_by_idents(&self,
+                                            idents: &[String])
+                                            -> Result<Vec<originsrv::OriginPackage>> {
+            self.queries.borrow_mut().push(idents.to_vec());
+            Ok(self.packages
+                   .iter()
+                   .filter(|package| idents.contains(&package.get_ident().to_string()))
+                   .cloned()
+                   .collect())
+        }
+
+        fn get_channel_packages(&self, _: &str) -> Result<Vec<String>> { unimplemented!() }
+
+        fn get_job_for_package(&self, _: &str) -> Result<Option<JobRecord>> { unimplemented!() }
+
+        fn get_latest_job_for_project(&self, _: &str) -> Result<Option<JobRecord>> {
+            unimplemented!()
+        }
+    }
+
+    fn make_package(ident: &str, deps: &[&str]) -> originsrv::OriginPackage {
+        let mut package = originsrv::OriginPackage::new();
+        package.set_ident(originsrv::OriginPackageIdent::from_str(ident).unwrap());
+        let mut package_deps = RepeatedField::new();
+        for dep in deps {
+            package_deps.push(originsrv::OriginPackageIdent::from_str(dep).unwrap());
+        }
+        package.set_deps(package_deps);
+        package
+    }
+
+    #[test]
+    fn each_level_is_read_in_one_query() {
+        // core/glibc/2/0 is only known as a dependency
+        let packages = vec![make_package("acme/app/1/1", &["core/lib/1/1", "core/glibc/2/0"]),
+                            make_package("core/lib/1/1", &[]),
+                            make_package("core/lib/1/2", &["core/glibc/2/0", "core/zlib/1/1"]),
+                            make_package("core/glibc/2/1", &["core/zlib/1/1"]),
+                            make_package("core/zlib/1/1", &[]),];
+        let mut graph = PackageGraph::new();
+        for package in &packages {
+            graph.extend(package, false);
+        }
+        let store = Store { packages,
+                            ..Store::default() };
+
+        let report = check(&store,
+                           &graph,
+                           "acme/app/1/1",
+                           "",
+                           &LimitsCfg::default(),
+                           &Cancel::default()).unwrap()
+                                              .unwrap();
+
+        let latest: Vec<&str> = report.dependencies.iter().map(|d| d.latest.as_str()).collect();
+        assert_eq!(latest, vec!["core/lib/1/2", "core/glibc/2/1"]);
+        assert_eq!(report.conflicts.len(), 1);
+        let reached: Vec<&str> = report.conflicts[0].reached
+                                                    .iter()
+                                                    .map(|r| r.ident.as_str())
+                                                    .collect();
+        assert_eq!(reached, vec!["core/glibc/2/1", "core/glibc/2/0"]);
+        assert_eq!(report.missing, vec!["core/glibc/2/0"]);
+
+        // core/zlib is reached twice on the second level, but read once
+        assert_eq!(report.queries, 3);
+        assert_eq!(*store.queries.borrow(),
+                   vec![vec!["core/glibc/2/1", "core/lib/1/2"],
+                        vec!["core/glibc/2/0", "core/zlib/1/1"]]);
+    }
+
+    #[test]
+    fn limits_stop_the_levels() {
+        let packages = vec![make_package("acme/app/1/1", &["core/a/1/1"]),
+                            make_package("core/a/1/1", &["core/b/1/1"]),
+                            make_package("core/b/1/1", &["core/c/1/1"]),
+                            make_package("core/c/1/1", &[]),];
+        let mut graph = PackageGraph::new();
+        for package in &packages {
+            graph.extend(package, false);
+        }
+        let store = Store { packages,
+                            ..Store::default() };
+        let limits = LimitsCfg { max_check_depth: 2,
+                                 ..LimitsCfg::default() };
+
+        let report = check(&store, &graph, "acme/app/1/1", "", &limits, &Cancel::default());
+        let truncated = report.unwrap().unwrap().truncated.unwrap();
+        assert_eq!(truncated.depth_limited, vec!["core/b/1/1"]);
+        assert_eq!(store.queries.borrow().len(), 1);
+
+        let cancel = Cancel::default();
+        cancel.cancel();
+        let report = check(&store, &graph, "acme/app/1/1", "", &limits, &cancel);
+        assert!(match report {
+                    Err(Error::Interrupted) => true,
+                    _ => false,
+                });
+    }
+
+    #[test]
+    fn cycles_are_followed_once_and_reported() {
+        let packages = vec![make_package("acme/app/1/1", &["core/a/1/1", "core/zlib/1/2"]),
+                            make_package("core/a/1/1", &["core/b/1/1"]),
+                            make_package("core/b/1/1", &["core/a/1/1", "core/zlib/1/0"]),
+                            make_package("core/zlib/1/0", &[]),
+                            make_package("core/zlib/1/2", &[]),];
+        let mut graph = PackageGraph::new();
+        for package in &packages {
+            graph.extend(package, false);
+        }
+        let store = Store { packages,
+                            ..Store::default() };
+
+        let report = check(&store,
+                           &graph,
+                           "acme/app/1/1",
+                           "",
+                           &LimitsCfg::default(),
+                           &Cancel::default()).unwrap()
+                                              .unwrap();
+
+        assert_eq!(report.cycles, vec![vec!["core/a/1/1", "core/b/1/1"]]);
+        assert_eq!(report.conflicts.len(), 1);
+        let reached: Vec<&str> = report.conflicts[0].reached
+                                                    .iter()
+                                                    .map(|r| r.ident.as_str())
+                                                    .collect();
+        assert_eq!(reached, vec!["core/zlib/1/2", "core/zlib/1/0"]);
+        assert!(report.truncated.is_none());
+        assert_eq!(*store.queries.borrow(),
+                   vec![vec!["core/a/1/1", "core/zlib/1/2"],
+                        vec!["core/b/1/1"],
+                        vec!["core/zlib/1/0"]]);
+    }
+
+    #[test]
+    fn conflicts_are_reported_once_whatever_the_order_of_the_deps() {
+        // Both sides of the diamond and core/extra depend on core/base, at two
+        // versions, listed in either order
+        let check_listed = |app_deps: &[&str]| {
+            let packages = vec![make_package("acme/app/1/1", app_deps),
+                                make_package("core/left/1/1", &["core/base/1/2"]),
+                                make_package("core/right/1/1", &["core/base/1/1"]),
+                                make_package("core/extra/1/1", &["core/base/1/1"]),
+                                make_package("core/base/1/1", &[]),
+                                make_package("core/base/1/2", &[]),];
+            let mut graph = PackageGraph::new();
+            for package in &packages {
+                graph.extend(package, false);
+            }
+            let store = Store { packages,
+                                ..Store::default() };
+            let report = check(&store,
+                               &graph,
+                               "acme/app/1/1",
+                               "",
+                               &LimitsCfg::default(),
+                               &Cancel::default()).unwrap()
+                                                  .unwrap();
+            report.conflicts
+                  .iter()
+                  .map(|conflict| {
+                      let reached: Vec<(String, Vec<String>)> =
+                          conflict.reached
+                                  .iter()
+                                  .map(|r| (r.ident.clone(), r.path.clone()))
+                                  .collect();
+                      (conflict.name.clone(), reached)
+                  })
+                  .collect::<Vec<_>>()
+        };
+
+        let forward = check_listed(&["core/left/1/1", "core/right/1/1", "core/extra/1/1"]);
+        let backward = check_listed(&["core/extra/1/1", "core/right/1/1", "core/left/1/1"]);
+        let via = |dep: &str| vec!["acme/app/1/1".to_string(), dep.to_string()];
+        assert_eq!(forward,
+                   vec![("core/base".to_string(),
+                         vec![("core/base/1/2".to_string(), via("core/left/1/1")),
+                              ("core/base/1/1".to_string(), via("core/extra/1/1"))])]);
+        assert_eq!(backward, forward);
+    }
+
+    #[test]
+    fn filters_leave_out_names_sharing_a_prefix() {
+        let packages = vec![make_package("acme/app/1/1", &["core/gcc/9/1", "core/gcc-libs/9/1"]),
+                            make_package("core/gcc/9/1", &[]),
+                            make_package("core/gcc-libs/9/1", &["core/gcc/9/0"]),];
+        let mut graph = PackageGraph::new();
+        for package in &packages {
+            graph.extend(package, false);
+        }
+        let store = Store { packages,
+                            ..Store::default() };
+
+        let report = check(&store,
+                           &graph,
+                           "acme/app/1/1",
+                           "core/gcc",
+                           &LimitsCfg::default(),
+                           &Cancel::default()).unwrap()
+                                              .unwrap();
+        let deps: Vec<&str> = report.dependencies.iter().map(|d| d.ident.as_str()).collect();
+        assert_eq!(deps, vec!["core/gcc/9/1"]);
+        assert!(report.conflicts.is_empty());
+        assert_eq!(*store.queries.borrow(), vec![vec!["core/gcc/9/1"]]);
+    }
+
+    #[test]
+    fn malformed_deps_are_skipped_and_reported() {
+        // A dependency with no name, as in 'core/'
+        let mut nameless = originsrv::OriginPackageIdent::new();
+        nameless.set_origin(String::from("core"));
+        let mut app = make_package("acme/app/1/1", &["core/a/1/1"]);
+        app.mut_deps().push(nameless.clone());
+        let mut a = make_package("core/a/1/1", &["core/zlib/1/1"]);
+        a.mut_deps().push(nameless);
+        let packages = vec![app, a, make_package("core/zlib/1/1", &[])];
+        let mut graph = PackageGraph::new();
+        for package in &packages {
+            graph.extend(package, false);
+        }
+        let store = Store { packages,
+                            ..Store::default() };
+
+        let report = check(&store,
+                           &graph,
+                           "acme/app/1/1",
+                           "",
+                           &LimitsCfg::default(),
+                           &Cancel::default()).unwrap()
+                                              .unwrap();
+        let latest: Vec<&str> = report.dependencies.iter().map(|d| d.latest.as_str()).collect();
+        assert_eq!(latest, vec!["core/a/1/1"]);
+        assert_eq!(report.malformed,
+                   vec![MalformedDep { ident:         String::from("core/"),
+                                       referenced_by: String::from("acme/app/1/1"), },
+                        MalformedDep { ident:         String::from("core/"),
+                                       referenced_by: String::from("core/a/1/1"), },]);
+        assert!(report.missing.is_empty());
+        assert_eq!(store.queries.borrow().len(), 2);
+
+        match check(&store, &graph, "acme/", "", &LimitsCfg::default(), &Cancel::default()) {
+            Err(Error::MalformedIdent(ref name)) => assert_eq!(name, "acme/"),
+            _ => panic!("expected a malformed name"),
+        }
+    }
+
+    #[test]
+    fn names_not_in_the_graph_are_not_looked_up() {
+        let packages = vec![make_package("acme/app/0/9", &[]), make_package("acme/app/1/1", &[])];
+        let mut graph = PackageGraph::new();
+        for package in &packages {
+            graph.extend(package, false);
+        }
+        let store = Store { packages,
+                            ..Store::default() };
+
+        match check(&store, &graph, "acme/ap", "", &LimitsCfg::default(), &Cancel::default()) {
+            Err(Error::NotInGraph(ref name, ref suggested)) => {
+                assert_eq!(name, "acme/ap");
+                assert_eq!(suggested, &["acme/app"]);
+            }
+            _ => panic!("expected acme/ap not to be in the graph"),
+        }
+        assert!(store.queries.borrow().is_empty());
+
+        // An older ident of a name in the graph is checked from the database
+        let report = check(&store,
+                           &graph,
+                           "acme/app/0/9",
+                           "",
+                           &LimitsCfg::default(),
+                           &Cancel::default()).unwrap()
+                                              .unwrap();
+        assert_eq!(report.package, "acme/app/0/9");
+    }
+
+    #[test]
+    fn unlimited_depth_stops_at_the_safety_net() {
+        let chain = MAX_CHECK_DEPTH + 10;
+        let packages: Vec<originsrv::OriginPackage> =
+            (0..chain).map(|i| {
+                          let dep = format!("core/p{}/1/1", i + 1);
+                          let deps: Vec<&str> = Some(dep.as_str()).filter(|_| i + 1 < chain)
+                                                                  .into_iter()
+                                                                  .collect();
+                          make_package(&format!("core/p{}/1/1", i), &deps)
+                      })
+                      .collect();
+        let mut graph = PackageGraph::new();
+        for package in &packages {
+            graph.extend(package, false);
+        }
+        let store = Store { packages,
+                            ..Store::default() };
+
+        let report = check(&store,
+                           &graph,
+                           "core/p0/1/1",
+                           "",
+                           &LimitsCfg::default(),
+                           &Cancel::default()).unwrap()
+                                              .unwrap();
+        let truncated = report.truncated.unwrap();
+        assert_eq!(truncated.depth_limited, vec![format!("core/p{}/1/1", MAX_CHECK_DEPTH)]);
+        assert_eq!(truncated.max_depth, MAX_CHECK_DEPTH);
+    }
+}
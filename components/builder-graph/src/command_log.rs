@@ -0,0 +1,138 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// An append-only record of the commands run, one tab-separated line per
+// command with the time it started, how long it took, the filter it ran with
+// ('-' for none) and the command as typed. Each line is flushed as it is
+// written, so that a crash loses nothing but the command that crashed. Only
+// the commands are recorded, never the connection settings.
+
+use std::{fs::OpenOptions,
+          io::Write,
+          time::Duration};
+
+use chrono::{DateTime,
+             Utc};
+
+pub struct CommandLog {
+    writer: Option<Box<dyn Write + Send>>,
+    path:   String,
+}
+
+impl CommandLog {
+    fn disabled() -> Self {
+        CommandLog { writer: None,
+                     path:   String::new(), }
+    }
+
+    // Opens the log file for appending, creating it if needed. A log that
+    // cannot be opened is disabled, with a warning, and without a file the
+    // log records nothing.
+    pub fn open(path: Option<&str>) -> Self {
+        let path = match path {
+            Some(path) => path,
+            None => return CommandLog::disabled(),
+        };
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => {
+                CommandLog { writer: Some(Box::new(file)),
+                             path:   path.to_string(), }
+            }
+            Err(e) => {
+                println!("Opening the command log {} failed, commands are not logged: {}",
+                         path, e);
+                CommandLog::disabled()
+            }
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool { self.writer.is_some() }
+
+    // Appends the command. The first write that fails disables the log, with
+    // a warning, so that the session goes on.
+    pub fn record(&mut self, command: &str, filter: &str, started: DateTime<Utc>, took: Duration) {
+        let writer = match self.writer {
+            Some(ref mut writer) => writer,
+            None => return,
+        };
+        let filter = if filter.is_empty() { "-" } else { filter };
+        let line = format!("{}\t{:.3}s\t{}\t{}\n",
+                           started.to_rfc3339(),
+                           took.as_secs_f64(),
+                           filter,
+                           command.trim());
+        if let Err(e) = writer.write_all(line.as_bytes()).and_then(|_| writer.flush()) {
+            println!("Writing to the command log {} failed, commands are no longer logged: {}",
+                     self.path, e);
+            self.writer = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env,
+              fs,
+              io};
+
+    use chrono::NaiveDateTime;
+
+    use super::*;
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::Other, "disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn commands_are_appended() {
+        let path = env::temp_dir().join(format!("bldr-graph-command-log-{}", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        fs::write(&path, "earlier\n").unwrap();
+
+        let started = DateTime::from_utc(NaiveDateTime::from_timestamp(1_583_403_000, 0), Utc);
+        let mut log = CommandLog::open(Some(&path));
+        log.record("rdeps core/openssl 20 ", "core", started, Duration::from_millis(1500));
+        log.record("stats", "", started, Duration::from_millis(2));
+        // A second log appends to the same file
+        CommandLog::open(Some(&path)).record("exit", "", started, Duration::from_secs(0));
+
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let started = started.to_rfc3339();
+        assert_eq!(content,
+                   format!("earlier\n{0}\t1.500s\tcore\trdeps core/openssl 20\n{0}\t0.002s\t-\t\
+                            stats\n{0}\t0.000s\t-\texit\n",
+                           started));
+    }
+
+    #[test]
+    fn a_failed_write_disables_the_log() {
+        let mut log = CommandLog { writer: Some(Box::new(FailingWriter)),
+                                   path:   "failing".to_string(), };
+        let started = DateTime::from_utc(NaiveDateTime::from_timestamp(1_583_403_000, 0), Utc);
+        log.record("stats", "", started, Duration::from_secs(1));
+        assert!(!log.is_enabled());
+        // Later commands are dropped without trying again
+        log.record("stats", "", started, Duration::from_secs(1));
+
+        assert!(!CommandLog::open(Some("/nonexistent/dir/commands.log")).is_enabled());
+        assert!(!CommandLog::open(None).is_enabled());
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Comparison of the latest packages of two graphs, typically built from the
+// databases of two environments. Only the latest idents are kept, so the
+// second graph can be dropped once they are extracted.
+
+use std::collections::BTreeMap;
+
+use crate::bldr_core::package_graph::PackageGraph;
+
+// The differences between the latest packages of an origin in graph A and
+// in graph B. Each list is sorted by name.
+#[derive(Debug, Default, PartialEq)]
+pub struct OriginDiff {
+    pub only_in_a: Vec<String>,
+    pub only_in_b: Vec<String>,
+    pub changed:   Vec<(String, String)>,
+}
+
+impl OriginDiff {
+    pub fn count(&self) -> usize {
+        self.only_in_a.len() + self.only_in_b.len() + self.changed.len()
+    }
+}
+
+// Returns the latest ident of each name in the graph, keyed by short name
+pub fn latest_by_name(graph: &PackageGraph) -> BTreeMap<String, String> {
+    graph.node_names()
+         .into_iter()
+         .filter_map(|name| graph.resolve(&name).map(|ident| (name, ident)))
+         .collect()
+}
+
+// Compares the latest idents of A and B, keyed by short name. Returns the
+// differences of each origin that has any, sorted by origin.
+pub fn compare_latest(a: &BTreeMap<String, String>,
+                      b: &BTreeMap<String, String>)
+                      -> BTreeMap<String, OriginDiff> {
+    let mut diffs: BTreeMap<String, OriginDiff> = BTreeMap::new();
+    let origin = |name: &str| name.split('/').next().unwrap_or_default().to_string();
+
+    for (name, ident) in a {
+        match b.get(name) {
+            None => diffs.entry(origin(name)).or_default().only_in_a.push(ident.clone()),
+            Some(other) if other != ident => {
+                diffs.entry(origin(name))
+                     .or_default()
+                     .changed
+                     .push((ident.clone(), other.clone()))
+            }
+            Some(_) => (),
+        }
+    }
+
+    for (name, ident) in b {
+        if !a.contains_key(name) {
+            diffs.entry(origin(name)).or_default().only_in_b.push(ident.clone());
+        }
+    }
+
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latest(idents: &[&str]) -> BTreeMap<String, String> {
+        idents.iter()
+              .map(|ident| {
+                  let parts: Vec<&str> = ident.split('/').collect();
+                  (format!("{}/{}", parts[0], parts[1]), (*ident).to_string())
+              })
+              .collect()
+    }
+
+    #[test]
+    fn compare_latest_groups_differences_by_origin() {
+        let a = latest(&["core/glibc/2.29/1", "core/zlib/1.2.11/1", "core/curl/7.65/1",
+                         "acme/app/1.0/1"]);
+        let b = latest(&["core/glibc/2.29/1", "core/zlib/1.2.11/2", "core/openssl/1.1/1",
+                         "foo/bar/1/1"]);
+
+        let diffs = compare_latest(&a, &b);
+        assert_eq!(diffs.keys().collect::<Vec<_>>(), vec!["acme", "core", "foo"]);
+
+        assert_eq!(diffs["core"],
+                   OriginDiff { only_in_a: vec!["core/curl/7.65/1".to_string()],
+                                only_in_b: vec!["core/openssl/1.1/1".to_string()],
+                                changed:   vec![("core/zlib/1.2.11/1".to_string(),
+                                                 "core/zlib/1.2.11/2".to_string())], });
+        assert_eq!(diffs["acme"].only_in_a, vec!["acme/app/1.0/1"]);
+        assert_eq!(diffs["foo"].only_in_b, vec!["foo/bar/1/1"]);
+        assert_eq!(diffs["core"].count(), 3);
+
+        assert!(compare_latest(&a, &a).is_empty());
+    }
+}
@@ -14,15 +14,71 @@
 
 //! Configuration for a Habitat Scheduler service
 
+use std::{collections::BTreeMap,
+          fs,
+          str::FromStr};
+
 use crate::{db::config::DataStoreCfg,
-            error::Error,
+            error::{Error,
+                    Result},
+            export::ExportFormat,
             hab_core::config::ConfigFile};
 
+/// Environment variables overriding the datastore settings of the config file
+pub const DB_HOST_ENVVAR: &str = "BLDR_GRAPH_DB_HOST";
+pub const DB_PORT_ENVVAR: &str = "BLDR_GRAPH_DB_PORT";
+pub const DB_NAME_ENVVAR: &str = "BLDR_GRAPH_DB_NAME";
+pub const DB_USER_ENVVAR: &str = "BLDR_GRAPH_DB_USER";
+pub const DB_PASSWORD_ENVVAR: &str = "BLDR_GRAPH_DB_PASSWORD";
+pub const DB_PASSWORD_FILE_ENVVAR: &str = "BLDR_GRAPH_DB_PASSWORD_FILE";
+
+/// Environment variable replacing the `features_enabled` list of the config file
+pub const FEATURES_ENVVAR: &str = "BLDR_GRAPH_FEATURES_ENABLED";
+
+/// Environment variable setting the log level, or the filters in `RUST_LOG` syntax, taking
+/// precedence over `RUST_LOG`
+pub const LOG_LEVEL_ENVVAR: &str = "BLDR_GRAPH_LOG_LEVEL";
+
+/// Statement timeout of the datastore sessions, unless the config file sets one
+pub const DEFAULT_STATEMENT_TIMEOUT_SEC: u64 = 120;
+
+/// Results listed by `top`, `find` and `rdeps` when no max is given, unless the config file
+/// sets another default
+pub const DEFAULT_MAX_RESULTS: usize = 10;
+
+/// Prompt shown before each command, unless the config file sets another
+pub const DEFAULT_PROMPT: &str = "command> ";
+
+/// Largest default max results accepted
+pub const MAX_RESULTS_LIMIT: usize = 100_000;
+
 #[derive(Debug, Deserialize)]
 #[serde(default)]
 pub struct Config {
-    pub datastore:        DataStoreCfg,
-    pub features_enabled: String,
+    pub datastore:           DataStoreCfg,
+    pub features_enabled:    String,
+    /// Origins whose packages are fetched from the database. All origins are fetched if empty.
+    pub origins:             Vec<String>,
+    pub retry:               RetryCfg,
+    /// Threads used to parse the packages when building the graph. 0 uses one per CPU.
+    pub build_threads:       usize,
+    /// File of rebuild costs, as 'origin/name cost' lines, loaded at startup.
+    pub costs_file:          Option<String>,
+    /// Renamed packages, mapping each old 'origin/name' to its new name.
+    pub aliases:             BTreeMap<String, String>,
+    /// Results listed by `top`, `find` and `rdeps` when the command gives no max.
+    pub default_max_results: usize,
+    /// Commands run in order once the graph is built, before the first prompt.
+    pub startup_commands:    Vec<String>,
+    /// File each command run is appended to, with when it ran, its filter and how long it took.
+    pub command_log:         Option<String>,
+    /// Prompt shown before each command, with `%d` replaced by the database name, `%f` by the
+    /// filter, `%n` by the node count and `%t` by the target.
+    pub prompt:              String,
+    /// Packages, as 'origin/name', whose status is printed at startup and by `watched`.
+    pub watched_packages:    Vec<String>,
+    pub export:              ExportCfg,
+    pub limits:              LimitsCfg,
 }
 
 impl Default for Config {
@@ -30,7 +86,193 @@ impl Default for Config {
         let mut datastore = DataStoreCfg::default();
         datastore.database = String::from("builder");
         Config { datastore,
-                 features_enabled: String::from("builddeps") }
+                 features_enabled: String::from("builddeps"),
+                 origins: Vec::new(),
+                 retry: RetryCfg::default(),
+                 build_threads: 0,
+                 costs_file: None,
+                 aliases: BTreeMap::new(),
+                 default_max_results: DEFAULT_MAX_RESULTS,
+                 startup_commands: Vec::new(),
+                 command_log: None,
+                 prompt: String::from(DEFAULT_PROMPT),
+                 watched_packages: Vec::new(),
+                 export: ExportCfg::default(),
+                 limits: LimitsCfg::default() }
+    }
+}
+
+/// Defaults of the export commands, for a file name given without a directory and an export
+/// given without a format.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ExportCfg {
+    /// Directory the files given by a bare name are written to, instead of the current one.
+    pub default_dir:    Option<String>,
+    /// Format of `export` when no `--format` is given, text if not set.
+    pub default_format: Option<String>,
+    /// What to do with a file that already exists, unless the command is given `--force`.
+    pub overwrite:      OverwritePolicy,
+    /// Whether a missing directory to export to is created, rather than failing the export.
+    pub create_dirs:    bool,
+}
+
+/// What an export does with a file that already exists: `refuse` to write it, move it aside
+/// to a `.bak` file first (`backup`), or `replace` it.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OverwritePolicy {
+    Refuse,
+    Backup,
+    Replace,
+}
+
+impl Default for OverwritePolicy {
+    fn default() -> Self { OverwritePolicy::Replace }
+}
+
+/// Limits on the work and output of a single command, so that one command on a package with
+/// a huge closure cannot run for hours or flood the terminal. 0 means no limit.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct LimitsCfg {
+    /// Levels of dependencies below the checked package that `check` follows.
+    pub max_check_depth:     usize,
+    /// Rows a listing such as `rdeps` or `dependents` prints, even when given a larger max.
+    pub max_output_rows:     usize,
+    /// Packages a traversal such as `rdeps` or `check` visits before it stops.
+    pub max_traversal_nodes: usize,
+}
+
+/// Retry policy for database operations that fail with a connection error. The delay between
+/// attempts starts at `initial_delay_ms` and doubles after each attempt, up to `max_delay_ms`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct RetryCfg {
+    pub attempts:         u32,
+    pub initial_delay_ms: u64,
+    pub max_delay_ms:     u64,
+}
+
+impl Default for RetryCfg {
+    fn default() -> Self {
+        RetryCfg { attempts:         5,
+                   initial_delay_ms: 500,
+                   max_delay_ms:     10_000, }
+    }
+}
+
+impl Config {
+    /// Reads the config file, naming it in the error if it cannot be read or parsed. A parse
+    /// error gives the line and the setting that is wrong.
+    pub fn load(path: &str) -> Result<Self> {
+        Config::from_file(path).map_err(|e| Error::ConfigFile(path.to_string(), Box::new(e)))
+    }
+
+    /// Returns the datastore settings the sessions are opened with. Unless the config file
+    /// says otherwise, sessions are read-only and statements time out after
+    /// `DEFAULT_STATEMENT_TIMEOUT_SEC`, since the graph never writes to the database.
+    pub fn session_datastore(&self) -> DataStoreCfg {
+        let mut datastore = self.datastore.clone();
+        datastore.read_only.get_or_insert(true);
+        datastore.statement_timeout_sec
+                 .get_or_insert(DEFAULT_STATEMENT_TIMEOUT_SEC);
+        datastore
+    }
+
+    /// Checks the settings that the config file can get wrong without failing to parse: the
+    /// default max results and the default export format.
+    pub fn validate(&self) -> Result<()> {
+        check_max_results(self.default_max_results)?;
+        if let Some(ref format) = self.export.default_format {
+            ExportFormat::from_str(format)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the settings other than the datastore's that are given in the environment,
+    /// looked up with `var`. Variables that are set to an empty value are ignored.
+    pub fn apply_env_overrides<F>(&mut self, var: F)
+        where F: Fn(&str) -> Option<String>
+    {
+        if let Some(features) = var(FEATURES_ENVVAR).filter(|value| !value.is_empty()) {
+            self.features_enabled = features;
+        }
+    }
+
+    /// Replaces the datastore settings that are given in the overrides. The password is read
+    /// from the password file, without its trailing newline, or else taken as given.
+    pub fn apply_datastore_overrides(&mut self, overrides: &DataStoreOverrides) -> Result<()> {
+        if let Some(ref host) = overrides.host {
+            self.datastore.host = host.clone();
+        }
+        if let Some(ref port) = overrides.port {
+            self.datastore.port = port.parse()
+                                      .map_err(|_| Error::InvalidDbPort(port.clone()))?;
+        }
+        if let Some(ref database) = overrides.database {
+            self.datastore.database = database.clone();
+        }
+        if let Some(ref user) = overrides.user {
+            self.datastore.user = user.clone();
+        }
+        if let Some(ref path) = overrides.password_file {
+            let password =
+                fs::read_to_string(path).map_err(|e| Error::DbPasswordFile(path.clone(), e))?;
+            self.datastore.password = Some(password.trim_end_matches(&['\r', '\n'][..])
+                                                   .to_string());
+        } else if let Some(ref password) = overrides.password {
+            self.datastore.password = Some(password.clone());
+        }
+        Ok(())
+    }
+}
+
+/// Checks a default max results, from the config file or set for the session, which has to be
+/// between 1 and `MAX_RESULTS_LIMIT`.
+pub fn check_max_results(max: usize) -> Result<usize> {
+    if max == 0 || max > MAX_RESULTS_LIMIT {
+        return Err(Error::InvalidMaxResults(max));
+    }
+    Ok(max)
+}
+
+/// Datastore settings given on the command line or in the environment. A setting that is
+/// not given keeps its value from the config file. The password itself is only ever taken
+/// from the environment, and the password file takes precedence over it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DataStoreOverrides {
+    pub host:          Option<String>,
+    pub port:          Option<String>,
+    pub database:      Option<String>,
+    pub user:          Option<String>,
+    pub password:      Option<String>,
+    pub password_file: Option<String>,
+}
+
+impl DataStoreOverrides {
+    /// Reads the overrides from the environment variables, looked up with `var`. Variables
+    /// that are set to an empty value are ignored.
+    pub fn from_env<F>(var: F) -> Self
+        where F: Fn(&str) -> Option<String>
+    {
+        let get = |name| var(name).filter(|value: &String| !value.is_empty());
+        DataStoreOverrides { host:          get(DB_HOST_ENVVAR),
+                             port:          get(DB_PORT_ENVVAR),
+                             database:      get(DB_NAME_ENVVAR),
+                             user:          get(DB_USER_ENVVAR),
+                             password:      get(DB_PASSWORD_ENVVAR),
+                             password_file: get(DB_PASSWORD_FILE_ENVVAR), }
+    }
+
+    /// Returns these overrides, falling back to `other` for each setting that is not given.
+    pub fn or(self, other: DataStoreOverrides) -> Self {
+        DataStoreOverrides { host:          self.host.or(other.host),
+                             port:          self.port.or(other.port),
+                             database:      self.database.or(other.database),
+                             user:          self.user.or(other.user),
+                             password:      self.password.or(other.password),
+                             password_file: self.password_file.or(other.password_file), }
     }
 }
 
@@ -40,12 +282,51 @@ impl ConfigFile for Config {
 
 #[cfg(test)]
 mod tests {
+    use std::{collections::HashMap,
+              env};
+
     use super::*;
+    use crate::error::EXIT_CONFIG;
+
+    // Sets environment variables for the duration of a test, restoring their previous values
+    // when dropped. Only one test sets the real environment, the others look the variables up
+    // in a map, so that tests running in parallel do not see each other's variables.
+    struct ScopedEnv {
+        saved: Vec<(&'static str, Option<String>)>,
+    }
+
+    impl ScopedEnv {
+        fn set(vars: &[(&'static str, &str)]) -> Self {
+            let saved = vars.iter()
+                            .map(|(name, value)| {
+                                let previous = env::var(name).ok();
+                                env::set_var(name, value);
+                                (*name, previous)
+                            })
+                            .collect();
+            ScopedEnv { saved }
+        }
+    }
+
+    impl Drop for ScopedEnv {
+        fn drop(&mut self) {
+            for (name, previous) in &self.saved {
+                match previous {
+                    Some(value) => env::set_var(name, value),
+                    None => env::remove_var(name),
+                }
+            }
+        }
+    }
 
     #[test]
     fn config_from_file() {
         let content = r#"
         features_enabled = "builddeps"
+        origins = ["core", "acme"]
+        build_threads = 2
+        costs_file = "/etc/bldr-graph/costs.txt"
+        default_max_results = 25
 
         [datastore]
         host = "1.1.1.1"
@@ -56,6 +337,18 @@ mod tests {
         connection_timeout_sec = 4800
         connection_test = true
         pool_size = 1
+
+        [retry]
+        attempts = 3
+        initial_delay_ms = 100
+
+        [aliases]
+        "core/openssl" = "core/openssl11"
+
+        [export]
+        default_dir = "/srv/exports"
+        default_format = "json"
+        overwrite = "backup"
         "#;
 
         let config = Config::from_raw(&content).unwrap();
@@ -66,6 +359,42 @@ mod tests {
         assert_eq!(config.datastore.connection_timeout_sec, 4800);
         assert_eq!(config.datastore.connection_test, true);
         assert_eq!(config.datastore.pool_size, 1);
+        assert_eq!(config.origins, vec!["core", "acme"]);
+        assert_eq!(config.build_threads, 2);
+        assert_eq!(config.costs_file.as_deref(), Some("/etc/bldr-graph/costs.txt"));
+        assert_eq!(config.retry.attempts, 3);
+        assert_eq!(config.retry.initial_delay_ms, 100);
+        assert_eq!(config.retry.max_delay_ms, 10_000);
+        assert_eq!(config.aliases["core/openssl"], "core/openssl11");
+        assert_eq!(config.default_max_results, 25);
+        assert_eq!(config.export.default_dir.as_deref(), Some("/srv/exports"));
+        assert_eq!(config.export.default_format.as_deref(), Some("json"));
+        assert_eq!(config.export.overwrite, OverwritePolicy::Backup);
+        assert!(!config.export.create_dirs);
+    }
+
+    #[test]
+    fn session_config_from_file() {
+        let content = r#"
+        startup_commands = ["filter acme", "stats"]
+        command_log = "/var/log/bldr-graph/commands.log"
+        prompt = "%d:%t %f> "
+        watched_packages = ["acme/api", "acme/worker"]
+
+        [limits]
+        max_check_depth = 8
+        max_output_rows = 1000
+        "#;
+
+        let config = Config::from_raw(&content).unwrap();
+        assert_eq!(config.startup_commands, vec!["filter acme", "stats"]);
+        assert_eq!(config.command_log.as_deref(),
+                   Some("/var/log/bldr-graph/commands.log"));
+        assert_eq!(config.prompt, "%d:%t %f> ");
+        assert_eq!(config.watched_packages, vec!["acme/api", "acme/worker"]);
+        assert_eq!(config.limits.max_check_depth, 8);
+        assert_eq!(config.limits.max_output_rows, 1000);
+        assert_eq!(config.limits.max_traversal_nodes, 0);
     }
 
     #[test]
@@ -75,5 +404,236 @@ mod tests {
 
         let config = Config::from_raw(&content).unwrap();
         assert_eq!(config.datastore.database, String::from("builder"));
+        assert!(config.origins.is_empty());
+        assert_eq!(config.build_threads, 0);
+        assert_eq!(config.default_max_results, DEFAULT_MAX_RESULTS);
+        assert!(config.startup_commands.is_empty());
+        assert_eq!(config.limits.max_output_rows, 0);
+        assert_eq!(config.prompt, DEFAULT_PROMPT);
+    }
+
+    #[test]
+    fn load_names_the_config_file_and_the_setting() {
+        let dir = env::temp_dir();
+        let missing = dir.join(format!("bldr-graph-missing-{}.toml", std::process::id()));
+        let missing = missing.to_string_lossy();
+        match Config::load(&missing) {
+            Err(e @ Error::ConfigFile(..)) => {
+                assert!(e.to_string().contains(&*missing), "{}", e);
+                assert_eq!(e.exit_code(), EXIT_CONFIG);
+            }
+            other => panic!("expected a config file error, got {:?}", other),
+        }
+
+        let bad = dir.join(format!("bldr-graph-bad-config-{}.toml", std::process::id()));
+        fs::write(&bad, include_str!("../tests/fixtures/bad-config.toml")).unwrap();
+        let loaded = Config::load(&bad.to_string_lossy());
+        fs::remove_file(&bad).unwrap();
+        match loaded {
+            Err(e @ Error::ConfigFile(..)) => {
+                let msg = e.to_string();
+                assert!(msg.contains(&*bad.to_string_lossy()), "{}", msg);
+                assert!(msg.contains("build_threads") && msg.contains("line 4"), "{}", msg);
+                assert_eq!(e.exit_code(), EXIT_CONFIG);
+            }
+            other => panic!("expected a config file error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn max_results_are_checked() {
+        assert_eq!(check_max_results(1).unwrap(), 1);
+        assert_eq!(check_max_results(MAX_RESULTS_LIMIT).unwrap(), MAX_RESULTS_LIMIT);
+        for max in &[0, MAX_RESULTS_LIMIT + 1] {
+            match check_max_results(*max) {
+                Err(Error::InvalidMaxResults(m)) => assert_eq!(m, *max),
+                other => panic!("expected invalid max results, got {:?}", other),
+            }
+        }
+
+        let mut config = Config::default();
+        config.validate().unwrap();
+        config.default_max_results = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn export_format_is_checked() {
+        let mut config = Config::default();
+        config.export.default_format = Some("GraphML".to_string());
+        config.validate().unwrap();
+        config.export.default_format = Some("pdf".to_string());
+        match config.validate() {
+            Err(Error::UnknownExportFormat(format)) => assert_eq!(format, "pdf"),
+            other => panic!("expected an unknown export format, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn session_datastore_defaults_to_read_only_with_timeout() {
+        let mut config = Config::default();
+        let datastore = config.session_datastore();
+        assert_eq!(datastore.read_only, Some(true));
+        assert_eq!(datastore.statement_timeout_sec,
+                   Some(DEFAULT_STATEMENT_TIMEOUT_SEC));
+
+        config.datastore.read_only = Some(false);
+        config.datastore.statement_timeout_sec = Some(5);
+        let datastore = config.session_datastore();
+        assert_eq!(datastore.read_only, Some(false));
+        assert_eq!(datastore.statement_timeout_sec, Some(5));
+    }
+
+    #[test]
+    fn datastore_overrides_from_env() {
+        let mut vars = HashMap::new();
+        vars.insert(DB_HOST_ENVVAR, "db.example.com");
+        vars.insert(DB_PORT_ENVVAR, "6432");
+        vars.insert(DB_USER_ENVVAR, "");
+
+        let overrides =
+            DataStoreOverrides::from_env(|name| vars.get(name).map(|v| (*v).to_string()));
+        assert_eq!(overrides.host, Some("db.example.com".to_string()));
+        assert_eq!(overrides.port, Some("6432".to_string()));
+        assert_eq!(overrides.database, None);
+        assert_eq!(overrides.user, None);
+        assert_eq!(overrides.password_file, None);
+    }
+
+    #[test]
+    fn password_is_taken_from_env_unless_a_file_is_given() {
+        let mut vars = HashMap::new();
+        vars.insert(DB_PASSWORD_ENVVAR, "env-secret");
+        let overrides =
+            DataStoreOverrides::from_env(|name| vars.get(name).map(|v| (*v).to_string()));
+        assert_eq!(overrides.password, Some("env-secret".to_string()));
+
+        let mut config = Config::default();
+        config.apply_datastore_overrides(&overrides).unwrap();
+        assert_eq!(config.datastore.password, Some("env-secret".to_string()));
+
+        let password_file = env::temp_dir().join(format!("bldr-graph-env-password-{}",
+                                                         std::process::id()));
+        fs::write(&password_file, "file-secret\n").unwrap();
+        let overrides =
+            DataStoreOverrides { password_file: Some(password_file.to_string_lossy()
+                                                                  .to_string()),
+                                 ..overrides };
+        config.apply_datastore_overrides(&overrides).unwrap();
+        fs::remove_file(&password_file).unwrap();
+        assert_eq!(config.datastore.password, Some("file-secret".to_string()));
+    }
+
+    #[test]
+    fn features_are_replaced_by_env() {
+        let mut config = Config::default();
+        config.apply_env_overrides(|_| Some(String::new()));
+        assert_eq!(config.features_enabled, "builddeps");
+
+        let mut vars = HashMap::new();
+        vars.insert(FEATURES_ENVVAR, "");
+        config.apply_env_overrides(|name| vars.get(name).map(|v| (*v).to_string()));
+        assert_eq!(config.features_enabled, "builddeps");
+
+        vars.insert(FEATURES_ENVVAR, "list,builddeps");
+        config.apply_env_overrides(|name| vars.get(name).map(|v| (*v).to_string()));
+        assert_eq!(config.features_enabled, "list,builddeps");
+    }
+
+    // The layers, from lowest to highest precedence: the defaults, the config file, the
+    // environment and the flags
+    #[test]
+    fn settings_are_layered_default_file_env_flags() {
+        let content = r#"
+        features_enabled = ""
+        [datastore]
+        host = "file-host"
+        port = 9000
+        user = "file-user"
+        database = "file-db"
+        "#;
+        let _env = ScopedEnv::set(&[(DB_HOST_ENVVAR, "env-host"),
+                                    (DB_PORT_ENVVAR, "6432"),
+                                    (DB_USER_ENVVAR, "env-user"),
+                                    (DB_NAME_ENVVAR, ""),
+                                    (FEATURES_ENVVAR, "builddeps")]);
+
+        let mut config = Config::from_raw(&content).unwrap();
+        config.apply_env_overrides(|name| env::var(name).ok());
+        let flags = DataStoreOverrides { host: Some("flag-host".to_string()),
+                                         ..Default::default() };
+        let overrides = flags.or(DataStoreOverrides::from_env(|name| env::var(name).ok()));
+        config.apply_datastore_overrides(&overrides).unwrap();
+
+        assert_eq!(config.datastore.host, "flag-host");
+        assert_eq!(config.datastore.port, 6432);
+        assert_eq!(config.datastore.user, "env-user");
+        assert_eq!(config.datastore.database, "file-db");
+        assert_eq!(config.features_enabled, "builddeps");
+        assert_eq!(config.build_threads, 0);
+    }
+
+    #[test]
+    fn datastore_overrides_flags_take_precedence_over_env() {
+        let flags = DataStoreOverrides { host: Some("flag-host".to_string()),
+                                         user: Some("flag-user".to_string()),
+                                         ..Default::default() };
+        let env = DataStoreOverrides { host: Some("env-host".to_string()),
+                                       database: Some("env-db".to_string()),
+                                       ..Default::default() };
+
+        let merged = flags.or(env);
+        assert_eq!(merged.host, Some("flag-host".to_string()));
+        assert_eq!(merged.user, Some("flag-user".to_string()));
+        assert_eq!(merged.database, Some("env-db".to_string()));
+        assert_eq!(merged.port, None);
+    }
+
+    #[test]
+    fn datastore_overrides_replace_config_file_settings() {
+        let content = r#"
+        [datastore]
+        host = "1.1.1.1"
+        port = 9000
+        user = "test"
+        "#;
+        let password_file = env::temp_dir().join(format!("bldr-graph-password-{}",
+                                                         std::process::id()));
+        fs::write(&password_file, "secret\n").unwrap();
+
+        let mut config = Config::from_raw(&content).unwrap();
+        let overrides =
+            DataStoreOverrides { port: Some("6432".to_string()),
+                                 database: Some("other".to_string()),
+                                 password_file: Some(password_file.to_string_lossy()
+                                                                  .to_string()),
+                                 ..Default::default() };
+        config.apply_datastore_overrides(&overrides).unwrap();
+        fs::remove_file(&password_file).unwrap();
+
+        assert_eq!(config.datastore.host, "1.1.1.1");
+        assert_eq!(config.datastore.port, 6432);
+        assert_eq!(config.datastore.user, "test");
+        assert_eq!(config.datastore.database, "other");
+        assert_eq!(config.datastore.password, Some("secret".to_string()));
+
+        // Nothing given leaves the defaults in place
+        let mut config = Config::default();
+        config.apply_datastore_overrides(&DataStoreOverrides::default())
+              .unwrap();
+        assert_eq!(config.datastore.host, "localhost");
+        assert_eq!(config.datastore.database, "builder");
+        assert_eq!(config.datastore.password, None);
+    }
+
+    #[test]
+    fn datastore_overrides_reject_invalid_port() {
+        let mut config = Config::default();
+        let overrides = DataStoreOverrides { port: Some("postgres".to_string()),
+                                             ..Default::default() };
+        match config.apply_datastore_overrides(&overrides) {
+            Err(Error::InvalidDbPort(ref port)) => assert_eq!(port, "postgres"),
+            other => panic!("unexpected result: {:?}", other),
+        }
     }
 }
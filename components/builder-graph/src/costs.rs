@@ -0,0 +1,135 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Rebuild costs of the packages, read from a file of 'origin/name cost' lines.
+// Blank lines and lines starting with '#' are skipped. The costs are weights
+// in whatever unit the file uses, and a package without one costs
+// DEFAULT_COST.
+
+use std::{cmp::Reverse,
+          collections::{BinaryHeap,
+                        HashMap},
+          fs};
+
+use crate::{error::{Error,
+                    Result},
+            export::check_short_name};
+
+pub const DEFAULT_COST: u64 = 1;
+
+#[derive(Debug, Default)]
+pub struct Costs {
+    costs:      HashMap<String, u64>,
+    pub source: Option<String>,
+}
+
+// The total cost of a set of packages, and how many of them had a cost of
+// their own rather than the default
+#[derive(Debug, PartialEq)]
+pub struct CostSummary {
+    pub total:    u64,
+    pub explicit: usize,
+}
+
+impl Costs {
+    pub fn load(filename: &str) -> Result<Costs> {
+        let mut costs = Costs::parse(&fs::read_to_string(filename)?)?;
+        costs.source = Some(filename.to_string());
+        Ok(costs)
+    }
+
+    fn parse(content: &str) -> Result<Costs> {
+        let mut costs = HashMap::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |msg: &str| Error::InvalidCostsFile(format!("line {}: {}", i + 1, msg));
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 2 {
+                return Err(invalid("expected 'origin/name cost'"));
+            }
+            check_short_name(fields[0]).map_err(|_| invalid("expected an origin/name"))?;
+            let cost = fields[1].parse::<u64>()
+                                .map_err(|_| invalid("the cost is not a whole number"))?;
+            costs.insert(fields[0].to_lowercase(), cost);
+        }
+        Ok(Costs { costs,
+                   source: None })
+    }
+
+    pub fn len(&self) -> usize { self.costs.len() }
+
+    pub fn is_empty(&self) -> bool { self.costs.is_empty() }
+
+    pub fn cost(&self, name: &str) -> u64 { self.costs.get(name).cloned().unwrap_or(DEFAULT_COST) }
+
+    pub fn summary<T: AsRef<str>>(&self, names: &[T]) -> CostSummary {
+        CostSummary { total:    names.iter().map(|name| self.cost(name.as_ref())).sum(),
+                      explicit: names.iter()
+                                     .filter(|name| self.costs.contains_key(name.as_ref()))
+                                     .count(), }
+    }
+}
+
+// Estimates the time to build jobs of the given costs on the number of
+// workers, giving the most costly job left to the least busy worker. This is
+// within a third of the best possible schedule, and exact when there are no
+// more jobs than workers.
+pub fn schedule(costs: &[u64], workers: usize) -> u64 {
+    let mut jobs = costs.to_vec();
+    jobs.sort_unstable_by(|a, b| b.cmp(a));
+    let mut busy: BinaryHeap<Reverse<u64>> = (0..workers.max(1)).map(|_| Reverse(0)).collect();
+    for job in jobs {
+        let Reverse(least) = busy.pop().unwrap();
+        busy.push(Reverse(least + job));
+    }
+    busy.into_iter().map(|Reverse(time)| time).max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn costs_are_parsed_with_a_default() {
+        let costs = Costs::parse("# minutes\ncore/chromium 240\n\n  Core/Bash   3  \n").unwrap();
+        assert_eq!(costs.len(), 2);
+        assert_eq!(costs.cost("core/chromium"), 240);
+        assert_eq!(costs.cost("core/bash"), 3);
+        assert_eq!(costs.cost("core/zlib"), DEFAULT_COST);
+        assert_eq!(costs.summary(&["core/chromium", "core/zlib", "core/bash"]),
+                   CostSummary { total:    244,
+                                 explicit: 2, });
+
+        for content in &["core/chromium", "core/chromium 1 2", "chromium 2", "core/x -1"] {
+            match Costs::parse(content) {
+                Err(Error::InvalidCostsFile(msg)) => assert!(msg.starts_with("line 1: ")),
+                other => panic!("expected an invalid costs file, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn schedule_spreads_the_jobs_over_the_workers() {
+        assert_eq!(schedule(&[], 4), 0);
+        assert_eq!(schedule(&[5, 3, 2], 1), 10);
+        assert_eq!(schedule(&[5, 3, 2], 2), 5);
+        assert_eq!(schedule(&[5, 3, 2], 8), 5);
+        assert_eq!(schedule(&[3, 3, 2, 2, 2], 2), 7);
+        // No workers is taken as one
+        assert_eq!(schedule(&[1, 1], 0), 2);
+    }
+}
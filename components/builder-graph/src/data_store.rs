@@ -12,23 +12,258 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{str::FromStr,
-          sync::Arc};
+use std::{cmp,
+          collections::{BTreeMap,
+                        VecDeque},
+          fmt,
+          result,
+          str::FromStr,
+          sync::{Arc,
+                 Mutex},
+          thread,
+          time::{Duration,
+                 Instant}};
 
 use postgres;
-use protobuf::{self,
-               RepeatedField};
+use protobuf;
 
-use crate::{config::Config,
+use crate::{config::{Config,
+                     RetryCfg},
             db::pool::Pool,
             error::{Error,
                     Result},
             protocol::originsrv};
 
-// DataStore inherits Send + Sync by virtue of having only one member, the pool itself.
+// Columns matching the rows returned by the get_graph_package functions
+const GRAPH_PACKAGE_COLUMNS: &str = "id, origin, owner_id, ident, checksum, manifest, config, \
+                                     target, array_to_string(exposes, ':') AS exposes, \
+                                     array_to_string(deps, ':') AS deps, \
+                                     array_to_string(tdeps, ':') AS tdeps, \
+                                     array_to_string(build_deps, ':') AS build_deps, \
+                                     array_to_string(build_tdeps, ':') AS build_tdeps, \
+                                     visibility::text AS package_visibility";
+
+/// Number of package rows fetched per query when iterating over the graph packages
+pub const GRAPH_PACKAGES_PAGE_SIZE: i64 = 10_000;
+
+// The graph packages, with their visibility, restricted to a target ($1), the
+// origins ($2, all if empty) and an origin's ($3) channel ($4). The public
+// packages only are counted if $5 is set.
+const GRAPH_PACKAGES_IN_SCOPE: &str = "get_graph_packages_v1() gp INNER JOIN origin_packages op \
+                                       ON op.id = gp.id WHERE \
+                                       ($1::text IS NULL OR gp.target = $1) AND \
+                                       (cardinality($2::text[]) = 0 OR gp.origin = ANY($2)) AND \
+                                       ($3::text IS NULL OR gp.id IN (SELECT ocp.package_id \
+                                       FROM origin_channel_packages ocp INNER JOIN \
+                                       origin_channels oc ON oc.id = ocp.channel_id WHERE \
+                                       oc.origin = $3 AND oc.name = $4))";
+
+// Orders the packages of a name from the newest version and release, as
+// version::compare_idents does: the dotted numbers the version starts with,
+// numerically, then the plain version before any pre-release, the dash
+// before it left out, then the releases, numerically when they are numbers
+const NEWEST_FIRST: &str = "array_remove(string_to_array(substring(op.ident_array[3] \
+                            from '^[0-9.]*'), '.'), '')::numeric[] DESC, \
+                            NULLIF(regexp_replace(op.ident_array[3], '^[0-9.]*(-(?=.))?', ''), \
+                            '') COLLATE \"C\" DESC NULLS FIRST, \
+                            CASE WHEN op.ident_array[4] ~ '^[0-9]+$' \
+                            THEN op.ident_array[4]::numeric END DESC NULLS LAST, \
+                            op.ident_array[4] COLLATE \"C\" DESC";
+
+// The creation time of the package in seconds since the epoch
+const PACKAGE_CREATED_AT: &str = "EXTRACT(EPOCH FROM op.created_at)::bigint AS package_created_at";
+
+/// A graph package, along with the time it was created in seconds since the epoch.
+#[derive(Debug)]
+pub struct GraphPackage {
+    pub package:    originsrv::OriginPackage,
+    pub created_at: Option<i64>,
+}
+
+/// The number of packages, and of distinct names, of an origin in the database.
+#[derive(Debug)]
+pub struct OriginPackageCounts {
+    pub origin:   String,
+    pub packages: i64,
+    pub names:    i64,
+}
+
+/// The build job that produced a package. Times are in seconds since the epoch.
+#[derive(Clone, Debug, Deserialize)]
+pub struct JobRecord {
+    pub id:            i64,
+    pub state:         String,
+    pub package_ident: Option<String>,
+    pub target:        String,
+    pub worker:        Option<String>,
+    pub started_at:    Option<i64>,
+    pub finished_at:   Option<i64>,
+}
+
+// Columns matching the fields of JobRecord
+const JOB_COLUMNS: &str = "id, job_state, package_ident, target, worker, \
+                           EXTRACT(EPOCH FROM build_started_at)::bigint AS started_at, \
+                           EXTRACT(EPOCH FROM build_finished_at)::bigint AS finished_at";
+
+/// Restricts the graph packages fetched to a target, the members of an origin's channel and/or
+/// the public packages. With `latest_only`, only the most recent package of each name and target
+/// is fetched.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GraphScope {
+    pub target:      Option<String>,
+    pub channel:     Option<(String, String)>,
+    pub visibility:  Visibility,
+    pub latest_only: bool,
+}
+
+impl fmt::Display for GraphScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.target.as_ref().map(String::as_str).unwrap_or("all targets"))?;
+        if let Some((ref origin, ref channel)) = self.channel {
+            write!(f, ", channel {}/{}", origin, channel)?;
+        }
+        if self.visibility == Visibility::Public {
+            write!(f, ", public packages only")?;
+        }
+        if self.latest_only {
+            write!(f, ", latest packages only")?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether all packages are graphed, or only the public ones (leaving out the private and
+/// hidden packages).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    All,
+    Public,
+}
+
+impl Default for Visibility {
+    fn default() -> Self { Visibility::All }
+}
+
+impl Visibility {
+    /// Whether the package is graphed with this visibility.
+    pub fn includes(self, package: &originsrv::OriginPackage) -> bool {
+        self == Visibility::All
+        || package.get_visibility() == originsrv::OriginPackageVisibility::Public
+    }
+}
+
+impl FromStr for Visibility {
+    type Err = String;
+
+    fn from_str(value: &str) -> result::Result<Self, Self::Err> {
+        match value {
+            "all" => Ok(Visibility::All),
+            "public" => Ok(Visibility::Public),
+            _ => Err(format!("Invalid visibility {}, expected public or all", value)),
+        }
+    }
+}
+
+impl fmt::Display for Visibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Visibility::All => write!(f, "all"),
+            Visibility::Public => write!(f, "public"),
+        }
+    }
+}
+
+/// The number of queries of a kind that were run, with the rows they returned and the time they
+/// took, including retries.
+#[derive(Clone, Debug, Default)]
+pub struct QueryStats {
+    pub queries:  u64,
+    pub failures: u64,
+    pub rows:     u64,
+    pub elapsed:  Duration,
+    pub slowest:  Duration,
+}
+
+// The number of rows a query returned, for the query metrics
+trait RowCount {
+    fn row_count(&self) -> usize;
+}
+
+impl<T> RowCount for Vec<T> {
+    fn row_count(&self) -> usize { self.len() }
+}
+
+impl<T> RowCount for Option<T> {
+    fn row_count(&self) -> usize { if self.is_some() { 1 } else { 0 } }
+}
+
+impl RowCount for originsrv::OriginPackage {
+    fn row_count(&self) -> usize { 1 }
+}
+
+/// The package and job records the graph is built from. `DataStore` reads them from the builder
+/// database, and `fixture::FixtureStore` from a file, so the commands can be exercised without a
+/// database.
+pub trait PackageStore {
+    /// The origins whose graph packages are fetched, or an empty slice for all origins.
+    fn origins(&self) -> &[String];
+
+    /// Returns up to `GRAPH_PACKAGES_PAGE_SIZE` graph packages of the origins (all if empty) in
+    /// the scope, with an id greater than `after_id`, in id order. The packages left out by the
+    /// visibility of the scope are included, except when only the latest packages are fetched,
+    /// which are then chosen among the visible ones. `what` names the query in the metrics.
+    fn get_job_graph_packages_page(&self,
+                                   what: &str,
+                                   scope: &GraphScope,
+                                   origins: &[String],
+                                   after_id: i64)
+                                   -> Result<Vec<GraphPackage>>;
+
+    /// Returns the number of graph packages and of distinct names of each origin in the scope,
+    /// sorted by origin.
+    fn get_graph_package_counts(&self, scope: &GraphScope) -> Result<Vec<OriginPackageCounts>>;
+
+    /// Returns the package with the fully-qualified ident.
+    fn get_job_graph_package(&self, ident: &str) -> Result<originsrv::OriginPackage>;
+
+    /// Looks up a package by a possibly partial ident ('origin/name',
+    /// 'origin/name/version' or a full ident), ignoring case. An exact match of
+    /// a full ident is tried first; otherwise the most recent matching package
+    /// is returned. The ident of the returned package is the one that was chosen.
+    fn find_job_graph_package(&self, ident: &str) -> Result<originsrv::OriginPackage>;
+
+    /// Returns the packages with the fully-qualified idents, skipping those that are not found.
+    fn get_job_graph_packages_by_idents(&self,
+                                        idents: &[String])
+                                        -> Result<Vec<originsrv::OriginPackage>>;
+
+    /// Returns the idents of the packages in the channels with the name, in any origin.
+    fn get_channel_packages(&self, channel: &str) -> Result<Vec<String>>;
+
+    /// Returns the job that built the package with the fully-qualified ident, if any.
+    fn get_job_for_package(&self, ident: &str) -> Result<Option<JobRecord>>;
+
+    /// Returns the most recent job for the project with the 'origin/name' name, if any.
+    fn get_latest_job_for_project(&self, name: &str) -> Result<Option<JobRecord>>;
+
+    /// Returns the metrics of the queries run since the store was created or the metrics were
+    /// reset, by kind of query. Stores that do not run queries have none.
+    fn query_metrics(&self) -> BTreeMap<String, QueryStats> { BTreeMap::new() }
+
+    /// Clears the query metrics, e.g. when the graph is rebuilt.
+    fn reset_metrics(&self) {}
+}
+
+// DataStore inherits Send + Sync by virtue of its members, the pool and its settings. Clones
+// share the query metrics.
 #[derive(Debug, Clone)]
 pub struct DataStore {
-    pool: Pool,
+    pool:              Pool,
+    origins:           Vec<String>,
+    retry:             RetryCfg,
+    statement_timeout: Option<u64>,
+    metrics:           Arc<Mutex<BTreeMap<String, QueryStats>>>,
 }
 
 // Sample connection_url: "postgresql://hab@127.0.0.1/builder"
@@ -37,56 +272,96 @@ impl DataStore {
     /// Create a new DataStore.
     ///
     /// * Can fail if the pool cannot be created
-    /// * Blocks creation of the datastore on the existince of the pool; might wait indefinetly.
-    pub fn new(config: &Config) -> Self {
-        let pool = Pool::new(&config.datastore);
-        DataStore { pool }
+    /// * Retries creating the pool with backoff, as configured, before giving up.
+    /// * Sessions are opened with the settings of `Config::session_datastore`, so they are
+    ///   read-only and time out by default.
+    pub fn new(config: &Config) -> Result<Self> {
+        let datastore = config.session_datastore();
+        let pool = with_retry(&config.retry, "Connecting to the database", || {
+                       Pool::try_new(&datastore).map_err(Error::Db)
+                   })?;
+        Ok(DataStore { pool,
+                       origins: config.origins.clone(),
+                       retry: config.retry.clone(),
+                       statement_timeout: datastore.statement_timeout_sec,
+                       metrics: Arc::default() })
     }
 
-    /// Create a new DataStore from a pre-existing pool; useful for testing the database.
-    pub fn from_pool(pool: Pool, _: Arc<String>) -> Result<DataStore> { Ok(DataStore { pool }) }
-
-    /// Setup the datastore.
-    ///
-    /// This includes all the schema and data migrations, along with stored procedures for data
-    /// access.
-    pub fn setup(&self) -> Result<()> { Ok(()) }
-
-    pub fn get_job_graph_packages(&self) -> Result<RepeatedField<originsrv::OriginPackage>> {
-        let mut packages = RepeatedField::new();
+    /// Connects to the database of the config and sets it up, the errors telling the two
+    /// steps apart and naming the database.
+    pub fn connect(config: &Config) -> Result<Self> {
+        let cfg = &config.datastore;
+        let address = format!("{}@{}:{}/{}", cfg.user, cfg.host, cfg.port, cfg.database);
+        let datastore =
+            DataStore::new(config).map_err(|e| Error::DbConnect(address.clone(), Box::new(e)))?;
+        datastore.setup()
+                 .map_err(|e| Error::DbSetup(address, Box::new(e)))?;
+        Ok(datastore)
+    }
 
-        let conn = self.pool.get()?;
+    /// Create a new DataStore from a pre-existing pool; useful for testing the database.
+    pub fn from_pool(pool: Pool, _: Arc<String>) -> Result<DataStore> {
+        Ok(DataStore { pool,
+                       origins: Vec::new(),
+                       retry: RetryCfg::default(),
+                       statement_timeout: None,
+                       metrics: Arc::default() })
+    }
 
-        let rows = &conn.query("SELECT * FROM get_graph_packages_v1()", &[])
-                        .map_err(Error::JobGraphPackagesGet)?;
+    // Runs a query with with_retry, recording it in the metrics under `what`, and reporting a
+    // query cancelled by the statement timeout as a QueryTimeout
+    fn with_retry<T, F>(&self, what: &str, f: F) -> Result<T>
+        where T: RowCount,
+              F: FnMut() -> Result<T>
+    {
+        let start = Instant::now();
+        let result = with_retry(&self.retry, what, f);
+        let elapsed = start.elapsed();
 
-        if rows.is_empty() {
-            warn!("No packages found");
-            return Ok(packages);
+        {
+            let mut metrics = self.metrics.lock().expect("Query metrics mutex is poisoned!");
+            let stats = metrics.entry(what.to_string()).or_default();
+            stats.queries += 1;
+            stats.elapsed += elapsed;
+            stats.slowest = cmp::max(stats.slowest, elapsed);
+            match result {
+                Ok(ref value) => stats.rows += value.row_count() as u64,
+                Err(Error::UnknownJobGraphPackage) => (),
+                Err(_) => stats.failures += 1,
+            }
         }
 
-        for row in rows {
-            let package = self.row_to_origin_package(&row)?;
-            packages.push(package);
+        match (result, self.statement_timeout) {
+            (Err(ref e), Some(secs)) if e.is_query_canceled() => Err(Error::QueryTimeout(secs)),
+            (result, _) => result,
         }
-
-        Ok(packages)
     }
 
-    pub fn get_job_graph_package(&self, ident: &str) -> Result<originsrv::OriginPackage> {
-        let conn = self.pool.get()?;
+    /// Setup the datastore.
+    ///
+    /// This includes all the schema and data migrations, along with stored procedures for data
+    /// access.
+    pub fn setup(&self) -> Result<()> { Ok(()) }
+
+    fn get_job(&self, query: &str, param: &str) -> Result<Option<JobRecord>> {
+        self.with_retry("Fetching a job", || {
+            let conn = self.pool.get()?;
 
-        let rows = &conn.query("SELECT * FROM get_graph_package_v1($1)", &[&ident])
-                        .map_err(Error::JobGraphPackagesGet)?;
+            let rows = &conn.query(query, &[&param]).map_err(Error::JobGet)?;
 
-        if rows.is_empty() {
-            error!("No package found");
-            return Err(Error::UnknownJobGraphPackage);
-        }
+            if rows.is_empty() {
+                return Ok(None);
+            }
 
-        assert!(rows.len() == 1);
-        let package = self.row_to_origin_package(&rows.get(0))?;
-        Ok(package)
+            let row = rows.get(0);
+            Ok(Some(JobRecord { id:            row.get("id"),
+                                state:         row.get("job_state"),
+                                package_ident: row.get("package_ident"),
+                                target:        row.get("target"),
+                                worker:        row.get("worker"),
+                                started_at:    row.get("started_at"),
+                                finished_at:   row.get("finished_at"), }))
+        })
     }
 
     fn row_to_origin_package(&self, row: &postgres::rows::Row) -> Result<originsrv::OriginPackage> {
@@ -116,11 +391,12 @@ impl DataStore {
         package.set_build_deps(Self::dep_to_idents(row.get("build_deps")));
         package.set_build_tdeps(Self::dep_to_idents(row.get("build_tdeps")));
 
-        // let pv: String = row.get("visibility");
-        // TED removing for now to kill the FromString in originsrv
-        // This should get converted to PackageVisibility in the future
-        // let pv2: originsrv::OriginPackageVisibility = pv.parse().unwrap();
-        // package.set_visibility(pv2);
+        let visibility: String = row.get("package_visibility");
+        package.set_visibility(match visibility.as_str() {
+                                   "private" => originsrv::OriginPackageVisibility::Private,
+                                   "hidden" => originsrv::OriginPackageVisibility::Hidden,
+                                   _ => originsrv::OriginPackageVisibility::Public,
+                               });
 
         Ok(package)
     }
@@ -136,3 +412,464 @@ impl DataStore {
         idents
     }
 }
+
+impl PackageStore for DataStore {
+    fn origins(&self) -> &[String] { &self.origins }
+
+    fn query_metrics(&self) -> BTreeMap<String, QueryStats> {
+        self.metrics
+            .lock()
+            .expect("Query metrics mutex is poisoned!")
+            .clone()
+    }
+
+    fn reset_metrics(&self) {
+        self.metrics
+            .lock()
+            .expect("Query metrics mutex is poisoned!")
+            .clear()
+    }
+
+    fn get_job_graph_packages_page(&self,
+                                   what: &str,
+                                   scope: &GraphScope,
+                                   origins: &[String],
+                                   after_id: i64)
+                                   -> Result<Vec<GraphPackage>> {
+        let (channel_origin, channel) = match scope.channel {
+            Some((ref origin, ref channel)) => (Some(origin), Some(channel)),
+            None => (None, None),
+        };
+
+        self.with_retry(what, || {
+            let conn = self.pool.get()?;
+
+            // The visibility is applied as the packages are iterated over, except
+            // for the latest packages, which must be chosen among the visible ones
+            let result = if scope.latest_only {
+                let public_only = scope.visibility == Visibility::Public;
+                conn.query(&format!("SELECT * FROM (SELECT DISTINCT ON (gp.origin, \
+                                     split_part(gp.ident, '/', 2), gp.target) gp.*, \
+                                     op.visibility::text AS package_visibility, {} FROM {} \
+                                     AND (NOT $7 OR op.visibility::text = 'public') ORDER BY \
+                                     gp.origin, split_part(gp.ident, '/', 2), gp.target, {}) \
+                                     latest WHERE id > $5 ORDER BY id LIMIT $6",
+                                    PACKAGE_CREATED_AT,
+                                    GRAPH_PACKAGES_IN_SCOPE,
+                                    NEWEST_FIRST),
+                           &[&scope.target,
+                             &origins,
+                             &channel_origin,
+                             &channel,
+                             &after_id,
+                             &GRAPH_PACKAGES_PAGE_SIZE,
+                             &public_only])
+            } else {
+                conn.query(&format!("SELECT gp.*, op.visibility::text AS package_visibility, {} \
+                                     FROM {} AND gp.id > $5 ORDER BY gp.id LIMIT $6",
+                                    PACKAGE_CREATED_AT,
+                                    GRAPH_PACKAGES_IN_SCOPE),
+                           &[&scope.target,
+                             &origins,
+                             &channel_origin,
+                             &channel,
+                             &after_id,
+                             &GRAPH_PACKAGES_PAGE_SIZE])
+            };
+            let rows = &result.map_err(Error::JobGraphPackagesGet)?;
+
+            let mut packages = Vec::new();
+            for row in rows {
+                packages.push(GraphPackage { package:    self.row_to_origin_package(&row)?,
+                                             created_at: row.get("package_created_at"), });
+            }
+
+            Ok(packages)
+        })
+    }
+
+    fn get_graph_package_counts(&self, scope: &GraphScope) -> Result<Vec<OriginPackageCounts>> {
+        let (channel_origin, channel) = match scope.channel {
+            Some((ref origin, ref channel)) => (Some(origin), Some(channel)),
+            None => (None, None),
+        };
+        let public_only = scope.visibility == Visibility::Public;
+
+        self.with_retry("Counting graph packages", || {
+            let conn = self.pool.get()?;
+
+            let rows = &conn.query(&format!("SELECT gp.origin, COUNT(*) AS packages, \
+                                             COUNT(DISTINCT split_part(gp.ident, '/', 2)) AS \
+                                             names FROM {} AND \
+                                             (NOT $5 OR op.visibility::text = 'public') \
+                                             GROUP BY gp.origin ORDER BY gp.origin",
+                                            GRAPH_PACKAGES_IN_SCOPE),
+                                   &[&scope.target,
+                                     &self.origins,
+                                     &channel_origin,
+                                     &channel,
+                                     &public_only])
+                            .map_err(Error::JobGraphPackagesGet)?;
+
+            Ok(rows.iter()
+                   .map(|row| {
+                       OriginPackageCounts { origin:   row.get("origin"),
+                                             packages: row.get("packages"),
+                                             names:    row.get("names"), }
+                   })
+                   .collect())
+        })
+    }
+
+    fn get_job_graph_package(&self, ident: &str) -> Result<originsrv::OriginPackage> {
+        self.with_retry("Fetching a graph package", || {
+            let conn = self.pool.get()?;
+
+            let rows = &conn.query("SELECT gp.*, op.visibility::text AS package_visibility FROM \
+                                    get_graph_package_v1($1) gp INNER JOIN origin_packages op \
+                                    ON op.id = gp.id",
+                                   &[&ident])
+                            .map_err(Error::JobGraphPackagesGet)?;
+
+            if rows.is_empty() {
+                debug!("No package found for {}", ident);
+                return Err(Error::UnknownJobGraphPackage);
+            }
+
+            assert!(rows.len() == 1);
+            let package = self.row_to_origin_package(&rows.get(0))?;
+            Ok(package)
+        })
+    }
+
+    fn find_job_graph_package(&self, ident: &str) -> Result<originsrv::OriginPackage> {
+        let parts = match ident_parts(ident) {
+            Some(parts) => parts,
+            None => return Err(Error::UnknownJobGraphPackage),
+        };
+
+        if parts.len() == 4 {
+            match self.get_job_graph_package(ident) {
+                Err(Error::UnknownJobGraphPackage) => (),
+                result => return result,
+            }
+        }
+
+        let version = parts.get(2).cloned().unwrap_or("");
+        let release = parts.get(3).cloned().unwrap_or("");
+
+        self.with_retry("Looking up a graph package", || {
+            let conn = self.pool.get()?;
+
+            let rows = &conn.query(&format!("SELECT {} FROM origin_packages_with_version_array \
+                                             op WHERE lower(ident_array[1]) = lower($1) \
+                                             AND lower(ident_array[2]) = lower($2) \
+                                             AND ($3 = '' OR lower(ident_array[3]) = lower($3)) \
+                                             AND ($4 = '' OR ident_array[4] = $4) \
+                                             ORDER BY {} LIMIT 1",
+                                            GRAPH_PACKAGE_COLUMNS,
+                                            NEWEST_FIRST),
+                                   &[&parts[0], &parts[1], &version, &release])
+                            .map_err(Error::JobGraphPackagesGet)?;
+
+            if rows.is_empty() {
+                debug!("No package found matching {}", ident);
+                return Err(Error::UnknownJobGraphPackage);
+            }
+
+            self.row_to_origin_package(&rows.get(0))
+        })
+    }
+
+    fn get_job_graph_packages_by_idents(&self,
+                                            idents: &[String])
+                                            -> Result<Vec<originsrv::OriginPackage>> {
+        self.with_retry("Fetching graph packages by ident", || {
+            let conn = self.pool.get()?;
+
+            let rows = &conn.query(&format!("SELECT {} FROM origin_packages WHERE ident = ANY($1)",
+                                            GRAPH_PACKAGE_COLUMNS),
+                                   &[&idents])
+                            .map_err(Error::JobGraphPackagesGet)?;
+
+            let mut packages = Vec::new();
+            for row in rows {
+                packages.push(self.row_to_origin_package(&row)?);
+            }
+
+            Ok(packages)
+        })
+    }
+
+    fn get_channel_packages(&self, channel: &str) -> Result<Vec<String>> {
+        self.with_retry("Fetching channel packages", || {
+            let conn = self.pool.get()?;
+
+            let rows = &conn.query("SELECT op.ident FROM origin_packages op INNER JOIN \
+                                    origin_channel_packages ocp ON ocp.package_id = op.id \
+                                    INNER JOIN origin_channels oc ON oc.id = ocp.channel_id \
+                                    WHERE oc.name = $1",
+                                   &[&channel])
+                            .map_err(Error::ChannelPackagesGet)?;
+
+            let mut idents = Vec::new();
+            for row in rows {
+                idents.push(row.get("ident"));
+            }
+
+            Ok(idents)
+        })
+    }
+
+    fn get_job_for_package(&self, ident: &str) -> Result<Option<JobRecord>> {
+        self.get_job(&format!("SELECT {} FROM jobs WHERE package_ident = $1 ORDER BY id DESC \
+                               LIMIT 1",
+                              JOB_COLUMNS),
+                     ident)
+    }
+
+    fn get_latest_job_for_project(&self, name: &str) -> Result<Option<JobRecord>> {
+        self.get_job(&format!("SELECT {} FROM jobs WHERE project_name = $1 ORDER BY id DESC \
+                               LIMIT 1",
+                              JOB_COLUMNS),
+                     name)
+    }
+}
+
+impl<'a> dyn PackageStore + 'a {
+    /// Returns an iterator over the graph packages of the configured origins, restricted to the
+    /// scope, which fetches them from the store a page at a time.
+    pub fn get_job_graph_packages(&self, scope: &GraphScope) -> GraphPackages {
+        self.get_job_graph_packages_after(scope, 0)
+    }
+
+    /// Like get_job_graph_packages, but only returns the packages with an id greater than the
+    /// watermark, i.e. those created since a previous fetch that read up to it.
+    pub fn get_job_graph_packages_after(&self,
+                                        scope: &GraphScope,
+                                        watermark: i64)
+                                        -> GraphPackages {
+        let what = if watermark == 0 {
+            "Fetching graph packages"
+        } else {
+            "Fetching new graph packages"
+        };
+        GraphPackages { datastore: self,
+                        what,
+                        scope: scope.clone(),
+                        origins: self.origins().to_vec(),
+                        last_id: watermark,
+                        page: VecDeque::new(),
+                        done: false,
+                        rows_read: 0,
+                        excluded: Vec::new() }
+    }
+
+    /// Returns an iterator over the graph packages of a single origin, restricted to the scope,
+    /// whether or not the origin is one of the configured origins.
+    pub fn get_origin_graph_packages(&self, scope: &GraphScope, origin: &str) -> GraphPackages {
+        GraphPackages { datastore: self,
+                        what:      "Fetching an origin's graph packages",
+                        scope:     scope.clone(),
+                        origins:   vec![origin.to_string()],
+                        last_id:   0,
+                        page:      VecDeque::new(),
+                        done:      false,
+                        rows_read: 0,
+                        excluded:  Vec::new(), }
+    }
+}
+
+// Splits an ident that has at least an origin and a name into its parts,
+// ignoring surrounding whitespace and a trailing slash
+pub(crate) fn ident_parts(ident: &str) -> Option<Vec<&str>> {
+    let parts: Vec<&str> = ident.trim().trim_end_matches('/').split('/').collect();
+    if parts.len() < 2 || parts.len() > 4 || parts.iter().any(|p| p.is_empty()) {
+        return None;
+    }
+    Some(parts)
+}
+
+// Runs the operation, retrying it with exponential backoff while it fails with
+// a transient error, up to the configured number of attempts
+fn with_retry<T, F>(retry: &RetryCfg, what: &str, mut f: F) -> Result<T>
+    where F: FnMut() -> Result<T>
+{
+    let mut delay = retry.initial_delay_ms;
+    let mut attempt = 1;
+
+    loop {
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(ref e) if e.is_transient() && attempt < retry.attempts => {
+                warn!("{} failed (attempt {} of {}), retrying in {}ms: {}",
+                      what, attempt, retry.attempts, delay, e);
+                thread::sleep(Duration::from_millis(delay));
+                delay = cmp::min(delay.saturating_mul(2), retry.max_delay_ms);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Iterator over the graph packages, paging through the package table in id order. A failure to
+/// fetch a page is yielded as an error, after which the iterator ends. Packages left out by the
+/// visibility of the scope are skipped.
+pub struct GraphPackages<'a> {
+    datastore: &'a dyn PackageStore,
+    what:      &'static str,
+    scope:     GraphScope,
+    origins:   Vec<String>,
+    last_id:   i64,
+    page:      VecDeque<GraphPackage>,
+    done:      bool,
+    rows_read: usize,
+    excluded:  Vec<String>,
+}
+
+impl<'a> GraphPackages<'a> {
+    /// Returns the number of package rows read so far, including the skipped ones.
+    pub fn rows_read(&self) -> usize { self.rows_read }
+
+    /// Returns the idents of the packages skipped so far because of their visibility.
+    pub fn excluded(&self) -> &[String] { &self.excluded }
+
+    /// Returns the highest package id read so far, or the starting watermark if none were read.
+    pub fn watermark(&self) -> i64 { self.last_id }
+}
+
+impl<'a> Iterator for GraphPackages<'a> {
+    type Item = Result<GraphPackage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.page.is_empty() && !self.done {
+                match self.datastore
+                          .get_job_graph_packages_page(self.what,
+                                                       &self.scope,
+                                                       &self.origins,
+                                                       self.last_id)
+                {
+                    Ok(packages) => {
+                        self.done = (packages.len() as i64) < GRAPH_PACKAGES_PAGE_SIZE;
+                        if let Some(last) = packages.last() {
+                            self.last_id = last.package.get_id() as i64;
+                        }
+                        self.page.extend(packages);
+                    }
+                    Err(e) => {
+                        self.done = true;
+                        return Some(Err(e));
+                    }
+                }
+            }
+
+            let next = self.page.pop_front()?;
+            self.rows_read += 1;
+            if self.scope.visibility.includes(&next.package) {
+                return Some(Ok(next));
+            }
+            self.excluded.push(next.package.get_ident().to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fixture::FixtureStore;
+
+    // Needs the test database, started with builder-db/tests/db/start.sh
+    #[test]
+    #[ignore]
+    fn sessions_reject_writes() {
+        let datastore = DataStore::new(&Config::default()).unwrap();
+        let conn = datastore.pool.get().unwrap();
+        let err = conn.execute("CREATE TABLE graph_read_only_test (id BIGINT)", &[])
+                      .unwrap_err();
+        // read_only_sql_transaction
+        assert_eq!(err.code().map(|c| c.code()), Some("25006"));
+    }
+
+    // Needs the test database, started with builder-db/tests/db/start.sh
+    #[test]
+    #[ignore]
+    fn newest_first_chooses_as_the_fixture_store_does() {
+        let datastore = DataStore::new(&Config::default()).unwrap();
+        let conn = datastore.pool.get().unwrap();
+        let scope = GraphScope { latest_only: true,
+                                 ..GraphScope::default() };
+        let cases = [["core/foo/2.0/20190101000000", "core/foo/2.0a/20190101000000"],
+                     ["core/foo/2.0-rc1/20190101000000", "core/foo/2.0/20190101000000"],
+                     ["core/foo/1.10.0/20190101000000", "core/foo/1.9.2/20190101000000"],
+                     ["core/foo/1.0/9", "core/foo/1.0/10"],
+                     ["core/foo/1.0/20190115003728", "core/foo/1.0/2019011500372"]];
+        for idents in &cases {
+            for &(first, second) in &[(idents[0], idents[1]), (idents[1], idents[0])] {
+                let rows = conn.query(&format!("SELECT array_to_string(op.ident_array, '/') \
+                                                AS ident FROM (VALUES \
+                                                (string_to_array($1, '/')), \
+                                                (string_to_array($2, '/'))) op(ident_array) \
+                                                ORDER BY {} LIMIT 1",
+                                               NEWEST_FIRST),
+                                      &[&first, &second])
+                               .unwrap();
+                let newest: String = rows.get(0).get("ident");
+
+                let json = format!(r#"{{"packages": [{{"ident": "{}"}}, {{"ident": "{}"}}]}}"#,
+                                   first, second);
+                let store = FixtureStore::from_json(&json, &[]).unwrap();
+                let store: &dyn PackageStore = &store;
+                let latest = store.get_job_graph_packages(&scope).next().unwrap().unwrap();
+                assert_eq!(newest, latest.package.get_ident().to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn unreachable_database_is_a_connection_error() {
+        // 192.0.2.0/24 is reserved for documentation, so nothing answers there
+        let mut config = Config::default();
+        config.datastore.host = String::from("192.0.2.1");
+        config.datastore.connection_timeout_sec = 1;
+        config.retry.attempts = 1;
+
+        match DataStore::connect(&config) {
+            Err(e @ Error::DbConnect(..)) => {
+                assert!(e.to_string().contains("@192.0.2.1:5432/builder"), "{}", e);
+                assert_eq!(e.exit_code(), crate::error::EXIT_CONNECT);
+            }
+            Err(e) => panic!("expected a connection error, got {}", e),
+            Ok(_) => panic!("connected to an unroutable address"),
+        }
+    }
+
+    #[test]
+    fn visibility_includes_public_packages_only_when_public() {
+        let mut package = originsrv::OriginPackage::new();
+        package.set_visibility(originsrv::OriginPackageVisibility::Private);
+        assert!(Visibility::All.includes(&package));
+        assert!(!Visibility::Public.includes(&package));
+
+        package.set_visibility(originsrv::OriginPackageVisibility::Public);
+        assert!(Visibility::Public.includes(&package));
+
+        assert_eq!("public".parse::<Visibility>(), Ok(Visibility::Public));
+        assert_eq!("all".parse::<Visibility>(), Ok(Visibility::All));
+        assert!("private".parse::<Visibility>().is_err());
+    }
+
+    #[test]
+    fn ident_parts_accepts_partial_idents() {
+        assert_eq!(ident_parts("core/openssl"), Some(vec!["core", "openssl"]));
+        assert_eq!(ident_parts(" core/openssl/1.1.1/ "),
+                   Some(vec!["core", "openssl", "1.1.1"]));
+        assert_eq!(ident_parts("core/openssl/1.1.1/20190911085822"),
+                   Some(vec!["core", "openssl", "1.1.1", "20190911085822"]));
+        assert_eq!(ident_parts("openssl"), None);
+        assert_eq!(ident_parts("core//1.1.1"), None);
+        assert_eq!(ident_parts("core/openssl/1.1.1/20190911085822/extra"), None);
+    }
+}
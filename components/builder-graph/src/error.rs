@@ -17,41 +17,122 @@ use std::{error,
           io,
           result};
 
-use crate::{db,
+use crate::{bldr_core::cancel::Cancelled,
+            config,
+            db,
             hab_core};
 
 use postgres;
 use protobuf;
 use r2d2;
+use serde_json;
 
 #[derive(Debug)]
 pub enum Error {
+    CacheFormatVersion(u32, u32),
+    ChannelPackagesGet(postgres::error::Error),
+    ConfigFile(String, Box<Error>),
     Db(db::error::Error),
+    DbConnect(String, Box<Error>),
+    DbPasswordFile(String, io::Error),
     DbPoolTimeout(r2d2::Error),
+    DbSetup(String, Box<Error>),
     DbTransaction(postgres::error::Error),
+    FileExists(String),
     HabitatCore(hab_core::Error),
+    Interrupted,
+    InvalidAliases(String),
+    InvalidCostsFile(String),
+    InvalidDbPort(String),
+    InvalidGraphFile(String),
+    InvalidMaxResults(usize),
+    MalformedIdent(String),
+    MissingExportDir(String),
+    NotInGraph(String, Vec<String>),
     IO(io::Error),
+    JobGet(postgres::error::Error),
     JobGraphPackagesGet(postgres::error::Error),
+    Json(serde_json::Error),
     Protobuf(protobuf::ProtobufError),
+    QueryTimeout(u64),
+    UnknownExportFormat(String),
     UnknownJobGraphPackage,
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
+// SQLSTATE of a statement cancelled by the server, e.g. on statement_timeout
+const QUERY_CANCELED: &str = "57014";
+
+// The exit codes of the failures that stop the tool at startup, by the step
+// that failed: reading the config, connecting to the database and setting it
+// up. Any other failure exits with 1.
+pub const EXIT_CONFIG: i32 = 2;
+pub const EXIT_CONNECT: i32 = 3;
+pub const EXIT_SETUP: i32 = 4;
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let msg = match *self {
+            Error::CacheFormatVersion(found, expected) => {
+                format!("Graph cache format version {} is not supported (expected {}), recreate \
+                         the cache with save-cache",
+                        found, expected)
+            }
+            Error::ChannelPackagesGet(ref e) => {
+                format!("Database error retrieving channel packages, {}", e)
+            }
+            Error::ConfigFile(ref s, ref e) => format!("Could not load config file {}, {}", s, e),
             Error::Db(ref e) => format!("{}", e),
+            Error::DbConnect(ref s, ref e) => {
+                format!("Could not connect to the database {}, {}", s, e)
+            }
+            Error::DbPasswordFile(ref s, ref e) => {
+                format!("Could not read the database password from {}, {}", s, e)
+            }
             Error::DbPoolTimeout(ref e) => {
                 format!("Timeout getting connection from the database pool, {}", e)
             }
+            Error::DbSetup(ref s, ref e) => format!("Could not set up the database {}, {}", s, e),
             Error::DbTransaction(ref e) => format!("Database transaction error, {}", e),
+            Error::FileExists(ref s) => {
+                format!("File {} already exists, use --force to overwrite it", s)
+            }
             Error::HabitatCore(ref e) => format!("{}", e),
+            Error::Interrupted => Cancelled.to_string(),
+            Error::InvalidAliases(ref s) => format!("Invalid aliases, {}", s),
+            Error::InvalidCostsFile(ref s) => format!("Invalid costs file, {}", s),
+            Error::InvalidDbPort(ref s) => format!("Invalid database port: {}", s),
+            Error::InvalidGraphFile(ref s) => format!("Invalid graph file, {}", s),
+            Error::MalformedIdent(ref s) => {
+                format!("Malformed ident '{}', expected origin/name[/version[/release]]", s)
+            }
+            Error::MissingExportDir(ref s) => {
+                format!("Export directory {} does not exist, create it or set create_dirs",
+                        s)
+            }
+            Error::NotInGraph(ref s, ref suggested) if suggested.is_empty() => {
+                format!("Package '{}' is not present in the graph", s)
+            }
+            Error::NotInGraph(ref s, ref suggested) => {
+                format!("Package '{}' is not present in the graph (did you mean {}?)",
+                        s,
+                        suggested.join(", "))
+            }
+            Error::InvalidMaxResults(max) => {
+                format!("Invalid default_max_results {}, expected 1 to {}",
+                        max,
+                        config::MAX_RESULTS_LIMIT)
+            }
             Error::IO(ref e) => format!("{}", e),
+            Error::JobGet(ref e) => format!("Database error retrieving job, {}", e),
             Error::JobGraphPackagesGet(ref e) => {
                 format!("Database error retrieving packages, {}", e)
             }
+            Error::Json(ref e) => format!("{}", e),
             Error::Protobuf(ref e) => format!("{}", e),
+            Error::QueryTimeout(secs) => format!("Query exceeded {}s timeout", secs),
+            Error::UnknownExportFormat(ref s) => format!("Unknown export format: {}", s),
             Error::UnknownJobGraphPackage => "Unknown Package".to_string(),
         };
         write!(f, "{}", msg)
@@ -61,22 +142,93 @@ impl fmt::Display for Error {
 impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
+            Error::CacheFormatVersion(..) => "Unsupported graph cache format version",
+            Error::ChannelPackagesGet(ref err) => err.description(),
+            Error::ConfigFile(..) => "Could not load config file",
             Error::Db(ref err) => err.description(),
+            Error::DbConnect(..) => "Could not connect to the database",
+            Error::DbPasswordFile(_, ref err) => err.description(),
             Error::DbPoolTimeout(ref err) => err.description(),
+            Error::DbSetup(..) => "Could not set up the database",
             Error::DbTransaction(ref err) => err.description(),
+            Error::FileExists(_) => "File already exists",
             Error::HabitatCore(ref err) => err.description(),
+            Error::Interrupted => "Interrupted",
+            Error::InvalidAliases(_) => "Invalid aliases",
+            Error::InvalidCostsFile(_) => "Invalid costs file",
+            Error::InvalidDbPort(_) => "Invalid database port",
+            Error::InvalidGraphFile(_) => "Invalid graph file",
+            Error::InvalidMaxResults(_) => "Invalid default max results",
+            Error::MalformedIdent(_) => "Malformed ident",
+            Error::MissingExportDir(_) => "Export directory does not exist",
+            Error::NotInGraph(..) => "Package not present in the graph",
             Error::IO(ref err) => err.description(),
+            Error::JobGet(ref err) => err.description(),
             Error::JobGraphPackagesGet(ref err) => err.description(),
+            Error::Json(ref err) => err.description(),
             Error::Protobuf(ref err) => err.description(),
+            Error::QueryTimeout(_) => "Query exceeded the statement timeout",
+            Error::UnknownExportFormat(_) => "Unknown export format",
             Error::UnknownJobGraphPackage => "Unknown Package",
         }
     }
 }
 
+impl Error {
+    /// Whether the error is a connection failure that may go away if the operation is retried.
+    pub fn is_transient(&self) -> bool {
+        match *self {
+            Error::Db(db::error::Error::ConnectionTimeout(_))
+            | Error::Db(db::error::Error::PostgresConnect(_))
+            | Error::DbPoolTimeout(_) => true,
+            Error::ChannelPackagesGet(ref e)
+            | Error::JobGet(ref e)
+            | Error::JobGraphPackagesGet(ref e) => {
+                e.as_io().is_some() || e.code().map_or(false, |c| c.code().starts_with("08"))
+            }
+            _ => false,
+        }
+    }
+
+    /// The code the tool exits with when the error stops it at startup, telling apart the
+    /// failures of the config, of the connection to the database and of its setup.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::ConfigFile(..)
+            | Error::DbPasswordFile(..)
+            | Error::InvalidAliases(_)
+            | Error::InvalidCostsFile(_)
+            | Error::InvalidDbPort(_)
+            | Error::InvalidMaxResults(_)
+            | Error::UnknownExportFormat(_) => EXIT_CONFIG,
+            Error::DbConnect(..) => EXIT_CONNECT,
+            Error::DbSetup(..) => EXIT_SETUP,
+            _ => 1,
+        }
+    }
+
+    /// Whether the error is a query that the database cancelled, as it does when the statement
+    /// timeout is exceeded.
+    pub fn is_query_canceled(&self) -> bool {
+        match *self {
+            Error::ChannelPackagesGet(ref e)
+            | Error::JobGet(ref e)
+            | Error::JobGraphPackagesGet(ref e) => {
+                e.code().map_or(false, |c| c.code() == QUERY_CANCELED)
+            }
+            _ => false,
+        }
+    }
+}
+
 impl From<hab_core::Error> for Error {
     fn from(err: hab_core::Error) -> Error { Error::HabitatCore(err) }
 }
 
+impl From<Cancelled> for Error {
+    fn from(_: Cancelled) -> Error { Error::Interrupted }
+}
+
 impl From<db::error::Error> for Error {
     fn from(err: db::error::Error) -> Self { Error::Db(err) }
 }
@@ -85,6 +237,10 @@ impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error { Error::IO(err) }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error { Error::Json(err) }
+}
+
 impl From<protobuf::ProtobufError> for Error {
     fn from(err: protobuf::ProtobufError) -> Error { Error::Protobuf(err) }
 }
@@ -0,0 +1,1343 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{collections::{BTreeMap,
+                        BTreeSet,
+                        HashSet},
+          env,
+          fmt,
+          fs::{self,
+               File},
+          io::{self,
+               BufRead,
+               BufReader,
+               BufWriter,
+               Write},
+          path::{Path,
+                 PathBuf},
+          str::FromStr};
+
+use chrono::Utc;
+use flate2::{read::GzDecoder,
+             write::GzEncoder,
+             Compression};
+use serde_json;
+
+use crate::{bldr_core::{cancel::uncancelled,
+                        package_graph::{matches_filter,
+                                        EdgeFilter,
+                                        PackageGraph,
+                                        Stats}},
+            config::{ExportCfg,
+                     OverwritePolicy},
+            data_store::{GraphScope,
+                         Visibility},
+            error::{Error,
+                    Result},
+            hab_core::package::PackageIdent};
+
+// The number of bytes written to an export file, before and (if the output
+// was gzip-compressed) after compression.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ByteCounts {
+    pub uncompressed: u64,
+    pub compressed:   Option<u64>,
+}
+
+impl fmt::Display for ByteCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.compressed {
+            Some(compressed) => {
+                write!(f,
+                       "{} bytes, {} bytes compressed",
+                       self.uncompressed,
+                       compressed)
+            }
+            None => write!(f, "{} bytes", self.uncompressed),
+        }
+    }
+}
+
+// Counts the bytes passed through to the inner writer
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
+enum Sink<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<CountingWriter<W>>),
+}
+
+// An export destination that optionally gzip-compresses the output as it is
+// streamed, keeping track of the bytes written.
+pub struct Output<W: Write> {
+    sink:    Sink<W>,
+    written: u64,
+}
+
+impl<W: Write> Output<W> {
+    pub fn new(writer: W, compress: bool) -> Self {
+        let sink = if compress {
+            Sink::Gzip(GzEncoder::new(CountingWriter { inner: writer,
+                                                       count: 0, },
+                                      Compression::default()))
+        } else {
+            Sink::Plain(writer)
+        };
+        Output { sink, written: 0 }
+    }
+
+    // Completes the compressed stream, if any, and flushes the output.
+    // Returns the underlying writer and the number of bytes written.
+    pub fn finish(self) -> Result<(W, ByteCounts)> {
+        match self.sink {
+            Sink::Plain(mut writer) => {
+                writer.flush()?;
+                Ok((writer,
+                    ByteCounts { uncompressed: self.written,
+                                 compressed:   None, }))
+            }
+            Sink::Gzip(encoder) => {
+                let mut counter = encoder.finish()?;
+                counter.flush()?;
+                Ok((counter.inner,
+                    ByteCounts { uncompressed: self.written,
+                                 compressed:   Some(counter.count), }))
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for Output<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = match self.sink {
+            Sink::Plain(ref mut writer) => writer.write(buf)?,
+            Sink::Gzip(ref mut encoder) => encoder.write(buf)?,
+        };
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.sink {
+            Sink::Plain(ref mut writer) => writer.flush(),
+            Sink::Gzip(ref mut encoder) => encoder.flush(),
+        }
+    }
+}
+
+// The absolute path an export command writes the file to, keeping the name
+// as it was given. A bare file name goes to the default directory, if there is
+// one. The directory of the file is created if it is missing and the config
+// allows it. An existing file is then refused, moved aside to a '.bak' file or
+// left to be replaced, as the config says, unless the command is forced to
+// replace it.
+pub fn output_path(cfg: &ExportCfg, filename: &str, force: bool) -> Result<String> {
+    let mut path = PathBuf::from(filename);
+    if let Some(ref dir) = cfg.default_dir {
+        if path.parent() == Some(Path::new("")) {
+            path = Path::new(dir).join(path);
+        }
+    }
+    if let Some(dir) = path.parent().filter(|dir| *dir != Path::new("")) {
+        if !dir.is_dir() {
+            if !cfg.create_dirs {
+                return Err(Error::MissingExportDir(dir.display().to_string()));
+            }
+            fs::create_dir_all(dir)?;
+        }
+    }
+
+    if path.exists() && !force {
+        match cfg.overwrite {
+            OverwritePolicy::Refuse => return Err(Error::FileExists(path.display().to_string())),
+            OverwritePolicy::Backup => {
+                let mut backup = path.clone().into_os_string();
+                backup.push(".bak");
+                fs::rename(&path, backup)?;
+            }
+            OverwritePolicy::Replace => (),
+        }
+    }
+
+    if path.is_relative() {
+        path = env::current_dir()?.join(path);
+    }
+    Ok(path.to_string_lossy().into_owned())
+}
+
+// Creates the export file, gzip-compressing the output if requested or if the
+// file name ends in '.gz'
+pub fn create_output(filename: &str, compress: bool) -> Result<Output<BufWriter<File>>> {
+    let writer = BufWriter::new(File::create(filename)?);
+    Ok(Output::new(writer, compress || filename.ends_with(".gz")))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    CsvNodes,
+    Dot,
+    GraphMl,
+    Json,
+    Plans,
+    Text,
+    Tsv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "csv-nodes" => Ok(ExportFormat::CsvNodes),
+            "dot" => Ok(ExportFormat::Dot),
+            "graphml" => Ok(ExportFormat::GraphMl),
+            "json" => Ok(ExportFormat::Json),
+            "plans" => Ok(ExportFormat::Plans),
+            "text" => Ok(ExportFormat::Text),
+            "tsv" => Ok(ExportFormat::Tsv),
+            _ => Err(Error::UnknownExportFormat(value.to_string())),
+        }
+    }
+}
+
+// How the graph being exported was built, recorded in the metadata of the
+// formats that have it
+#[derive(Clone, Copy, Debug)]
+pub struct GraphMode {
+    pub build_deps: bool,
+    pub visibility: Visibility,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonMetadata {
+    generated_at: String,
+    node_count:   usize,
+    edge_count:   usize,
+    build_deps:   bool,
+    #[serde(default)]
+    visibility:   Visibility,
+    filter:       Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonNode {
+    ident:  Option<String>,
+    name:   String,
+    origin: String,
+}
+
+// The package in 'from' depends on the package in 'to'
+#[derive(Serialize, Deserialize)]
+struct JsonEdge {
+    from: String,
+    to:   String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonDocument {
+    metadata: JsonMetadata,
+    nodes:    Vec<JsonNode>,
+    edges:    Vec<JsonEdge>,
+}
+
+// Writes the graph to the file in the given format, restricted to the nodes
+// matching the filter. Returns the number of nodes and edges written. The
+// plans format writes full idents rather than names if idents is set.
+pub fn export(graph: &PackageGraph,
+              filename: &str,
+              format: ExportFormat,
+              filter: &str,
+              mode: GraphMode,
+              idents: bool,
+              compress: bool)
+              -> Result<(usize, usize, ByteCounts)> {
+    let mut writer = create_output(filename, compress)?;
+
+    let counts = match format {
+        ExportFormat::Csv => write_csv(graph, &mut writer, filter)?,
+        ExportFormat::CsvNodes => write_csv_nodes(graph, &mut writer, filter)?,
+        ExportFormat::Dot => write_dot(graph, &mut writer, filter)?,
+        ExportFormat::GraphMl => write_graphml(graph, &mut writer, filter)?,
+        ExportFormat::Json => write_json(graph, &mut writer, filter, mode)?,
+        ExportFormat::Plans => write_plans(graph, &mut writer, filter, idents)?,
+        ExportFormat::Text => write_text(graph, &mut writer, filter)?,
+        ExportFormat::Tsv => write_tsv(graph, &mut writer, filter)?,
+    };
+
+    let (_, bytes) = writer.finish()?;
+    Ok((counts.0, counts.1, bytes))
+}
+
+fn write_text<W: Write>(graph: &PackageGraph,
+                        writer: &mut W,
+                        filter: &str)
+                        -> Result<(usize, usize)> {
+    let mut count = 0;
+
+    for ident in graph.latest() {
+        if matches_filter(&ident, filter) {
+            writeln!(writer, "{}", ident)?;
+            count += 1;
+        }
+    }
+
+    Ok((count, 0))
+}
+
+#[derive(Serialize)]
+struct JsonOriginSummary<'a> {
+    origin:         &'a str,
+    package_count:  usize,
+    internal_edges: usize,
+    edges_out:      usize,
+    edges_in:       usize,
+    top:            Vec<JsonTopPackage<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonTopPackage<'a> {
+    name:       &'a str,
+    dependents: usize,
+}
+
+// Writes a summary of each origin, as CSV or JSON. Returns the number of
+// origins written.
+pub fn export_report(graph: &PackageGraph,
+                     filename: &str,
+                     format: ExportFormat,
+                     top: usize,
+                     compress: bool)
+                     -> Result<(usize, ByteCounts)> {
+    let summaries = graph.origin_summaries(top);
+    let mut writer = create_output(filename, compress)?;
+
+    match format {
+        ExportFormat::Csv => {
+            writeln!(writer,
+                     "origin,package_count,internal_edges,edges_out,edges_in,top_packages")?;
+            for s in &summaries {
+                let top: Vec<String> = s.top
+                                        .iter()
+                                        .map(|&(ref name, count)| format!("{}:{}", name, count))
+                                        .collect();
+                writeln!(writer,
+                         "{},{},{},{},{},{}",
+                         csv_field(&s.origin),
+                         s.package_count,
+                         s.internal_edges,
+                         s.edges_out,
+                         s.edges_in,
+                         csv_field(&top.join(";")))?;
+            }
+        }
+        ExportFormat::Json => {
+            let doc: Vec<JsonOriginSummary> =
+                summaries.iter()
+                         .map(|s| {
+                             let top = s.top
+                                        .iter()
+                                        .map(|&(ref name, dependents)| {
+                                            JsonTopPackage { name, dependents }
+                                        })
+                                        .collect();
+                             JsonOriginSummary { origin: &s.origin,
+                                                 package_count: s.package_count,
+                                                 internal_edges: s.internal_edges,
+                                                 edges_out: s.edges_out,
+                                                 edges_in: s.edges_in,
+                                                 top }
+                         })
+                         .collect();
+            serde_json::to_writer_pretty(&mut writer, &doc)?;
+            writeln!(writer)?;
+        }
+        _ => return Err(Error::UnknownExportFormat(format!("{:?}", format))),
+    }
+
+    let (_, bytes) = writer.finish()?;
+    Ok((summaries.len(), bytes))
+}
+
+// Writes one row per latest package, sorted by origin and then name. The
+// target column is left empty as the graph does not track targets.
+fn write_tsv<W: Write>(graph: &PackageGraph,
+                       writer: &mut W,
+                       filter: &str)
+                       -> Result<(usize, usize)> {
+    writeln!(writer, "origin\tname\tversion\trelease\ttarget")?;
+
+    let mut idents: Vec<PackageIdent> = graph.latest()
+                                             .iter()
+                                             .filter(|ident| matches_filter(ident, filter))
+                                             .filter_map(|ident| PackageIdent::from_str(ident).ok())
+                                             .collect();
+    idents.sort_by(|a, b| a.origin.cmp(&b.origin).then_with(|| a.name.cmp(&b.name)));
+
+    for ident in &idents {
+        writeln!(writer,
+                 "{}\t{}\t{}\t{}\t",
+                 ident.origin,
+                 ident.name,
+                 ident.version.as_ref().map(String::as_str).unwrap_or_default(),
+                 ident.release.as_ref().map(String::as_str).unwrap_or_default())?;
+    }
+
+    Ok((idents.len(), 0))
+}
+
+// Writes the latest packages matching the filter under an '[origin]' header
+// per origin, one name (or ident) per line in build order within the origin,
+// with packages in cycles listed last. Dependencies on packages that are not
+// in the file are listed in a trailing comment section. Returns the number of
+// packages and of such dependencies.
+fn write_plans<W: Write>(graph: &PackageGraph,
+                         writer: &mut W,
+                         filter: &str,
+                         idents: bool)
+                         -> Result<(usize, usize)> {
+    let latest: Vec<String> = graph.latest()
+                                   .into_iter()
+                                   .filter(|ident| matches_filter(ident, filter))
+                                   .collect();
+    let names: HashSet<&str> = latest.iter().map(|ident| short_name_of(ident)).collect();
+    let origins: BTreeSet<&str> = names.iter().map(|name| origin_of(name)).collect();
+
+    let mut count = 0;
+    let mut external = Vec::new();
+
+    for (i, origin) in origins.iter().enumerate() {
+        if i > 0 {
+            writeln!(writer)?;
+        }
+        writeln!(writer, "[{}]", origin)?;
+
+        let (ordered, cycles) = graph.toposort(origin);
+        for (j, group) in Some(ordered).into_iter().chain(cycles).enumerate() {
+            let group: Vec<String> = group.into_iter()
+                                          .filter(|ident| matches_filter(ident, filter))
+                                          .collect();
+            if j > 0 && !group.is_empty() {
+                writeln!(writer, "# cycle")?;
+            }
+
+            for ident in &group {
+                let name = short_name_of(ident);
+                if idents {
+                    writeln!(writer, "{}", ident)?;
+                } else {
+                    writeln!(writer, "{}", &name[origin.len() + 1..])?;
+                }
+                count += 1;
+
+                for dep in graph.direct_deps(name).unwrap_or_default() {
+                    if !names.contains(dep.as_str()) {
+                        external.push((name.to_string(), dep));
+                    }
+                }
+            }
+        }
+    }
+
+    if !external.is_empty() {
+        writeln!(writer, "\n# Dependencies on packages that are not in this file")?;
+        for (name, dep) in &external {
+            writeln!(writer, "# {} -> {}", name, dep)?;
+        }
+    }
+
+    Ok((count, external.len()))
+}
+
+// Writes one row per edge whose dependent package matches the filter
+fn write_csv<W: Write>(graph: &PackageGraph,
+                       writer: &mut W,
+                       filter: &str)
+                       -> Result<(usize, usize)> {
+    writeln!(writer, "from_ident,to_ident,from_origin,to_origin")?;
+
+    let mut count = 0;
+    for (from, to) in graph.edges() {
+        if !matches_filter(&from, filter) {
+            continue;
+        }
+
+        writeln!(writer,
+                 "{},{},{},{}",
+                 csv_field(&graph.resolve(&from).unwrap_or_else(|| from.clone())),
+                 csv_field(&graph.resolve(&to).unwrap_or_else(|| to.clone())),
+                 csv_field(origin_of(&from)),
+                 csv_field(origin_of(&to)))?;
+        count += 1;
+    }
+
+    Ok((0, count))
+}
+
+// Writes one row per node matching the filter
+fn write_csv_nodes<W: Write>(graph: &PackageGraph,
+                             writer: &mut W,
+                             filter: &str)
+                             -> Result<(usize, usize)> {
+    writeln!(writer, "ident,origin,name,version,rdep_count")?;
+
+    let mut count = 0;
+    for name in graph.node_names() {
+        if !matches_filter(&name, filter) {
+            continue;
+        }
+
+        let ident = graph.resolve(&name).unwrap_or_else(|| name.clone());
+        let version = ident.split('/').nth(2).unwrap_or_default();
+        writeln!(writer,
+                 "{},{},{},{},{}",
+                 csv_field(&ident),
+                 csv_field(origin_of(&name)),
+                 csv_field(name.split('/').nth(1).unwrap_or_default()),
+                 csv_field(version),
+                 graph.dependent_count(&name).unwrap_or(0))?;
+        count += 1;
+    }
+
+    Ok((count, 0))
+}
+
+fn write_graphml<W: Write>(graph: &PackageGraph,
+                           writer: &mut W,
+                           filter: &str)
+                           -> Result<(usize, usize)> {
+    let nodes: Vec<String> = graph.node_names()
+                                  .into_iter()
+                                  .filter(|name| matches_filter(name, filter))
+                                  .collect();
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(writer,
+             r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+    writeln!(writer,
+             r#"  <key id="ident" for="node" attr.name="ident" attr.type="string"/>"#)?;
+    writeln!(writer,
+             r#"  <key id="origin" for="node" attr.name="origin" attr.type="string"/>"#)?;
+    writeln!(writer,
+             r#"  <key id="rdeps" for="node" attr.name="rdep_count" attr.type="int"/>"#)?;
+    writeln!(writer, r#"  <graph id="packages" edgedefault="directed">"#)?;
+
+    for name in &nodes {
+        let ident = graph.resolve(name).unwrap_or_else(|| name.clone());
+        writeln!(writer, r#"    <node id="{}">"#, xml_escape(name))?;
+        writeln!(writer, r#"      <data key="ident">{}</data>"#, xml_escape(&ident))?;
+        writeln!(writer,
+                 r#"      <data key="origin">{}</data>"#,
+                 xml_escape(origin_of(name)))?;
+        writeln!(writer,
+                 r#"      <data key="rdeps">{}</data>"#,
+                 graph.dependent_count(name).unwrap_or(0))?;
+        writeln!(writer, "    </node>")?;
+    }
+
+    let mut edge_count = 0;
+    for (from, to) in graph.edges() {
+        if nodes.binary_search(&from).is_ok() && nodes.binary_search(&to).is_ok() {
+            writeln!(writer,
+                     r#"    <edge source="{}" target="{}"/>"#,
+                     xml_escape(&from),
+                     xml_escape(&to))?;
+            edge_count += 1;
+        }
+    }
+
+    writeln!(writer, "  </graph>")?;
+    writeln!(writer, "</graphml>")?;
+
+    Ok((nodes.len(), edge_count))
+}
+
+fn write_json<W: Write>(graph: &PackageGraph,
+                        writer: &mut W,
+                        filter: &str,
+                        mode: GraphMode)
+                        -> Result<(usize, usize)> {
+    let nodes: Vec<JsonNode> =
+        graph.node_names()
+             .into_iter()
+             .filter(|name| matches_filter(name, filter))
+             .map(|name| {
+                 JsonNode { ident:  graph.resolve(&name),
+                            origin: origin_of(&name).to_string(),
+                            name }
+             })
+             .collect();
+
+    let edges: Vec<JsonEdge> = graph.edges()
+                                    .into_iter()
+                                    .filter(|&(ref from, ref to)| {
+                                        matches_filter(from, filter) && matches_filter(to, filter)
+                                    })
+                                    .map(|(from, to)| JsonEdge { from, to })
+                                    .collect();
+
+    let metadata = JsonMetadata { generated_at: Utc::now().to_rfc3339(),
+                                  node_count: nodes.len(),
+                                  edge_count: edges.len(),
+                                  build_deps: mode.build_deps,
+                                  visibility: mode.visibility,
+                                  filter: Some(filter.to_string()).filter(|f| !f.is_empty()) };
+
+    let doc = JsonDocument { metadata,
+                             nodes,
+                             edges };
+
+    serde_json::to_writer_pretty(&mut *writer, &doc)?;
+    writeln!(writer)?;
+
+    Ok((doc.metadata.node_count, doc.metadata.edge_count))
+}
+
+#[derive(Serialize)]
+struct JsonStats<'a> {
+    generated_at: String,
+    source:       &'a str,
+    target:       Option<&'a str>,
+    channel:      Option<String>,
+    visibility:   Visibility,
+    latest_only:  bool,
+    stats:        &'a Stats,
+}
+
+// Writes the graph statistics as a JSON document, along with the time, the
+// source of the graph and the target, channel and visibility it was
+// restricted to
+pub fn export_stats(stats: &Stats,
+                    filename: &str,
+                    source: &str,
+                    scope: &GraphScope)
+                    -> Result<ByteCounts> {
+    let mut writer = create_output(filename, false)?;
+    let doc = JsonStats { generated_at: Utc::now().to_rfc3339(),
+                          source,
+                          target: scope.target.as_ref().map(String::as_str),
+                          channel: scope.channel
+                                        .as_ref()
+                                        .map(|(origin, channel)| format!("{}/{}", origin, channel)),
+                          visibility: scope.visibility,
+                          latest_only: scope.latest_only,
+                          stats };
+
+    serde_json::to_writer_pretty(&mut writer, &doc)?;
+    writeln!(writer)?;
+
+    let (_, bytes) = writer.finish()?;
+    Ok(bytes)
+}
+
+// Writes the neighborhood of the package, up to depth hops away on both the
+// dependency and reverse dependency sides, as a DOT digraph with the package
+// highlighted. Returns None if the package is not in the graph.
+pub fn export_dot_neighborhood(graph: &PackageGraph,
+                               name: &str,
+                               depth: usize,
+                               filename: &str,
+                               compress: bool)
+                               -> Result<Option<(usize, usize, ByteCounts)>> {
+    let neighborhood = match graph.neighborhood(name, depth, depth) {
+        Some(neighborhood) => neighborhood,
+        None => return Ok(None),
+    };
+
+    let mut writer = create_output(filename, compress)?;
+    let (node_count, edge_count) = write_dot_graph(&mut writer,
+                                                   &neighborhood.node_names(),
+                                                   neighborhood.edges().into_iter(),
+                                                   Some(name))?;
+    let (_, bytes) = writer.finish()?;
+
+    Ok(Some((node_count, edge_count, bytes)))
+}
+
+#[derive(Debug, Default)]
+pub struct ResolveFileTotals {
+    pub resolved:  usize,
+    pub not_found: usize,
+    pub malformed: usize,
+}
+
+// Resolves each 'origin/name' line of the input file, writing a tab-separated
+// line per input with the name, the latest ident and its direct dependency
+// idents (comma-separated). Names that are not in the graph get a NOT-FOUND
+// marker, and lines that are not in 'origin/name' format a MALFORMED marker.
+// Blank lines are skipped.
+pub fn resolve_file(graph: &PackageGraph,
+                    input: &str,
+                    output: &str)
+                    -> Result<ResolveFileTotals> {
+    let reader = BufReader::new(File::open(input)?);
+    let mut writer = BufWriter::new(File::create(output)?);
+    let mut totals = ResolveFileTotals::default();
+
+    for line in reader.split(b'\n') {
+        let line = String::from_utf8_lossy(&line?).trim().to_string();
+        if line.is_empty() {
+            continue;
+        }
+
+        let name = line.to_lowercase();
+        if check_short_name(&name).is_err() {
+            writeln!(writer, "{}\tMALFORMED", line)?;
+            totals.malformed += 1;
+            continue;
+        }
+
+        match graph.resolve(&name) {
+            Some(ident) => {
+                let deps: Vec<String> = graph.direct_deps(&name)
+                                             .unwrap_or_default()
+                                             .into_iter()
+                                             .map(|dep| graph.resolve(&dep).unwrap_or(dep))
+                                             .collect();
+                writeln!(writer, "{}\t{}\t{}", line, ident, deps.join(","))?;
+                totals.resolved += 1;
+            }
+            None => {
+                writeln!(writer, "{}\tNOT-FOUND", line)?;
+                totals.not_found += 1;
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(totals)
+}
+
+#[derive(Debug, Default)]
+pub struct LatestDiff {
+    pub added:     Vec<String>,
+    pub removed:   Vec<String>,
+    pub changed:   Vec<(String, String)>,
+    pub malformed: Vec<(usize, String)>,
+}
+
+// Compares the latest packages matching the filter against a previous export
+// of the latest packages (one fully-qualified ident per line, as written by
+// the text format), and writes the added, removed and changed packages in
+// three sections. The previous export is decompressed if its name ends in
+// '.gz'. Malformed lines in it are skipped and returned with their line
+// numbers.
+pub fn export_diff(graph: &PackageGraph,
+                   filename: &str,
+                   old_filename: &str,
+                   filter: &str,
+                   compress: bool)
+                   -> Result<(LatestDiff, ByteCounts)> {
+    let file = File::open(old_filename)?;
+    let diff = if old_filename.ends_with(".gz") {
+        diff_latest(graph, BufReader::new(GzDecoder::new(file)), filter)?
+    } else {
+        diff_latest(graph, BufReader::new(file), filter)?
+    };
+
+    let mut writer = create_output(filename, compress)?;
+
+    writeln!(writer, "# added ({})", diff.added.len())?;
+    for ident in &diff.added {
+        writeln!(writer, "{}", ident)?;
+    }
+
+    writeln!(writer, "\n# removed ({})", diff.removed.len())?;
+    for ident in &diff.removed {
+        writeln!(writer, "{}", ident)?;
+    }
+
+    writeln!(writer, "\n# changed ({})", diff.changed.len())?;
+    for (old, new) in &diff.changed {
+        writeln!(writer, "{} -> {}", old, new)?;
+    }
+
+    let (_, bytes) = writer.finish()?;
+    Ok((diff, bytes))
+}
+
+fn diff_latest<R: BufRead>(graph: &PackageGraph, old: R, filter: &str) -> Result<LatestDiff> {
+    let mut diff = LatestDiff::default();
+    let mut previous = BTreeMap::new();
+
+    for (i, line) in old.split(b'\n').enumerate() {
+        let line = String::from_utf8_lossy(&line?).trim().to_string();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match PackageIdent::from_str(&line) {
+            Ok(ref ident) if ident.fully_qualified() => {
+                if matches_filter(&line, filter) {
+                    previous.insert(format!("{}/{}", ident.origin, ident.name), line);
+                }
+            }
+            _ => diff.malformed.push((i + 1, line)),
+        }
+    }
+
+    let mut current = BTreeMap::new();
+    for ident in graph.latest() {
+        if matches_filter(&ident, filter) {
+            current.insert(short_name_of(&ident).to_string(), ident);
+        }
+    }
+
+    for (name, ident) in &current {
+        match previous.get(name) {
+            Some(old) if old != ident => diff.changed.push((old.clone(), ident.clone())),
+            Some(_) => (),
+            None => diff.added.push(ident.clone()),
+        }
+    }
+
+    for (name, ident) in previous {
+        if !current.contains_key(&name) {
+            diff.removed.push(ident);
+        }
+    }
+
+    Ok(diff)
+}
+
+// Builds a graph from a file written by the json export format
+pub fn load_json(filename: &str) -> Result<PackageGraph> {
+    let doc: JsonDocument = serde_json::from_reader(BufReader::new(File::open(filename)?))?;
+
+    let mut nodes = Vec::with_capacity(doc.nodes.len());
+    for node in doc.nodes {
+        check_short_name(&node.name)?;
+        let ident = match node.ident {
+            Some(ref ident) => Some(PackageIdent::from_str(ident)?),
+            None => None,
+        };
+        nodes.push((node.name, ident));
+    }
+
+    let mut edges = Vec::with_capacity(doc.edges.len());
+    for edge in doc.edges {
+        check_short_name(&edge.from)?;
+        check_short_name(&edge.to)?;
+        edges.push((edge.from, edge.to));
+    }
+
+    let mut graph = PackageGraph::new();
+    graph.build_from_edges(nodes.into_iter(), edges.into_iter());
+
+    Ok(graph)
+}
+
+pub fn check_short_name(name: &str) -> Result<()> {
+    let parts: Vec<&str> = name.split('/').collect();
+    if parts.len() == 2 && parts.iter().all(|p| !p.is_empty()) {
+        Ok(())
+    } else {
+        Err(Error::InvalidGraphFile(format!("'{}' is not in origin/name format", name)))
+    }
+}
+
+// Writes the latest packages of the origin (or all packages, if the origin is
+// empty) in build order, one ident per line. Packages trapped in cycles are
+// written in a trailing section of commented blocks, one per cycle. Returns
+// the number of ordered packages and of cycle-trapped packages.
+pub fn export_manifest(graph: &PackageGraph,
+                       filename: &str,
+                       origin: &str,
+                       compress: bool)
+                       -> Result<(usize, usize, ByteCounts)> {
+    let (ordered, cycles) = graph.toposort(origin);
+    let mut writer = create_output(filename, compress)?;
+
+    for ident in &ordered {
+        writeln!(writer, "{}", ident)?;
+    }
+
+    if !cycles.is_empty() {
+        writeln!(writer, "\n# The following packages are part of dependency cycles")?;
+    }
+
+    let mut trapped = 0;
+    for (i, cycle) in cycles.iter().enumerate() {
+        writeln!(writer, "# cycle {} ({} packages)", i + 1, cycle.len())?;
+        for ident in cycle {
+            writeln!(writer, "{}", ident)?;
+        }
+        trapped += cycle.len();
+    }
+
+    let (_, bytes) = writer.finish()?;
+    Ok((ordered.len(), trapped, bytes))
+}
+
+// Writes the latest idents of the reverse dependencies of the package that
+// match the filter, one per line. Refuses to overwrite an existing file unless
+// forced. Returns None, without creating the file, if the package is not in
+// the graph.
+pub fn export_rdeps(graph: &PackageGraph,
+                    name: &str,
+                    filename: &str,
+                    filter: &str,
+                    force: bool,
+                    compress: bool)
+                    -> Result<Option<(usize, ByteCounts)>> {
+    let none = HashSet::new();
+    let walk = uncancelled(|cancel| graph.rdeps_walk(name, &none, EdgeFilter::All, 0, cancel));
+    let walk = match walk {
+        Some(walk) => walk,
+        None => return Ok(None),
+    };
+
+    if !force && Path::new(filename).exists() {
+        return Err(Error::FileExists(filename.to_string()));
+    }
+
+    let mut writer = create_output(filename, compress)?;
+    let mut count = 0;
+
+    for rdep in walk.iter().filter(|rdep| matches_filter(rdep.name, filter)) {
+        writeln!(writer, "{}", rdep.ident)?;
+        count += 1;
+    }
+
+    let (_, bytes) = writer.finish()?;
+    Ok(Some((count, bytes)))
+}
+
+// Writes each dependency cycle as a block with its size, the latest idents of
+// its members, and the dependencies among the members, as 'package ->
+// dependency' lines. Returns the number of cycles and of packages in them.
+pub fn export_cycles(graph: &PackageGraph,
+                     filename: &str,
+                     compress: bool)
+                     -> Result<(usize, usize, ByteCounts)> {
+    let cycles = graph.cycles();
+    let mut writer = create_output(filename, compress)?;
+    let mut trapped = 0;
+
+    for (i, cycle) in cycles.iter().enumerate() {
+        if i > 0 {
+            writeln!(writer)?;
+        }
+        writeln!(writer, "# cycle {} ({} packages)", i + 1, cycle.members.len())?;
+        for name in &cycle.members {
+            writeln!(writer, "{}", graph.resolve(name).unwrap_or_else(|| name.clone()))?;
+        }
+        for (package, dep) in &cycle.edges {
+            writeln!(writer, "  {} -> {}", package, dep)?;
+        }
+        trapped += cycle.members.len();
+    }
+
+    let (_, bytes) = writer.finish()?;
+    Ok((cycles.len(), trapped, bytes))
+}
+
+fn write_dot<W: Write>(graph: &PackageGraph,
+                       writer: &mut W,
+                       filter: &str)
+                       -> Result<(usize, usize)> {
+    let nodes: Vec<String> = graph.node_names()
+                                  .into_iter()
+                                  .filter(|name| matches_filter(name, filter))
+                                  .collect();
+
+    write_dot_graph(writer, &nodes, graph.edges().into_iter(), None)
+}
+
+// Writes a DOT digraph with the nodes clustered by origin. The nodes must be
+// sorted so that each origin is contiguous. Edges point from a package to its
+// dependency; edges referring to nodes not in the list are skipped. The root
+// node, if any, is highlighted.
+fn write_dot_graph<W, I>(writer: &mut W,
+                         nodes: &[String],
+                         edges: I,
+                         root: Option<&str>)
+                         -> Result<(usize, usize)>
+    where W: Write,
+          I: Iterator<Item = (String, String)>
+{
+    writeln!(writer, "digraph packages {{")?;
+
+    let mut origin = None;
+    for name in nodes {
+        let node_origin = origin_of(name);
+        if origin != Some(node_origin) {
+            if origin.is_some() {
+                writeln!(writer, "  }}")?;
+            }
+            writeln!(writer, "  subgraph {} {{", dot_id(&format!("cluster_{}", node_origin)))?;
+            writeln!(writer, "    label = {};", dot_id(node_origin))?;
+            origin = Some(node_origin);
+        }
+
+        if root == Some(name.as_str()) {
+            writeln!(writer,
+                     "    {} [label = {}, style = filled, fillcolor = yellow];",
+                     dot_id(name),
+                     dot_id(name))?;
+        } else {
+            writeln!(writer, "    {} [label = {}];", dot_id(name), dot_id(name))?;
+        }
+    }
+
+    if origin.is_some() {
+        writeln!(writer, "  }}")?;
+    }
+
+    let mut edge_count = 0;
+    for (from, to) in edges {
+        if nodes.binary_search(&from).is_ok() && nodes.binary_search(&to).is_ok() {
+            writeln!(writer, "  {} -> {};", dot_id(&from), dot_id(&to))?;
+            edge_count += 1;
+        }
+    }
+
+    writeln!(writer, "}}")?;
+
+    Ok((nodes.len(), edge_count))
+}
+
+// Quotes an identifier for DOT, escaping the characters DOT treats specially
+// inside quoted strings
+fn dot_id(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn origin_of(name: &str) -> &str { name.split('/').next().unwrap_or_default() }
+
+// Returns the 'origin/name' prefix of the ident
+fn short_name_of(ident: &str) -> &str {
+    match ident.match_indices('/').nth(1) {
+        Some((i, _)) => &ident[..i],
+        None => ident,
+    }
+}
+
+// Quotes a CSV field if it contains a delimiter, quote or line break
+fn csv_field(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::originsrv;
+    use flate2::read::GzDecoder;
+    use protobuf::RepeatedField;
+    use std::io::Read;
+    use xml::reader::{EventReader,
+                      XmlEvent};
+
+    fn make_package(ident: &str, deps: &[&str]) -> originsrv::OriginPackage {
+        let mut package = originsrv::OriginPackage::new();
+        package.set_ident(originsrv::OriginPackageIdent::from_str(ident).unwrap());
+        let mut package_deps = RepeatedField::new();
+        for dep in deps {
+            package_deps.push(originsrv::OriginPackageIdent::from_str(dep).unwrap());
+        }
+        package.set_deps(package_deps);
+        package
+    }
+
+    fn parse_graphml(graph: &PackageGraph, filter: &str) -> (Vec<String>, Vec<(String, String)>) {
+        let mut buf = Vec::new();
+        write_graphml(graph, &mut buf, filter).unwrap();
+
+        let mut nodes = Vec::new();
+        let mut edges = Vec::new();
+        for event in EventReader::new(buf.as_slice()) {
+            if let XmlEvent::StartElement { name, attributes, .. } = event.unwrap() {
+                let attr = |key: &str| {
+                    attributes.iter()
+                              .find(|a| a.name.local_name == key)
+                              .map(|a| a.value.clone())
+                              .unwrap()
+                };
+                match name.local_name.as_str() {
+                    "node" => nodes.push(attr("id")),
+                    "edge" => edges.push((attr("source"), attr("target"))),
+                    _ => (),
+                }
+            }
+        }
+
+        (nodes, edges)
+    }
+
+    #[test]
+    fn output_path_applies_the_export_defaults() {
+        let dir = env::temp_dir().join(format!("bldr-graph-exports-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut cfg = ExportCfg { default_dir: Some(dir.to_string_lossy().to_string()),
+                                  ..Default::default() };
+
+        match output_path(&cfg, "graph.json", false) {
+            Err(Error::MissingExportDir(_)) => (),
+            other => panic!("expected a missing export dir, got {:?}", other),
+        }
+        cfg.create_dirs = true;
+        let path = output_path(&cfg, "graph.json", false).unwrap();
+        assert_eq!(path, dir.join("graph.json").to_string_lossy());
+        assert!(dir.is_dir());
+
+        // Paths with a directory are left as they are, made absolute
+        let relative = output_path(&cfg, "./graph.json", false).unwrap();
+        assert_eq!(relative,
+                   env::current_dir().unwrap().join("./graph.json").to_string_lossy());
+
+        fs::write(&path, "old").unwrap();
+        cfg.overwrite = OverwritePolicy::Replace;
+        assert_eq!(output_path(&cfg, "graph.json", false).unwrap(), path);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+
+        cfg.overwrite = OverwritePolicy::Refuse;
+        match output_path(&cfg, "graph.json", false) {
+            Err(Error::FileExists(_)) => (),
+            other => panic!("expected an existing file, got {:?}", other),
+        }
+        assert_eq!(output_path(&cfg, "graph.json", true).unwrap(), path);
+
+        cfg.overwrite = OverwritePolicy::Backup;
+        assert_eq!(output_path(&cfg, "graph.json", false).unwrap(), path);
+        assert!(!Path::new(&path).exists());
+        assert_eq!(fs::read_to_string(dir.join("graph.json.bak")).unwrap(), "old");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn output_path_keeps_the_name_and_checks_its_directory() {
+        let dir = env::temp_dir().join(format!("bldr-graph-paths-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut cfg = ExportCfg::default();
+
+        let mixed = dir.join("Exports").join("Graph-Core.JSON");
+        let mixed = mixed.to_string_lossy();
+        match output_path(&cfg, &mixed, false) {
+            Err(Error::MissingExportDir(missing)) => {
+                assert_eq!(missing, dir.join("Exports").to_string_lossy())
+            }
+            other => panic!("expected a missing export dir, got {:?}", other),
+        }
+        assert!(!dir.exists());
+
+        cfg.create_dirs = true;
+        assert_eq!(output_path(&cfg, &mixed, false).unwrap(), mixed);
+        assert!(dir.join("Exports").is_dir());
+        drop(create_output(&mixed, false).unwrap());
+        assert!(dir.join("Exports").join("Graph-Core.JSON").is_file());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unwritable_output_is_an_error() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = env::temp_dir().join(format!("bldr-graph-readonly-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o555)).unwrap();
+
+        // Permissions are not enforced for root, which the check is then skipped for
+        let target = dir.join("graph.json");
+        if File::create(&target).is_err() {
+            match create_output(&target.to_string_lossy(), false) {
+                Err(Error::IO(ref e)) => assert_eq!(e.kind(), io::ErrorKind::PermissionDenied),
+                other => panic!("expected a permission error, got {:?}", other.map(|_| ())),
+            }
+        }
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn filters_leave_out_names_sharing_a_prefix() {
+        let mut graph = PackageGraph::new();
+        let packages = vec![make_package("core/gcc/9/1", &[]),
+                            make_package("core/gcc-libs/9/1", &["core/gcc/9/1"]),
+                            make_package("corebanking/ledger/1/1", &["core/gcc-libs/9/1"]),];
+        graph.build(packages.into_iter(), true);
+
+        let text = |filter| {
+            let mut out = Vec::new();
+            write_text(&graph, &mut out, filter).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(text("core/gcc"), "core/gcc/9/1\n");
+        assert_eq!(text("core"), "core/gcc/9/1\ncore/gcc-libs/9/1\n");
+        assert_eq!(text("corebanking"), "corebanking/ledger/1/1\n");
+
+        let (nodes, edges) = parse_graphml(&graph, "core");
+        assert_eq!(nodes, vec!["core/gcc", "core/gcc-libs"]);
+        assert_eq!(edges, vec![("core/gcc-libs".to_string(), "core/gcc".to_string())]);
+    }
+
+    #[test]
+    fn exports_list_the_newest_version() {
+        // The newer ident of each package, then the older one
+        let cases = [("core/foo/1.10.0/20190101000000", "core/foo/1.9.2/20190101000000"),
+                     ("core/foo/2.0/20190101000000", "core/foo/2.0a/20190101000000"),
+                     ("core/foo/1.0/20190115003728", "core/foo/1.0/20190114003728"),
+                     ("core/foo/10/20190101000000", "core/foo/9/20190101000000")];
+        for &(newer, older) in &cases {
+            for idents in &[[newer, older], [older, newer]] {
+                let mut graph = PackageGraph::new();
+                graph.build(idents.iter().map(|ident| make_package(ident, &[])), true);
+
+                let mut text = Vec::new();
+                write_text(&graph, &mut text, "").unwrap();
+                assert_eq!(String::from_utf8(text).unwrap(), format!("{}\n", newer));
+
+                let mut tsv = Vec::new();
+                write_tsv(&graph, &mut tsv, "").unwrap();
+                let parts: Vec<&str> = newer.split('/').collect();
+                assert_eq!(String::from_utf8(tsv).unwrap(),
+                           format!("origin\tname\tversion\trelease\ttarget\n{}\t\n",
+                                   parts.join("\t")));
+            }
+        }
+    }
+
+    #[test]
+    fn graphml_is_well_formed() {
+        let mut graph = PackageGraph::new();
+        let packages = vec![make_package("foo/a&b/1/2", &["foo/<c>/1/2"]),
+                            make_package("foo/<c>/1/2", &["bar/\"d'/1/2"]),
+                            make_package("bar/\"d'/1/2", &[]),];
+        graph.build(packages.into_iter(), true);
+
+        let (nodes, edges) = parse_graphml(&graph, "");
+        assert_eq!(nodes, vec!["bar/\"d'", "foo/<c>", "foo/a&b"]);
+        assert_eq!(edges,
+                   vec![("foo/<c>".to_string(), "bar/\"d'".to_string()),
+                        ("foo/a&b".to_string(), "foo/<c>".to_string())]);
+
+        let (nodes, edges) = parse_graphml(&graph, "foo");
+        assert_eq!(nodes, vec!["foo/<c>", "foo/a&b"]);
+        assert_eq!(edges, vec![("foo/a&b".to_string(), "foo/<c>".to_string())]);
+    }
+
+    #[test]
+    fn output_counts_plain_and_compressed_bytes() {
+        let text = "core/zlib/1.2.11/20190115003728\n".repeat(100);
+
+        let mut output = Output::new(Vec::new(), false);
+        output.write_all(text.as_bytes()).unwrap();
+        let (buf, bytes) = output.finish().unwrap();
+        assert_eq!(buf, text.as_bytes());
+        assert_eq!(bytes,
+                   ByteCounts { uncompressed: text.len() as u64,
+                                compressed:   None, });
+
+        let mut output = Output::new(Vec::new(), true);
+        output.write_all(text.as_bytes()).unwrap();
+        let (buf, bytes) = output.finish().unwrap();
+        assert_eq!(bytes,
+                   ByteCounts { uncompressed: text.len() as u64,
+                                compressed:   Some(buf.len() as u64), });
+
+        let mut decoded = String::new();
+        GzDecoder::new(buf.as_slice()).read_to_string(&mut decoded)
+                                      .unwrap();
+        assert_eq!(decoded, text);
+    }
+
+    #[test]
+    fn diff_latest_compares_against_previous_export() {
+        let mut graph = PackageGraph::new();
+        let packages = vec![make_package("foo/bar/1/3", &["foo/baz/1/2"]),
+                            make_package("foo/baz/1/2", &[]),
+                            make_package("foo/new/1/2", &[]),
+                            make_package("other/pkg/1/2", &[]),];
+        graph.build(packages.into_iter(), true);
+
+        let old = "# comment\nfoo/bar/1/2\nfoo/baz/1/2\n\nfoo/gone/1/2\nfoo/nope\nother/pkg/1/1\n";
+        let diff = diff_latest(&graph, old.as_bytes(), "foo").unwrap();
+
+        assert_eq!(diff.added, vec!["foo/new/1/2"]);
+        assert_eq!(diff.removed, vec!["foo/gone/1/2"]);
+        assert_eq!(diff.changed,
+                   vec![("foo/bar/1/2".to_string(), "foo/bar/1/3".to_string())]);
+        assert_eq!(diff.malformed, vec![(6, "foo/nope".to_string())]);
+    }
+
+    #[test]
+    fn plans_are_grouped_by_origin_in_build_order() {
+        let mut graph = PackageGraph::new();
+        let packages = vec![make_package("foo/app/1/2", &["foo/lib/1/2", "core/zlib/1/2"]),
+                            make_package("foo/lib/1/2", &["core/zlib/1/2"]),
+                            make_package("core/zlib/1/2", &["core/glibc/1/2"]),
+                            make_package("core/glibc/1/2", &[]),];
+        graph.build(packages.into_iter(), true);
+
+        let mut buf = Vec::new();
+        assert_eq!(write_plans(&graph, &mut buf, "", false).unwrap(), (4, 0));
+        assert_eq!(String::from_utf8(buf).unwrap(),
+                   "[core]\nglibc\nzlib\n\n[foo]\nlib\napp\n");
+
+        let mut buf = Vec::new();
+        assert_eq!(write_plans(&graph, &mut buf, "foo", true).unwrap(), (2, 2));
+        assert_eq!(String::from_utf8(buf).unwrap(),
+                   "[foo]\nfoo/lib/1/2\nfoo/app/1/2\n\n# Dependencies on packages that are not \
+                    in this file\n# foo/lib -> core/zlib\n# foo/app -> core/zlib\n");
+    }
+}
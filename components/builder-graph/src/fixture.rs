@@ -0,0 +1,476 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// A package store backed by a JSON file of package records, for exercising the
+// graph and the commands without a builder database. The file is an object
+// with a "packages" list, and an optional "jobs" list of job records:
+//
+//   { "packages": [ { "ident": "core/zlib/1.2.11/20190115003728",
+//                     "deps": ["core/glibc/2.29/20190115012144"],
+//                     "channels": ["core/stable"] } ] }
+//
+// The packages get ids in file order. A package's "target" defaults to
+// x86_64-linux and its "visibility" to public.
+
+use std::{cmp::Ordering,
+          collections::{BTreeMap,
+                        BTreeSet,
+                        HashMap},
+          fs,
+          str::FromStr};
+
+use protobuf::RepeatedField;
+use serde_json;
+
+use crate::{bldr_core::version,
+            data_store::{ident_parts,
+                         GraphPackage,
+                         GraphScope,
+                         JobRecord,
+                         OriginPackageCounts,
+                         PackageStore,
+                         GRAPH_PACKAGES_PAGE_SIZE},
+            error::{Error,
+                    Result},
+            hab_core::package::PackageIdent,
+            protocol::originsrv};
+
+// The target of the packages that do not give one
+const DEFAULT_TARGET: &str = "x86_64-linux";
+
+#[derive(Debug, Deserialize)]
+struct FixtureFile {
+    packages: Vec<FixturePackage>,
+    #[serde(default)]
+    jobs:     Vec<JobRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FixturePackage {
+    ident:       String,
+    #[serde(default)]
+    target:      Option<String>,
+    #[serde(default)]
+    deps:        Vec<String>,
+    #[serde(default)]
+    tdeps:       Vec<String>,
+    #[serde(default)]
+    build_deps:  Vec<String>,
+    #[serde(default)]
+    build_tdeps: Vec<String>,
+    #[serde(default)]
+    visibility:  Option<String>,
+    #[serde(default)]
+    created_at:  Option<i64>,
+    /// The 'origin/channel' channels the package is in
+    #[serde(default)]
+    channels:    Vec<String>,
+}
+
+// A fixture package, converted to the record served by the store
+#[derive(Debug)]
+struct Record {
+    package:    originsrv::OriginPackage,
+    created_at: Option<i64>,
+    channels:   Vec<String>,
+}
+
+impl Record {
+    fn origin(&self) -> &str { self.package.get_ident().get_origin() }
+
+    fn name(&self) -> &str { self.package.get_ident().get_name() }
+
+    fn ident(&self) -> String { self.package.get_ident().to_string() }
+
+    fn in_scope(&self, scope: &GraphScope, origins: &[String]) -> bool {
+        scope.target
+             .as_ref()
+             .map_or(true, |target| self.package.get_target() == target)
+        && (origins.is_empty() || origins.iter().any(|o| o == self.origin()))
+        && scope.channel.as_ref().map_or(true, |&(ref origin, ref channel)| {
+                                     self.channels.contains(&format!("{}/{}", origin, channel))
+                                 })
+    }
+
+    fn to_graph_package(&self) -> GraphPackage {
+        GraphPackage { package:    self.package.clone(),
+                       created_at: self.created_at, }
+    }
+}
+
+/// Serves the package and job records of a fixture file.
+#[derive(Debug)]
+pub struct FixtureStore {
+    records: Vec<Record>,
+    jobs:    Vec<JobRecord>,
+    origins: Vec<String>,
+}
+
+impl FixtureStore {
+    /// Reads the fixture file. Only the packages of the origins are served, all if it is empty.
+    pub fn from_file(path: &str, origins: &[String]) -> Result<Self> {
+        FixtureStore::from_json(&fs::read_to_string(path)?, origins)
+    }
+
+    /// Parses the records of a fixture, as read from a fixture file.
+    pub fn from_json(json: &str, origins: &[String]) -> Result<Self> {
+        let file: FixtureFile = serde_json::from_str(json)?;
+        let mut records = Vec::new();
+        for (index, fixture) in file.packages.into_iter().enumerate() {
+            records.push(to_record(index as u64 + 1, fixture)?);
+        }
+        Ok(FixtureStore { records,
+                          jobs: file.jobs,
+                          origins: origins.to_vec() })
+    }
+
+    // The newest of the records, by version and release
+    fn newest<'a, I>(records: I) -> Option<&'a Record>
+        where I: IntoIterator<Item = &'a Record>
+    {
+        records.into_iter().fold(None, |newest, record| {
+                               match newest {
+                                   Some(newest) if compare_idents(newest, record)
+                                                   != Ordering::Less =>
+                                   {
+                                       Some(newest)
+                                   }
+                                   _ => Some(record),
+                               }
+                           })
+    }
+}
+
+fn to_record(id: u64, fixture: FixturePackage) -> Result<Record> {
+    let ident = originsrv::OriginPackageIdent::from_str(&fixture.ident).map_err(|_| {
+                    Error::InvalidGraphFile(format!("invalid package ident {}", fixture.ident))
+                })?;
+    if ident_parts(&fixture.ident).map_or(true, |parts| parts.len() != 4) {
+        return Err(Error::InvalidGraphFile(format!("{} is not a fully qualified ident",
+                                                   fixture.ident)));
+    }
+
+    let mut package = originsrv::OriginPackage::new();
+    package.set_id(id);
+    package.set_origin(ident.get_origin().to_string());
+    package.set_target(fixture.target
+                              .unwrap_or_else(|| DEFAULT_TARGET.to_string()));
+    package.set_deps(to_idents(&fixture.deps)?);
+    package.set_tdeps(to_idents(&fixture.tdeps)?);
+    package.set_build_deps(to_idents(&fixture.build_deps)?);
+    package.set_build_tdeps(to_idents(&fixture.build_tdeps)?);
+    package.set_visibility(match fixture.visibility.as_ref().map(String::as_str) {
+                               Some("private") => originsrv::OriginPackageVisibility::Private,
+                               Some("hidden") => originsrv::OriginPackageVisibility::Hidden,
+                               _ => originsrv::OriginPackageVisibility::Public,
+                           });
+    package.set_ident(ident);
+
+    Ok(Record { package,
+                created_at: fixture.created_at,
+                channels: fixture.channels })
+}
+
+fn to_idents(idents: &[String]) -> Result<RepeatedField<originsrv::OriginPackageIdent>> {
+    idents.iter()
+          .map(|ident| {
+              originsrv::OriginPackageIdent::from_str(ident).map_err(|_| {
+                  Error::InvalidGraphFile(format!("invalid dependency ident {}", ident))
+              })
+          })
+          .collect()
+}
+
+// Compares the versions and releases of two records of the same package
+fn compare_idents(a: &Record, b: &Record) -> Ordering {
+    match (PackageIdent::from_str(&a.ident()), PackageIdent::from_str(&b.ident())) {
+        (Ok(a), Ok(b)) => version::compare_idents(&a, &b),
+        _ => Ordering::Equal,
+    }
+}
+
+impl PackageStore for FixtureStore {
+    fn origins(&self) -> &[String] { &self.origins }
+
+    fn get_job_graph_packages_page(&self,
+                                   _what: &str,
+                                   scope: &GraphScope,
+                                   origins: &[String],
+                                   after_id: i64)
+                                   -> Result<Vec<GraphPackage>> {
+        let in_scope = self.records
+                           .iter()
+                           .filter(|record| record.in_scope(scope, origins));
+
+        let mut records: Vec<&Record> = if scope.latest_only {
+            // Like the database, choose the latest packages among the visible ones
+            let mut by_name: HashMap<(&str, &str, &str), Vec<&Record>> = HashMap::new();
+            for record in in_scope.filter(|record| scope.visibility.includes(&record.package)) {
+                by_name.entry((record.origin(), record.name(), record.package.get_target()))
+                       .or_default()
+                       .push(record);
+            }
+            by_name.values()
+                   .filter_map(|records| FixtureStore::newest(records.iter().cloned()))
+                   .collect()
+        } else {
+            in_scope.collect()
+        };
+
+        records.retain(|record| record.package.get_id() as i64 > after_id);
+        records.sort_by_key(|record| record.package.get_id());
+        Ok(records.into_iter()
+                  .take(GRAPH_PACKAGES_PAGE_SIZE as usize)
+                  .map(Record::to_graph_package)
+                  .collect())
+    }
+
+    fn get_graph_package_counts(&self, scope: &GraphScope) -> Result<Vec<OriginPackageCounts>> {
+        let mut counts: BTreeMap<&str, (i64, BTreeSet<&str>)> = BTreeMap::new();
+        for record in self.records
+                          .iter()
+                          .filter(|record| record.in_scope(scope, &self.origins))
+                          .filter(|record| scope.visibility.includes(&record.package))
+        {
+            let entry = counts.entry(record.origin()).or_default();
+            entry.0 += 1;
+            entry.1.insert(record.name());
+        }
+
+        Ok(counts.into_iter()
+                 .map(|(origin, (packages, names))| {
+                     OriginPackageCounts { origin: origin.to_string(),
+                                           packages,
+                                           names: names.len() as i64 }
+                 })
+                 .collect())
+    }
+
+    fn get_job_graph_package(&self, ident: &str) -> Result<originsrv::OriginPackage> {
+        self.records
+            .iter()
+            .find(|record| record.ident() == ident)
+            .map(|record| record.package.clone())
+            .ok_or(Error::UnknownJobGraphPackage)
+    }
+
+    fn find_job_graph_package(&self, ident: &str) -> Result<originsrv::OriginPackage> {
+        let parts = match ident_parts(ident) {
+            Some(parts) => parts,
+            None => return Err(Error::UnknownJobGraphPackage),
+        };
+
+        if parts.len() == 4 {
+            match self.get_job_graph_package(ident) {
+                Err(Error::UnknownJobGraphPackage) => (),
+                result => return result,
+            }
+        }
+
+        let matches = self.records.iter().filter(|record| {
+                                             let ident = record.package.get_ident();
+                                             ident.get_origin().eq_ignore_ascii_case(parts[0])
+                                             && ident.get_name().eq_ignore_ascii_case(parts[1])
+                                             && parts.get(2).map_or(true, |version| {
+                                                 ident.get_version()
+                                                      .eq_ignore_ascii_case(version)
+                                             })
+                                             && parts.get(3)
+                                                     .map_or(true, |release| {
+                                                         ident.get_release() == *release
+                                                     })
+                                         });
+
+        FixtureStore::newest(matches).map(|record| record.package.clone())
+                                     .ok_or(Error::UnknownJobGraphPackage)
+    }
+
+    fn get_job_graph_packages_by_idents(&self,
+                                        idents: &[String])
+                                        -> Result<Vec<originsrv::OriginPackage>> {
+        Ok(self.records
+               .iter()
+               .filter(|record| idents.contains(&record.ident()))
+               .map(|record| record.package.clone())
+               .collect())
+    }
+
+    fn get_channel_packages(&self, channel: &str) -> Result<Vec<String>> {
+        Ok(self.records
+               .iter()
+               .filter(|record| {
+                   record.channels
+                         .iter()
+                         .any(|c| c.splitn(2, '/').nth(1) == Some(channel))
+               })
+               .map(Record::ident)
+               .collect())
+    }
+
+    fn get_job_for_package(&self, ident: &str) -> Result<Option<JobRecord>> {
+        Ok(self.jobs
+               .iter()
+               .filter(|job| job.package_ident.as_ref().map(String::as_str) == Some(ident))
+               .max_by_key(|job| job.id)
+               .cloned())
+    }
+
+    fn get_latest_job_for_project(&self, name: &str) -> Result<Option<JobRecord>> {
+        let prefix = format!("{}/", name);
+        Ok(self.jobs
+               .iter()
+               .filter(|job| {
+                   job.package_ident
+                      .as_ref()
+                      .map_or(false, |ident| ident.starts_with(&prefix))
+               })
+               .max_by_key(|job| job.id)
+               .cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{bldr_core::package_graph::PackageGraph,
+                data_store::Visibility};
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/graph-packages.json");
+
+    fn fixture_store() -> FixtureStore { FixtureStore::from_json(FIXTURE, &[]).unwrap() }
+
+    fn graph_of(store: &dyn PackageStore, scope: &GraphScope) -> PackageGraph {
+        let mut graph = PackageGraph::new();
+        for next in store.get_job_graph_packages(scope) {
+            graph.extend(&next.unwrap().package, false);
+        }
+        graph
+    }
+
+    #[test]
+    fn graph_from_fixture_has_expected_stats() {
+        let graph = graph_of(&fixture_store(), &GraphScope::default());
+        let stats = graph.stats();
+
+        assert_eq!(stats.node_count, 27);
+        // The edge closing the util-linux -> systemd -> util-linux cycle is rolled back
+        assert_eq!(stats.edge_count, 61);
+        assert!(!stats.is_cyclic);
+        assert_eq!(stats.origins["core"], 24);
+        assert_eq!(stats.origins["acme"], 3);
+    }
+
+    #[test]
+    fn graph_from_fixture_resolves_latest_packages() {
+        let graph = graph_of(&fixture_store(), &GraphScope::default());
+
+        assert_eq!(graph.resolve("core/glibc"),
+                   Some("core/glibc/2.29/20190115012144".to_string()));
+        assert_eq!(graph.resolve("core/openssl"),
+                   Some("core/openssl/1.0.2t/20190910124106".to_string()));
+        assert_eq!(graph.resolve("acme/app"),
+                   Some("acme/app/1.1.0/20200201000000".to_string()));
+        assert_eq!(graph.resolve("core/gcc"), None);
+    }
+
+    #[test]
+    fn graph_from_fixture_follows_cross_origin_rdeps() {
+        let graph = graph_of(&fixture_store(), &GraphScope::default());
+
+        let mut rdeps: Vec<String> = graph.rdeps("core/openssl")
+                                          .unwrap()
+                                          .into_iter()
+                                          .map(|(name, _)| name)
+                                          .collect();
+        rdeps.sort();
+        assert_eq!(rdeps,
+                   vec!["acme/app", "acme/lib", "acme/tool", "core/curl", "core/git",
+                        "core/libarchive"]);
+    }
+
+    #[test]
+    fn fixture_store_applies_the_scope() {
+        let store = fixture_store();
+        let store: &dyn PackageStore = &store;
+
+        let scope = GraphScope { visibility: Visibility::Public,
+                                 ..GraphScope::default() };
+        let mut packages = store.get_job_graph_packages(&scope);
+        assert_eq!(packages.by_ref().count(), 29);
+        assert_eq!(packages.excluded(),
+                   &["acme/lib/0.3.0/20200105000000".to_string(),
+                     "acme/tool/0.1.0/20200110000000".to_string()]);
+
+        let scope = GraphScope { latest_only: true,
+                                 ..GraphScope::default() };
+        assert_eq!(store.get_job_graph_packages(&scope).count(), 27);
+
+        let scope = GraphScope { channel: Some(("core".to_string(), "stable".to_string())),
+                                 ..GraphScope::default() };
+        assert_eq!(store.get_job_graph_packages(&scope).count(), 4);
+
+        let acme = store.get_origin_graph_packages(&GraphScope::default(), "acme");
+        assert_eq!(acme.count(), 4);
+    }
+
+    #[test]
+    fn fixture_store_chooses_the_newest_by_version_order() {
+        // The newer ident of each package, then the older one
+        let cases = [("core/foo/2.0/20190101000000", "core/foo/2.0a/20190101000000"),
+                     ("core/foo/2.0/20190101000000", "core/foo/2.0-rc1/20190101000000"),
+                     ("core/foo/1.10.0/20190101000000", "core/foo/1.9.2/20190101000000"),
+                     ("core/foo/1.0/10", "core/foo/1.0/9"),
+                     ("core/foo/1.0/20190115003728", "core/foo/1.0/2019011500372")];
+        let scope = GraphScope { latest_only: true,
+                                 ..GraphScope::default() };
+        for &(newer, older) in &cases {
+            for idents in &[[newer, older], [older, newer]] {
+                let json = format!(r#"{{"packages": [{{"ident": "{}"}}, {{"ident": "{}"}}]}}"#,
+                                   idents[0], idents[1]);
+                let store = FixtureStore::from_json(&json, &[]).unwrap();
+                let store: &dyn PackageStore = &store;
+
+                let latest: Vec<String> =
+                    store.get_job_graph_packages(&scope)
+                         .map(|next| next.unwrap().package.get_ident().to_string())
+                         .collect();
+                assert_eq!(latest, vec![newer.to_string()]);
+                assert_eq!(store.find_job_graph_package("core/foo")
+                                .unwrap()
+                                .get_ident()
+                                .to_string(),
+                           newer);
+            }
+        }
+    }
+
+    #[test]
+    fn fixture_store_finds_partial_idents() {
+        let store = fixture_store();
+
+        let package = store.find_job_graph_package("CORE/OpenSSL").unwrap();
+        assert_eq!(package.get_ident().to_string(),
+                   "core/openssl/1.0.2t/20190910124106");
+        let package = store.find_job_graph_package("core/openssl/1.0.2r").unwrap();
+        assert_eq!(package.get_ident().to_string(),
+                   "core/openssl/1.0.2r/20190305210149");
+        assert!(store.get_job_graph_package("core/openssl/1.0.2r/20190305210149")
+                     .is_ok());
+        assert!(store.find_job_graph_package("core/gcc").is_err());
+
+        let job = store.get_latest_job_for_project("core/curl").unwrap().unwrap();
+        assert_eq!(job.id, 1001);
+    }
+}
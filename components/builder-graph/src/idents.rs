@@ -0,0 +1,224 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The idents the commands are given or read from package records: an
+// origin and a name, then optionally a version and a release, none of them
+// empty. A record can still hold a malformed one, such as a dependency with
+// no name, which the commands skip, going on with the rest. The session keeps
+// a tally of those, listed by 'malformed', to help find and fix the records.
+// A name without a version has to be in the graph, and when it is not the
+// commands say so, with the names closest to it, rather than looking it up
+// in the database, which has no use for a bare name.
+
+use std::collections::{BTreeMap,
+                       BTreeSet};
+
+use crate::{bldr_core::package_graph::{PackageGraph,
+                                       SearchMode},
+            error::{Error,
+                    Result}};
+
+// The 'origin/name' of the ident
+pub fn short_name(ident: &str) -> Result<String> {
+    let parts: Vec<&str> = ident.split('/').collect();
+    if parts.len() < 2 || parts.len() > 4 || parts.iter().any(|part| part.is_empty()) {
+        return Err(Error::MalformedIdent(ident.to_string()));
+    }
+    Ok(format!("{}/{}", parts[0], parts[1]))
+}
+
+// The most names suggested for a name that is not in the graph
+const MAX_SUGGESTIONS: usize = 5;
+
+// The latest ident of the package given by its 'origin/name', or the ident
+// as given if it has a version, which the database is left to find even
+// when it is not the latest one. A name the graph does not have is an error.
+pub fn resolve_name(graph: &PackageGraph, name: &str) -> Result<String> {
+    if short_name(name)?.len() < name.len() {
+        return Ok(name.to_string());
+    }
+    graph.resolve(name)
+         .ok_or_else(|| Error::NotInGraph(name.to_string(), suggestions(graph, name)))
+}
+
+// The names in the graph closest to the 'origin/name' given, ignoring case:
+// those whose name is within two edits of the name given or contains it, in
+// any origin, closest first
+pub fn suggestions(graph: &PackageGraph, name: &str) -> Vec<String> {
+    let package = |name: &str| name.splitn(2, '/').last().unwrap_or("").to_lowercase();
+    let wanted = package(name);
+
+    let mut close: Vec<(usize, String)> =
+        graph.search_with("*", SearchMode::Glob, true)
+             .into_iter()
+             .filter_map(|candidate| {
+                 let other = package(&candidate);
+                 let distance = edit_distance(&wanted, &other);
+                 if distance <= 2 || other.contains(&wanted) {
+                     Some((distance, candidate))
+                 } else {
+                     None
+                 }
+             })
+             .collect();
+    close.sort();
+    close.into_iter()
+         .take(MAX_SUGGESTIONS)
+         .map(|(_, candidate)| candidate)
+         .collect()
+}
+
+// The number of characters to insert, delete or replace to turn a into b
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let replaced = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// The malformed idents met in the session, each with the idents of the
+// packages whose records referenced it
+#[derive(Debug, Default)]
+pub struct MalformedIdents(BTreeMap<String, BTreeSet<String>>);
+
+impl MalformedIdents {
+    // Reports the ident referenced by the package as skipped, and counts it
+    pub fn report(&mut self, ident: &str, referenced_by: &str) {
+        println!("Skipped malformed ident '{}', referenced by {}", ident, referenced_by);
+        self.0
+            .entry(ident.to_string())
+            .or_default()
+            .insert(referenced_by.to_string());
+    }
+
+    pub fn len(&self) -> usize { self.0.len() }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &BTreeSet<String>)> { self.0.iter() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::originsrv;
+    use std::str::FromStr;
+
+    #[test]
+    fn short_names_need_an_origin_and_a_name() {
+        assert_eq!(short_name("core/zlib").unwrap(), "core/zlib");
+        assert_eq!(short_name("core/zlib/1.2.11").unwrap(), "core/zlib");
+        assert_eq!(short_name("core/zlib/1.2.11/20190115003728").unwrap(), "core/zlib");
+
+        for ident in &["",
+                       "zlib",
+                       "/",
+                       "core/",
+                       "/zlib",
+                       "core/zlib/",
+                       "core/zlib/1.2.11/",
+                       "core//1.2.11",
+                       "core/zlib/1.2.11/20190115003728/x86_64-linux"]
+        {
+            match short_name(ident) {
+                Err(Error::MalformedIdent(ref malformed)) => assert_eq!(malformed, ident),
+                other => panic!("expected '{}' to be malformed, got {:?}", ident, other),
+            }
+        }
+    }
+
+    fn make_graph(idents: &[&str]) -> PackageGraph {
+        let mut graph = PackageGraph::new();
+        let packages = idents.iter().map(|ident| {
+                                        let mut package = originsrv::OriginPackage::new();
+                                        package.set_ident(FromStr::from_str(ident).unwrap());
+                                        package
+                                    });
+        graph.build(packages, true);
+        graph
+    }
+
+    #[test]
+    fn resolve_name_checks_the_ident() {
+        let graph = make_graph(&["core/zlib/1.2.11/20190115003728"]);
+        assert_eq!(resolve_name(&graph, "core/zlib").unwrap(),
+                   "core/zlib/1.2.11/20190115003728");
+        assert_eq!(resolve_name(&graph, "core/zlib/1.2.11").unwrap(), "core/zlib/1.2.11");
+        assert!(resolve_name(&graph, "").is_err());
+        assert!(resolve_name(&graph, "zlib").is_err());
+        assert!(resolve_name(&graph, "core/zlib/").is_err());
+    }
+
+    #[test]
+    fn names_not_in_the_graph_are_errors_but_older_idents_are_not() {
+        let graph = make_graph(&["core/gcc/9.1.0/20190115003728",
+                                 "core/gcc-libs/9.1.0/20190115003728",
+                                 "acme/gcc/1.0.0/20190115003728",
+                                 "core/zlib/1.2.11/20190115003728"]);
+
+        match resolve_name(&graph, "core/gc") {
+            Err(Error::NotInGraph(ref name, ref suggested)) => {
+                assert_eq!(name, "core/gc");
+                assert_eq!(suggested, &["acme/gcc", "core/gcc", "core/gcc-libs"]);
+            }
+            other => panic!("expected core/gc not to be in the graph, got {:?}", other),
+        }
+        match resolve_name(&graph, "core/nothing-like-it") {
+            Err(Error::NotInGraph(_, ref suggested)) => assert!(suggested.is_empty()),
+            other => panic!("expected no suggestions, got {:?}", other),
+        }
+
+        // Idents are left for the database to find, the latest or not
+        assert_eq!(resolve_name(&graph, "core/gcc/8.3.0/20180608091936").unwrap(),
+                   "core/gcc/8.3.0/20180608091936");
+        assert_eq!(resolve_name(&graph, "core/missing/1.0.0").unwrap(), "core/missing/1.0.0");
+    }
+
+    #[test]
+    fn edit_distance_counts_characters() {
+        assert_eq!(edit_distance("zlib", "zlib"), 0);
+        assert_eq!(edit_distance("zlb", "zlib"), 1);
+        assert_eq!(edit_distance("opnessl", "openssl"), 2);
+        assert_eq!(edit_distance("", "gcc"), 3);
+        assert_eq!(edit_distance("gcc", "bash"), 4);
+    }
+
+    #[test]
+    fn malformed_idents_are_tallied_once_per_referencing_package() {
+        let mut malformed = MalformedIdents::default();
+        assert!(malformed.is_empty());
+
+        malformed.report("core/", "acme/app/1/1");
+        malformed.report("core/", "acme/app/1/1");
+        malformed.report("core/", "acme/web/1/1");
+        malformed.report("", "acme/app/1/1");
+
+        assert_eq!(malformed.len(), 2);
+        let tally: Vec<(&str, Vec<&str>)> =
+            malformed.iter()
+                     .map(|(ident, by)| (ident.as_str(), by.iter().map(String::as_str).collect()))
+                     .collect();
+        assert_eq!(tally,
+                   vec![("", vec!["acme/app/1/1"]),
+                        ("core/", vec!["acme/app/1/1", "acme/web/1/1"])]);
+    }
+}
@@ -29,29 +29,110 @@ use habitat_builder_db as db;
 use habitat_builder_protocol as protocol;
 use habitat_core as hab_core;
 
+pub mod aliases;
+pub mod args;
+pub mod bench;
+pub mod cache;
+pub mod check;
+pub mod command_log;
+pub mod compare;
 pub mod config;
+pub mod costs;
 pub mod data_store;
 pub mod error;
+pub mod export;
+pub mod fixture;
+pub mod idents;
+pub mod memstats;
+pub mod prompt;
 
-use std::{collections::HashMap,
+use std::{cmp::{self,
+                Ordering},
+          collections::{BTreeMap,
+                        BTreeSet,
+                        HashMap,
+                        HashSet},
+          env,
           fs::File,
-          io::Write,
-          iter::FromIterator};
+          io::{self,
+               BufRead,
+               BufReader,
+               BufWriter,
+               Write},
+          iter::{self,
+                 FromIterator},
+          mem,
+          ops::Deref,
+          process,
+          str::FromStr,
+          sync::{mpsc::{self,
+                        Receiver,
+                        Sender},
+                 Arc,
+                 Mutex},
+          thread,
+          time::{Duration,
+                 Instant}};
 
+use chrono::{NaiveDateTime,
+             Utc};
 use clap::{App,
-           Arg};
+           Arg,
+           ArgMatches};
 use copperline::Copperline;
 use time::PreciseTime;
 
-use crate::{bldr_core::package_graph::PackageGraph,
-            config::Config,
-            data_store::DataStore,
-            hab_core::config::ConfigFile};
+use crate::{aliases::Aliases,
+            bldr_core::{cancel::{Cancel,
+                                 Cancelled},
+                        package_graph::{self,
+                                        matches_filter,
+                                        AliasApplied,
+                                        AliasError,
+                                        EdgeFilter,
+                                        ExtendDelta,
+                                        MemoryStats,
+                                        NodeMetadata,
+                                        PackageDeps,
+                                        PackageGraph,
+                                        RdepRef,
+                                        Removed,
+                                        SearchMode,
+                                        VersionMatch,
+                                        VersionReq},
+                        version::compare_idents},
+            args::parse_max,
+            command_log::CommandLog,
+            config::{Config,
+                     DataStoreOverrides,
+                     ExportCfg,
+                     LimitsCfg},
+            costs::{CostSummary,
+                    Costs},
+            data_store::{DataStore,
+                         GraphPackage,
+                         GraphScope,
+                         PackageStore,
+                         QueryStats,
+                         Visibility},
+            export::{ExportFormat,
+                     GraphMode},
+            fixture::FixtureStore,
+            idents::{resolve_name,
+                     short_name,
+                     suggestions,
+                     MalformedIdents},
+            hab_core::{config::ConfigFile,
+                       package::{PackageIdent,
+                                 PackageTarget}},
+            memstats::format_bytes,
+            prompt::PromptValues,
+            protocol::originsrv};
 
 const VERSION: &str = include_str!(concat!(env!("OUT_DIR"), "/VERSION"));
 
 fn main() {
-    env_logger::init();
+    init_logger();
 
     let matches =
         App::new("bldr-graph").version(VERSION)
@@ -59,383 +140,4587 @@ fn main() {
                               .arg(Arg::with_name("config").help("Filepath to configuration file")
                                                            .required(false)
                                                            .index(1))
+                              .arg(Arg::with_name("load").help("Load the graph from a json \
+                                                                export instead of the database")
+                                                         .long("load")
+                                                         .value_name("FILE")
+                                                         .takes_value(true)
+                                                         .conflicts_with("cache"))
+                              .arg(Arg::with_name("cache").help("Load the graph from a cache \
+                                                                 file written by save-cache")
+                                                          .long("cache")
+                                                          .value_name("FILE")
+                                                          .takes_value(true))
+                              .arg(Arg::with_name("target").help("Only graph the packages for \
+                                                                  the target")
+                                                           .long("target")
+                                                           .value_name("TARGET")
+                                                           .takes_value(true))
+                              .arg(Arg::with_name("channel").help("Only graph the packages in \
+                                                                   the origin's channel")
+                                                            .long("channel")
+                                                            .value_name("ORIGIN/CHANNEL")
+                                                            .takes_value(true))
+                              .arg(Arg::with_name("visibility").help("Graph only the public \
+                                                                      packages, or all of them")
+                                                               .long("visibility")
+                                                               .possible_values(&["public",
+                                                                                  "all"])
+                                                               .default_value("all"))
+                              .arg(Arg::with_name("compare-db").help("Config file of the \
+                                                                      database compare-env \
+                                                                      compares with")
+                                                               .long("compare-db")
+                                                               .value_name("CONFIG")
+                                                               .takes_value(true))
+                              .arg(Arg::with_name("fixture").help("Read the package records \
+                                                                   from a fixture file instead \
+                                                                   of the database")
+                                                            .long("fixture")
+                                                            .value_name("FILE")
+                                                            .takes_value(true)
+                                                            .conflicts_with("load"))
+                              .arg(Arg::with_name("lazy").help("Start with an empty graph, and \
+                                                                add origins to it with load")
+                                                         .long("lazy")
+                                                         .conflicts_with_all(&["load", "cache"]))
+                              .arg(Arg::with_name("latest-only").help("Only fetch the most \
+                                                                       recent package of each \
+                                                                       name and target")
+                                                                .long("latest-only")
+                                                                .conflicts_with("load"))
+                              .arg(Arg::with_name("db-host").help("Database host, overriding \
+                                                                   the config file")
+                                                            .long("db-host")
+                                                            .value_name("HOST")
+                                                            .takes_value(true))
+                              .arg(Arg::with_name("db-port").help("Database port, overriding \
+                                                                   the config file")
+                                                            .long("db-port")
+                                                            .value_name("PORT")
+                                                            .takes_value(true))
+                              .arg(Arg::with_name("db-name").help("Database name, overriding \
+                                                                   the config file")
+                                                            .long("db-name")
+                                                            .value_name("NAME")
+                                                            .takes_value(true))
+                              .arg(Arg::with_name("db-user").help("Database user, overriding \
+                                                                   the config file")
+                                                            .long("db-user")
+                                                            .value_name("USER")
+                                                            .takes_value(true))
+                              .arg(Arg::with_name("db-password-file").help("File containing \
+                                                                            the database \
+                                                                            password")
+                                                                     .long("db-password-file")
+                                                                     .value_name("FILE")
+                                                                     .takes_value(true))
+                              .arg(Arg::with_name("auto-refresh").help("Rebuild the graph from \
+                                                                        the database in the \
+                                                                        background every SECS \
+                                                                        seconds")
+                                                                 .long("auto-refresh")
+                                                                 .value_name("SECS")
+                                                                 .takes_value(true)
+                                                                 .conflicts_with_all(&["load",
+                                                                                       "lazy"]))
+                              .arg(Arg::with_name("no-rc").help("Skip the startup commands of \
+                                                                 the config file")
+                                                          .long("no-rc"))
+                              .arg(Arg::with_name("feature").help("Enable the feature flag, \
+                                                                   along with those of the \
+                                                                   config file")
+                                                            .long("feature")
+                                                            .value_name("NAME")
+                                                            .takes_value(true)
+                                                            .multiple(true)
+                                                            .number_of_values(1))
+                              .arg(Arg::with_name("list-features").help("Print the feature \
+                                                                         flags and exit")
+                                                                  .long("list-features"))
                               .get_matches();
 
-    let config = match matches.value_of("config") {
-        Some(cfg_path) => Config::from_file(cfg_path).unwrap(),
+    let mut config = match matches.value_of("config") {
+        Some(cfg_path) => Config::load(cfg_path).unwrap_or_else(|e| exit_on(&e)),
         None => Config::default(),
     };
 
-    enable_features(&config);
+    // Flags take precedence over the environment, which takes precedence over the config file.
+    // The password is never given as a flag, so that it does not show in the process list.
+    config.apply_env_overrides(|name| env::var(name).ok());
+    let flags = DataStoreOverrides { host:          matches.value_of("db-host").map(String::from),
+                                     port:          matches.value_of("db-port").map(String::from),
+                                     database:      matches.value_of("db-name").map(String::from),
+                                     user:          matches.value_of("db-user").map(String::from),
+                                     password:      None,
+                                     password_file: matches.value_of("db-password-file")
+                                                           .map(String::from), };
+    let overrides = flags.or(DataStoreOverrides::from_env(|name| env::var(name).ok()));
+    if let Err(e) = config.apply_datastore_overrides(&overrides) {
+        exit_on(&e);
+    }
+
+    if let Err(e) = config.validate() {
+        exit_on(&e);
+    }
+
+    enable_features(&config, &matches);
+
+    let mut cl = Copperline::new();
+
+    let target = match matches.value_of("target").map(parse_target) {
+        None => None,
+        Some(Ok(target)) => Some(target),
+        Some(Err(e)) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    };
+    let channel = match matches.value_of("channel").map(parse_channel) {
+        None => None,
+        Some(Ok(channel)) => Some(channel),
+        Some(Err(e)) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    };
+    let visibility = match Visibility::from_str(matches.value_of("visibility").unwrap_or("all")) {
+        Ok(visibility) => visibility,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    };
+    let scope = GraphScope { target,
+                             channel,
+                             visibility,
+                             latest_only: matches.is_present("latest-only") };
+
+    let aliases = Aliases::from_map(config.aliases.clone()).unwrap_or_else(|e| exit_on(&e));
+
+    let refresh_interval = match matches.value_of("auto-refresh").map(parse_interval) {
+        None => None,
+        Some(Ok(interval)) => Some(interval),
+        Some(Err(e)) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let (datastore, mut graph, watermark) = open_graph(&matches, &config, &scope);
+    apply_aliases(&mut graph, aliases.iter());
+
+    // The refresher reads from a connection of its own, so that it never
+    // holds up the commands
+    let refresher = refresh_interval.map(|interval| {
+                                        let datastore = connect(&config,
+                                                                matches.value_of("fixture"));
+                                        println!("Auto-refresh: every {} sec",
+                                                 interval.as_secs());
+                                        Refresher::spawn(datastore,
+                                                         &scope,
+                                                         interval,
+                                                         config.build_threads)
+                                    });
+
+    let compare_db = matches.value_of("compare-db").map(String::from);
+
+    let costs = load_costs(&config);
+
+    let source = match matches.value_of("load").or_else(|| matches.value_of("fixture")) {
+        Some(path) => path.to_string(),
+        None => cache_source(&config),
+    };
+
+    println!("\nAvailable commands: help, stats, top, find, resolve, filter, rdeps, deps, check, \
+              exit\n",);
+
+    let max_results = config.default_max_results;
+    let command_log = CommandLog::open(config.command_log.as_deref());
+
+    // The startup commands run as if typed at the first prompts, so a failing
+    // one is reported like any other command and the next one still runs
+    let startup = if matches.is_present("no-rc") {
+        Vec::new()
+    } else {
+        config.startup_commands.clone()
+    };
+
+    let mut malformed = MalformedIdents::default();
+    if !config.watched_packages.is_empty() {
+        do_watched(&graph, &config.watched_packages, &mut malformed);
+    }
+
+    // The prompt's database is the file the graph was loaded from, if any
+    let database = if datastore.is_some() {
+        config.datastore.database.clone()
+    } else {
+        source.clone()
+    };
+
+    let session = Session { config,
+                            datastore,
+                            graph,
+                            watermark,
+                            scope,
+                            refresher,
+                            compare_db,
+                            costs,
+                            source,
+                            database,
+                            filter: String::from(""),
+                            excludes: HashSet::new(),
+                            max_results,
+                            command_log,
+                            malformed,
+                            cancel: Cancel::default() };
+    run_session(session, &mut cl, &mut startup.into_iter());
+}
+
+// What the worker running the commands tells the main thread: that it is
+// ready for the next command, with the prompt to read it at, or that the
+// session ended. Ctrl-C comes on the same channel, so that the main thread
+// sees it while it waits for a command to finish.
+enum Event {
+    Ready(String),
+    Interrupt,
+    Ended,
+}
+
+// Sends Ended once the worker stops, whether on exit or because a command
+// panicked, so that the main thread never waits for it in vain
+struct SessionEnd(Sender<Event>);
+
+impl Drop for SessionEnd {
+    fn drop(&mut self) { drop(self.0.send(Event::Ended)); }
+}
+
+// The most reads at the prompt in a row that can fail before the session ends
+const MAX_READ_ERRORS: usize = 10;
+
+// Runs the commands on a worker thread, one at a time, while the main thread
+// reads them and watches for Ctrl-C. The first Ctrl-C while a command runs
+// cancels it, which the long graph walks and the check notice as they go,
+// and a second one before it stopped exits. At the prompt, the line editor
+// reads Ctrl-C as a key, which drops the line typed so far. The session ends
+// on exit, and at the end of the input, on Ctrl-D or once the commands piped
+// in run out, letting the worker finish as on exit.
+fn run_session<I>(session: Session, cl: &mut Copperline, startup: &mut I)
+    where I: Iterator<Item = String>
+{
+    let cancel = session.cancel.clone();
+    let (events, received) = mpsc::channel();
+    let interrupts = events.clone();
+    if let Err(e) = ctrlc::set_handler(move || drop(interrupts.send(Event::Interrupt))) {
+        println!("Ctrl-C cannot interrupt the commands, {}\n", e);
+    }
+    let (commands, worker_commands) = mpsc::channel();
+    let worker = thread::spawn(move || serve(session, &worker_commands, &SessionEnd(events)));
+
+    let mut read_errors = 0;
+    'session: while let Some(prompt) = wait_for_worker(&received, &cancel) {
+        let cmd = loop {
+            match read_command(cl, startup, &prompt) {
+                Ok(cmd) => break cmd,
+                Err(ReadError::Cleared) => println!(),
+                Err(ReadError::Ended) => {
+                    println!();
+                    break 'session;
+                }
+                Err(ReadError::Failed(e)) => {
+                    read_errors += 1;
+                    if read_errors == MAX_READ_ERRORS {
+                        println!("Reading the command failed {} times in a row, exiting: {}",
+                                 read_errors, e);
+                        break 'session;
+                    }
+                }
+            }
+        };
+        read_errors = 0;
+
+        // A Ctrl-C from before the command was read is not for it
+        received.try_iter().for_each(drop);
+        cancel.reset();
+        if commands.send(cmd).is_err() {
+            break;
+        }
+    }
+
+    // The worker ends once there are no more commands to wait for
+    drop(commands);
+    if worker.join().is_err() {
+        process::exit(101);
+    }
+    if read_errors == MAX_READ_ERRORS {
+        process::exit(1);
+    }
+}
+
+// Waits for the worker to be ready for the next command, cancelling the one
+// it runs on the first Ctrl-C and exiting on the second. None once the
+// session ended.
+fn wait_for_worker(received: &Receiver<Event>, cancel: &Cancel) -> Option<String> {
+    loop {
+        match received.recv().ok()? {
+            Event::Ready(prompt) => return Some(prompt),
+            Event::Interrupt if cancel.cancel() => {
+                println!("\nInterrupted twice, exiting");
+                process::exit(130);
+            }
+            Event::Interrupt => (),
+            Event::Ended => return None,
+        }
+    }
+}
+
+fn serve(mut session: Session, commands: &Receiver<String>, end: &SessionEnd) {
+    loop {
+        if end.0.send(Event::Ready(session.prompt())).is_err() {
+            return;
+        }
+        match commands.recv() {
+            Ok(cmd) if session.run(&cmd) => (),
+            _ => return,
+        }
+    }
+}
+
+// The state the commands share, owned by the worker thread that runs them
+struct Session {
+    config:      Config,
+    datastore:   Option<Box<dyn PackageStore + Send>>,
+    graph:       Graphs,
+    watermark:   Option<i64>,
+    scope:       GraphScope,
+    refresher:   Option<Refresher>,
+    compare_db:  Option<String>,
+    costs:       Costs,
+    source:      String,
+    database:    String,
+    filter:      String,
+    excludes:    HashSet<String>,
+    max_results: usize,
+    command_log: CommandLog,
+    malformed:   MalformedIdents,
+    cancel:      Cancel,
+}
+
+impl Session {
+    fn prompt(&self) -> String {
+        let target = self.graph.selected_scope(&self.scope).target;
+        prompt::render(&self.config.prompt,
+                       &PromptValues { database:   &self.database,
+                                       filter:     &self.filter,
+                                       node_count: self.graph.counts().0,
+                                       target:     target.as_deref(), })
+    }
+
+    // Runs the command line, returning false once the session should end.
+    // A command the token cancelled stops with "interrupted", and leaves the
+    // graph as it was.
+    fn run(&mut self, cmd: &str) -> bool {
+        let Session { ref config,
+                      ref datastore,
+                      ref mut graph,
+                      ref mut watermark,
+                      ref mut scope,
+                      ref refresher,
+                      ref compare_db,
+                      ref mut costs,
+                      ref source,
+                      ref mut filter,
+                      ref mut excludes,
+                      ref mut max_results,
+                      ref mut command_log,
+                      ref mut malformed,
+                      ref cancel,
+                      .. } = *self;
+
+        if let Some(ref refresher) = refresher {
+            swap_refreshed(refresher, graph, watermark, scope);
+        }
+
+        let v: Vec<&str> = cmd.trim_end().split_whitespace().collect();
+        if v.is_empty() {
+            return true;
+        }
+
+        let command = v[0].to_lowercase();
+        let (started, start_time) = (Utc::now(), Instant::now());
+        let command_filter = filter.clone();
+        let mut outcome = Ok(());
+        let mut done = false;
+        match command.as_str() {
+            "help" => do_help(),
+            "stats" => {
+                let interval = refresher.as_ref().map(|r| r.interval);
+                do_stats(graph, &v[1..], source, scope, interval)
+            }
+            "targets" => do_targets(graph),
+            "top" => outcome = do_top(graph, &v[1..], *max_results, cancel),
+            "filter" => {
+                if v.len() < 2 {
+                    *filter = String::from("");
+                    println!("Removed filter\n");
+                } else {
+                    *filter = String::from(v[1]);
+                    println!("New filter: {}\n", filter);
+                }
+            }
+            "exclude" => {
+                if v.len() < 2 {
+                    println!("Missing package name, 'list' or 'clear'\n")
+                } else {
+                    do_exclude(excludes, v[1].to_lowercase().as_str())
+                }
+            }
+            "find" => do_find(graph, &v[1..], *max_results),
+            "resolve" => do_resolve(graph, &v[1..]),
+            "rdeps" => {
+                outcome = do_rdeps(graph,
+                                   &v[1..],
+                                   filter,
+                                   excludes,
+                                   *max_results,
+                                   &config.limits,
+                                   cancel)
+            }
+            "max-results" => do_max_results(max_results, &v[1..]),
+            "deps" => {
+                if v.len() < 2 {
+                    println!("Missing package name\n")
+                } else {
+                    let name = v[1].to_lowercase();
+                    let datastore = datastore.as_ref().map(|ds| &**ds as &dyn PackageStore);
+                    do_deps(datastore, graph, &name, filter)
+                }
+            }
+            "job" => with_datastore(datastore, "job", |ds| do_job(ds, graph, &v[1..])),
+            "check" => {
+                with_datastore(datastore, "check", |ds| {
+                    outcome =
+                        do_check(ds, graph, &v[1..], filter, &config.limits, malformed, cancel)
+                })
+            }
+            "builddeps" => do_builddeps(graph, &v[1..]),
+            "export" => {
+                let mode = GraphMode { build_deps: graph.use_build_deps,
+                                       visibility: scope.visibility, };
+                do_export(graph, &v[1..], filter, mode, &config.export)
+            }
+            "save-cache" => do_save_cache(graph, &v[1..], source),
+            "reload" => {
+                with_datastore(datastore, "reload", |ds| {
+                    do_reload(ds, graph, watermark, scope, config.build_threads)
+                })
+            }
+            "update" => {
+                with_datastore(datastore, "update", |ds| {
+                    do_update(ds, graph, watermark, scope)
+                })
+            }
+            "target" => {
+                if !do_select_target(graph, &v[1..]) {
+                    with_datastore(datastore, "target", |ds| {
+                        do_target(ds,
+                                  graph,
+                                  watermark,
+                                  scope,
+                                  &v[1..],
+                                  config.build_threads)
+                    })
+                }
+            }
+            "export-dot" => do_export_dot(graph, &v[1..], &config.export),
+            "export-manifest" => do_export_manifest(graph, &v[1..], &config.export),
+            "export-cycles" => do_export_cycles(graph, &v[1..], &config.export),
+            "export-rdeps" => do_export_rdeps(graph, &v[1..], filter, &config.export),
+            "report" => do_report(graph, &v[1..], &config.export),
+            "resolve-file" => do_resolve_file(graph, &v[1..]),
+            "promote-sim" => {
+                with_datastore(datastore, "promote-sim", |ds| {
+                    do_promote_sim(ds, graph, &v[1..], malformed)
+                })
+            }
+            "plan" => do_plan(graph, costs, excludes, &v[1..]),
+            "build-levels" => do_build_levels(graph, costs, excludes, &v[1..]),
+            "impact" => do_impact(graph, costs, excludes, &v[1..]),
+            "costs" => do_costs(costs, &v[1..]),
+            "aliases" => do_aliases(graph, &v[1..]),
+            "bottlenecks" => do_bottlenecks(graph, &v[1..]),
+            "dups" => do_dups(graph, filter),
+            "pinned" => {
+                with_datastore(datastore, "pinned", |ds| {
+                    do_pinned(ds, graph, &v[1..], malformed)
+                })
+            }
+            "watched" => do_watched(graph, &config.watched_packages, malformed),
+            "malformed" => do_malformed(malformed),
+            "skipped" => do_skipped(graph),
+            "dependents" => do_dependents(graph, &v[1..], filter, &config.limits),
+            "fanout" => do_fanout(graph, &v[1..]),
+            "components" => do_components(graph, &v[1..]),
+            "validate" => do_validate(graph),
+            "bench" => do_bench(graph, &v[1..]),
+            "memstats" => do_memstats(graph),
+            "verify" => {
+                with_datastore(datastore, "verify", |ds| {
+                    do_verify(ds, graph, &v[1..], graph.use_build_deps, malformed)
+                })
+            }
+            "compare-env" => {
+                match v.get(1).cloned().or_else(|| compare_db.as_ref().map(String::as_str)) {
+                    Some(path) => {
+                        do_compare_env(graph, &graph.selected_scope(scope), path, source)
+                    }
+                    None => println!("Missing config file, or start with --compare-db\n"),
+                }
+            }
+            "verify-db" => {
+                with_datastore(datastore, "verify-db", |ds| {
+                    do_verify_db(ds, graph, &graph.selected_scope(scope))
+                })
+            }
+            "age" => do_age(graph, &v[1..]),
+            "oldest" => do_oldest(graph, &v[1..], filter),
+            "outdated" => {
+                let origin = if v.len() < 2 { "" } else { v[1] };
+                do_outdated(graph, origin.to_lowercase().as_str(), filter)
+            }
+            "coverage" => {
+                if v.len() < 2 {
+                    println!("Missing file name\n")
+                } else {
+                    do_coverage(graph, v[1], filter)
+                }
+            }
+            "dbstats" => with_datastore(datastore, "dbstats", do_dbstats),
+            "remove" => do_remove(graph, &v[1..]),
+            "load" => {
+                with_datastore(datastore, "load", |ds| {
+                    do_load(ds, graph, scope, &v[1..])
+                })
+            }
+            "exit" => done = true,
+            _ => println!("Unknown command\n"),
+        }
+        if let Err(e) = outcome {
+            println!("\n{}\n", e);
+        }
+        warn_if_incomplete(graph, &command, &v[1..]);
+        command_log.record(cmd, &command_filter, started, start_time.elapsed());
+        if let Some(ref refresher) = refresher {
+            refresher.set_scope(scope);
+        }
+        !done
+    }
+}
+
+// The costs of the costs_file of the config file, if it has one. The tool
+// exits if the file cannot be read.
+fn load_costs(config: &Config) -> Costs {
+    match config.costs_file {
+        Some(ref filename) => {
+            match Costs::load(filename) {
+                Ok(costs) => {
+                    println!("OK: {} costs read from {}", costs.len(), filename);
+                    costs
+                }
+                Err(e) => {
+                    println!("Reading costs from {} failed: {}", filename, e);
+                    process::exit(e.exit_code());
+                }
+            }
+        }
+        None => Costs::default(),
+    }
+}
+
+// Logs as BLDR_GRAPH_LOG_LEVEL says if it is set, or else as RUST_LOG does
+fn init_logger() {
+    let mut builder = env_logger::Builder::from_default_env();
+    let filters = env::var(config::LOG_LEVEL_ENVVAR).unwrap_or_default();
+    if !filters.is_empty() {
+        builder.parse_filters(&filters);
+    }
+    builder.init();
+}
+
+// Why reading at the prompt gave no command: Ctrl-C dropped the line, the
+// input ended, or reading it failed, which may not happen again
+enum ReadError {
+    Cleared,
+    Ended,
+    Failed(String),
+}
+
+// The next startup command, echoed at the prompt, or else the next line
+// typed, which is added to the history
+fn read_command<I>(cl: &mut Copperline,
+                   startup: &mut I,
+                   prompt: &str)
+                   -> Result<String, ReadError>
+    where I: Iterator<Item = String>
+{
+    if let Some(cmd) = startup.next() {
+        println!("{}{}", prompt, cmd);
+        return Ok(cmd);
+    }
+    let cmd = match cl.read_line_utf8(prompt) {
+        Ok(cmd) => cmd,
+        Err(copperline::Error::Cancel) => return Err(ReadError::Cleared),
+        Err(copperline::Error::EndOfFile) => return Err(ReadError::Ended),
+        // The line editor needs a terminal, so piped commands are read as lines
+        Err(copperline::Error::UnsupportedTerm) => read_plain_line(prompt)?,
+        Err(e) => return Err(ReadError::Failed(format!("{:?}", e))),
+    };
+    cl.add_history(cmd.clone());
+    Ok(cmd)
+}
+
+// Reads the next line of the input without the line editor, as when it is
+// not a terminal
+fn read_plain_line(prompt: &str) -> Result<String, ReadError> {
+    print!("{}", prompt);
+    io::stdout().flush()
+                .map_err(|e| ReadError::Failed(e.to_string()))?;
+
+    let mut line = String::new();
+    match io::stdin().read_line(&mut line) {
+        Ok(0) => Err(ReadError::Ended),
+        Ok(_) => Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string()),
+        Err(e) => Err(ReadError::Failed(e.to_string())),
+    }
+}
+
+// Connects to the database and builds the graph, or loads it, as given on the
+// command line. The watermark is the highest package id read from the
+// database, and is only known when the graph was built from it.
+fn open_graph(matches: &ArgMatches,
+              config: &Config,
+              scope: &GraphScope)
+              -> (Option<Box<dyn PackageStore + Send>>, Graphs, Option<i64>) {
+    let fixture = matches.value_of("fixture");
+    match (matches.value_of("load"), matches.value_of("cache")) {
+        (Some(path), _) => (None, Graphs::single(load_graph(path)), None),
+        (None, Some(path)) => {
+            let datastore = connect(config, fixture);
+            let (cached, created_at) = load_cache(path);
+            let mut graph = Graphs::single(cached);
+            graph.built_at = created_at;
+            (Some(datastore), graph, None)
+        }
+        (None, None) if matches.is_present("lazy") => {
+            let datastore = connect(config, fixture);
+            println!("Started with an empty graph, use 'load <origin>' to add origins");
+            (Some(datastore), Graphs::lazy(), None)
+        }
+        (None, None) => {
+            let datastore = connect(config, fixture);
+            let (graph, watermark) = build_graph(&*datastore, scope, config.build_threads);
+            (Some(datastore), graph, Some(watermark))
+        }
+    }
+}
+
+// Connects to the database, or reads the package records from the fixture file
+// when one is given
+fn connect(config: &Config, fixture: Option<&str>) -> Box<dyn PackageStore + Send> {
+    if let Some(path) = fixture {
+        println!("Reading the package records from {}", path);
+        return match FixtureStore::from_file(path, &config.origins) {
+                   Ok(store) => Box::new(store),
+                   Err(e) => {
+                       println!("Failed to read {}: {}", path, e);
+                       process::exit(1);
+                   }
+               };
+    }
+
+    println!("Connecting to {}", cache_source(config));
+
+    match DataStore::connect(config) {
+        Ok(datastore) => Box::new(datastore),
+        Err(e) => exit_on(&e),
+    }
+}
+
+// Ends the startup on the error, printing it and exiting with the code of the
+// step that failed
+fn exit_on(e: &error::Error) -> ! {
+    println!("{}", e);
+    process::exit(e.exit_code())
+}
+
+// The graph used by the commands, which it derefs to, along with the graph of
+// the other set of dependencies when the graph was built from the database.
+// The builddeps toggle switches between the graph of the runtime dependencies
+// and the one that also has the build dependencies. A lazy graph only has the
+// packages of the origins that were loaded, which are tracked. A graph built
+// from the database has a pair of graphs per target, the selected target's
+// being the ones in use and the others stashed. A graph without a target
+// (loaded from a file, or lazy) has the packages of every target. The graph
+// was built, or last updated, at built_at (in seconds since the epoch).
+struct Graphs {
+    active:         PackageGraph,
+    other:          Option<PackageGraph>,
+    use_build_deps: bool,
+    loaded_origins: Option<BTreeSet<String>>,
+    target:         Option<String>,
+    stashed:        BTreeMap<String, (PackageGraph, Option<PackageGraph>)>,
+    built_at:       i64,
+}
+
+impl Graphs {
+    // A graph with a single set of dependencies, as loaded from a file
+    fn single(graph: PackageGraph) -> Self {
+        Graphs { active:         graph,
+                 other:          None,
+                 use_build_deps: feat::is_enabled(feat::BuildDeps),
+                 loaded_origins: None,
+                 target:         None,
+                 stashed:        BTreeMap::new(),
+                 built_at:       Utc::now().timestamp(), }
+    }
+
+    fn new(runtime: PackageGraph, build: PackageGraph, use_build_deps: bool) -> Self {
+        let (active, other) = if use_build_deps {
+            (build, runtime)
+        } else {
+            (runtime, build)
+        };
+        Graphs { active,
+                 other: Some(other),
+                 use_build_deps,
+                 loaded_origins: None,
+                 target: None,
+                 stashed: BTreeMap::new(),
+                 built_at: Utc::now().timestamp() }
+    }
+
+    // The runtime and build graphs of each target, selecting the target with
+    // the most packages (the first in order if several have as many)
+    fn by_target(built: BTreeMap<String, (PackageGraph, PackageGraph)>,
+                 use_build_deps: bool)
+                 -> Self {
+        let selected = built.iter()
+                            .min_by_key(|(_, (runtime, _))| cmp::Reverse(runtime.counts().0))
+                            .map(|(target, _)| target.clone());
+        let mut graphs = Graphs::new(PackageGraph::new(), PackageGraph::new(), use_build_deps);
+        for (target, (runtime, build)) in built {
+            let pair = if use_build_deps {
+                (build, Some(runtime))
+            } else {
+                (runtime, Some(build))
+            };
+            graphs.stashed.insert(target, pair);
+        }
+        if let Some(target) = selected {
+            graphs.select_target(&target);
+        }
+        graphs
+    }
+
+    // Makes the graphs of the target the ones in use, returning false if no
+    // packages were loaded for it
+    fn select_target(&mut self, target: &str) -> bool {
+        if self.target.as_ref().map(String::as_str) == Some(target) {
+            return true;
+        }
+        let (active, other) = match self.stashed.remove(target) {
+            Some(pair) => pair,
+            None => return false,
+        };
+        let previous = (mem::replace(&mut self.active, active),
+                        mem::replace(&mut self.other, other));
+        if let Some(previous_target) = self.target.replace(target.to_string()) {
+            self.stashed.insert(previous_target, previous);
+        }
+        true
+    }
+
+    // The loaded targets, with the node count of each, or nothing if the
+    // graph is not split by target
+    fn targets(&self) -> Vec<(String, usize)> {
+        let mut targets: Vec<(String, usize)> =
+            self.stashed
+                .iter()
+                .map(|(target, (active, _))| (target.clone(), active.counts().0))
+                .collect();
+        if let Some(ref target) = self.target {
+            targets.push((target.clone(), self.active.counts().0));
+        }
+        targets.sort();
+        targets
+    }
+
+    // Every graph held, those in use first, each labelled with its target (if
+    // the graphs are split by target) and its set of dependencies
+    fn all(&self) -> Vec<(String, &PackageGraph)> {
+        let (active_kind, other_kind) = if self.use_build_deps {
+            ("build deps", "runtime deps")
+        } else {
+            ("runtime deps", "build deps")
+        };
+        let in_use = (self.target.as_ref(), &self.active, self.other.as_ref());
+        let stashed = self.stashed
+                          .iter()
+                          .map(|(target, (active, other))| (Some(target), active, other.as_ref()));
+
+        let mut all = Vec::new();
+        for (target, active, other) in iter::once(in_use).chain(stashed) {
+            let label = |kind: &str| {
+                match target {
+                    Some(target) => format!("{} {}", target, kind),
+                    None => kind.to_string(),
+                }
+            };
+            all.push((label(active_kind), active));
+            if let Some(other) = other {
+                all.push((label(other_kind), other));
+            }
+        }
+        all
+    }
+
+    // The scope restricted to the selected target, which is what the graph
+    // in use has the packages of
+    fn selected_scope(&self, scope: &GraphScope) -> GraphScope {
+        GraphScope { target: self.target.clone().or_else(|| scope.target.clone()),
+                     ..scope.clone() }
+    }
+
+    // An empty graph, extended an origin at a time with load
+    fn lazy() -> Self {
+        let mut graphs = Graphs::new(PackageGraph::new(),
+                                     PackageGraph::new(),
+                                     feat::is_enabled(feat::BuildDeps));
+        graphs.loaded_origins = Some(BTreeSet::new());
+        graphs
+    }
+
+    // The origins of the names that are not loaded, which is none of them
+    // unless the graph is lazy
+    fn unloaded_origins<'a, I>(&self, names: I) -> BTreeSet<String>
+        where I: IntoIterator<Item = &'a String>
+    {
+        match self.loaded_origins {
+            Some(ref loaded) => {
+                names.into_iter()
+                     .map(|name| name.split('/').next().unwrap_or_default().to_string())
+                     .filter(|origin| !loaded.contains(origin))
+                     .collect()
+            }
+            None => BTreeSet::new(),
+        }
+    }
+
+    // The graph that also has the build dependencies, whose edges record
+    // which kind of dependency they are, if both sets of dependencies exist
+    fn with_build_deps(&self) -> Option<&PackageGraph> {
+        match self.other {
+            Some(ref other) if !self.use_build_deps => Some(other),
+            Some(_) => Some(&self.active),
+            None => None,
+        }
+    }
+
+    // Switches the active graph, returning false if the other set of
+    // dependencies is not available
+    fn set_build_deps(&mut self, use_build_deps: bool) -> bool {
+        if use_build_deps != self.use_build_deps {
+            match self.other {
+                Some(ref mut other) => mem::swap(&mut self.active, other),
+                None => return false,
+            }
+            for (active, other) in self.stashed.values_mut() {
+                if let Some(ref mut other) = *other {
+                    mem::swap(active, other);
+                }
+            }
+            self.use_build_deps = use_build_deps;
+        }
+        true
+    }
+
+    // Removes the package, given as a short name or fully-qualified ident,
+    // from both sets of dependencies. Returns what the active graph dropped.
+    fn remove(&mut self, name: &str) -> Option<Removed> {
+        let removed = if name.split('/').count() == 4 {
+            self.active.remove(name)
+        } else {
+            self.active.remove_name(name)
+        }?;
+        if let (Some(other), Ok(name)) = (self.other.as_mut(), short_name(name)) {
+            other.remove_name(&name);
+        }
+        Some(removed)
+    }
+
+    // Adds the alias to the active graph, then, if it takes it, to both sets
+    // of dependencies of every other target. Returns what it did in the
+    // active graph, and the targets whose graphs did not take it, with why.
+    fn add_alias(&mut self,
+                 old: &str,
+                 new: &str)
+                 -> Result<(AliasApplied, Vec<(String, AliasError)>), AliasError> {
+        let applied = self.active.add_alias(old, new)?;
+
+        let mut rejected = Vec::new();
+        if let Some(ref mut other) = self.other {
+            if let Err(e) = other.add_alias(old, new) {
+                let target = self.target.clone().unwrap_or_else(|| "all targets".to_string());
+                rejected.push((target, e));
+            }
+        }
+        for (target, (active, other)) in self.stashed.iter_mut() {
+            // The other set of dependencies is left alone if the target's
+            // active graph does not take the alias
+            let mut graphs = iter::once(active).chain(other.as_mut());
+            if let Some(e) = graphs.find_map(|graph| graph.add_alias(old, new).err()) {
+                rejected.push((target.to_string(), e));
+            }
+        }
+        Ok((applied, rejected))
+    }
+
+    // Extends both sets of dependencies of the package's target, returning
+    // what changed in the active graph, or None if the package is for another
+    // target than the selected one
+    fn extend(&mut self, next: &GraphPackage) -> Option<ExtendDelta> {
+        let use_build_deps = self.use_build_deps;
+        match self.target {
+            Some(ref selected) if selected != next.package.get_target() => {
+                let (active, other) =
+                    self.stashed
+                        .entry(next.package.get_target().to_string())
+                        .or_insert_with(|| (PackageGraph::new(), Some(PackageGraph::new())));
+                extend_graphs(active, other.as_mut(), next, use_build_deps);
+                None
+            }
+            _ => {
+                Some(extend_graphs(&mut self.active, self.other.as_mut(), next, use_build_deps))
+            }
+        }
+    }
+}
+
+// Extends the active graph and the other set of dependencies with the package,
+// returning what changed in the active graph
+fn extend_graphs(active: &mut PackageGraph,
+                 other: Option<&mut PackageGraph>,
+                 next: &GraphPackage,
+                 use_build_deps: bool)
+                 -> ExtendDelta {
+    let ident = next.package.get_ident().to_string();
+    let delta = active.extend_delta(&next.package, use_build_deps);
+    if let Some(created_at) = next.created_at {
+        active.set_created(&ident, created_at);
+    }
+    if let Some(other) = other {
+        other.extend(&next.package, !use_build_deps);
+        if let Some(created_at) = next.created_at {
+            other.set_created(&ident, created_at);
+        }
+    }
+    delta
+}
+
+impl Deref for Graphs {
+    type Target = PackageGraph;
+
+    fn deref(&self) -> &PackageGraph { &self.active }
+}
+
+fn build_graph(datastore: &dyn PackageStore, scope: &GraphScope, threads: usize) -> (Graphs, i64) {
+    match fetch_graph(datastore, scope, threads, false) {
+        Ok(built) => built,
+        Err(e) => {
+            println!("Failed to build graph: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+// Fetches the packages in the scope once and builds both graphs for each
+// target from them, the runtime dependencies and the runtime and build
+// dependencies, parsing the packages on the given number of threads (0 for
+// one per CPU). Returns the graphs of every target, with the one with the
+// most packages selected, and the highest package id read. A quiet build, in
+// the background, prints nothing.
+fn fetch_graph(datastore: &dyn PackageStore,
+               scope: &GraphScope,
+               threads: usize,
+               quiet: bool)
+               -> error::Result<(Graphs, i64)> {
+    if !quiet {
+        if *scope == GraphScope::default() {
+            println!("Building graph... please wait.");
+        } else {
+            println!("Building graph for {}... please wait.", scope);
+        }
+    }
+
+    let mut packages = datastore.get_job_graph_packages(scope);
+    let mut fetched: BTreeMap<String, Vec<_>> = BTreeMap::new();
+    let mut created: BTreeMap<String, Vec<_>> = BTreeMap::new();
+    let start_time = Instant::now();
+    for next in packages.by_ref() {
+        let next = next?;
+        let target = next.package.get_target().to_string();
+        if let Some(created_at) = next.created_at {
+            created.entry(target.clone())
+                   .or_default()
+                   .push((next.package.get_ident().to_string(), created_at));
+        }
+        fetched.entry(target).or_default().push(next.package);
+    }
+    let fetch_time = start_time.elapsed();
+
+    let start_time = Instant::now();
+    let mut built = BTreeMap::new();
+    for (target, target_packages) in fetched {
+        let parsed = package_graph::parse_packages(target_packages, threads);
+        let mut runtime = PackageGraph::new();
+        let mut build = PackageGraph::new();
+        runtime.build_parsed(&parsed, false);
+        build.build_parsed(&parsed, true);
+        for (ident, created_at) in created.remove(&target).unwrap_or_default() {
+            runtime.set_created(&ident, created_at);
+            build.set_created(&ident, created_at);
+        }
+        built.insert(target, (runtime, build));
+    }
+    let build_time = start_time.elapsed();
+    let use_build_deps = feat::is_enabled(feat::BuildDeps);
+    if quiet {
+        return Ok((Graphs::by_target(built, use_build_deps), packages.watermark()));
+    }
+
+    let (mut node_count, mut edge_count, mut build_edge_count) = (0, 0, 0);
+    let mut skipped_count = 0;
+    let mut index_time = Duration::default();
+    for (runtime, build) in built.values() {
+        let (nodes, edges) = runtime.counts();
+        node_count += nodes;
+        edge_count += edges;
+        build_edge_count += build.counts().1;
+        skipped_count += build.skipped().len();
+        index_time += runtime.search_index_time() + build.search_index_time();
+    }
+    // The search indexes are built along with the graphs
+    println!("OK: {} packages read, {} skipped, {} nodes, {} edges, {} with build deps (fetch: \
+              {:.3}s, build: {:.3}s, of which search index: {:.3}s)",
+             packages.rows_read(),
+             skipped_count,
+             node_count,
+             edge_count,
+             build_edge_count,
+             fetch_time.as_secs_f64(),
+             build_time.as_secs_f64(),
+             index_time.as_secs_f64());
+    if skipped_count > 0 {
+        println!("Skipped the packages or dependencies whose idents could not be parsed, use \
+                  'skipped' to list them");
+    }
+    if built.len() > 1 {
+        let per_target: Vec<String> =
+            built.iter()
+                 .map(|(target, (runtime, _))| format!("{} ({})", target, runtime.counts().0))
+                 .collect();
+        println!("Targets: {}", per_target.join(", "));
+    }
+
+    // The graphs with the build dependencies have all the edges
+    if scope.visibility == Visibility::Public {
+        let build_graphs: Vec<&PackageGraph> = built.values().map(|(_, build)| build).collect();
+        report_excluded(&build_graphs, packages.excluded());
+    }
+
+    let graph = Graphs::by_target(built, use_build_deps);
+    println!("Build dependencies: {}", if use_build_deps { "on" } else { "off" });
+    if !graph.stashed.is_empty() {
+        println!("Selected target: {}, use 'target <target>' to switch",
+                 graph.target.as_ref().map(String::as_str).unwrap_or_default());
+    }
+
+    let origins = datastore.origins();
+    if !origins.is_empty() {
+        let dangling = graph.node_names()
+                            .iter()
+                            .filter(|name| {
+                                let origin = name.split('/').next().unwrap_or_default();
+                                !origins.iter().any(|o| o == origin)
+                            })
+                            .count();
+        println!("Fetched origins: {}. {} dependencies on other origins are kept as dangling \
+                  references",
+                 origins.join(", "),
+                 dangling);
+    }
+
+    if let Some((ref origin, ref channel)) = scope.channel {
+        report_outside_channel(&graph, &format!("{}/{}", origin, channel));
+    }
+
+    Ok((graph, packages.watermark()))
+}
+
+// Switches the commands between the graph of the runtime dependencies and the
+// graph that also has the build dependencies
+fn do_builddeps(graph: &mut Graphs, args: &[&str]) {
+    let use_build_deps = match args.get(0) {
+        Some(&"on") => true,
+        Some(&"off") => false,
+        None => {
+            println!("Build dependencies: {}\n",
+                     if graph.use_build_deps { "on" } else { "off" });
+            return;
+        }
+        Some(_) => {
+            println!("Usage: builddeps [on|off]\n");
+            return;
+        }
+    };
+
+    if graph.set_build_deps(use_build_deps) {
+        let stats = graph.stats();
+        println!("Build dependencies: {} ({} nodes, {} edges)\n",
+                 if use_build_deps { "on" } else { "off" },
+                 stats.node_count,
+                 stats.edge_count);
+    } else {
+        println!("Only one set of dependencies is available for a graph loaded from a file\n");
+    }
+}
+
+// Warns about the public packages that depend on packages which were left out
+// of the graphs because they are private or hidden
+fn report_excluded(graphs: &[&PackageGraph], excluded: &[String]) {
+    const MAX_LISTED: usize = 10;
+
+    let mut excluded_names = HashSet::new();
+    for ident in excluded {
+        match short_name(ident) {
+            Ok(name) => excluded_names.insert(name),
+            Err(e) => {
+                println!("Skipped a package left out, {}", e);
+                continue;
+            }
+        };
+    }
+    let mut flagged = Vec::new();
+    for graph in graphs {
+        for name in graph.node_names() {
+            if let Some(ident) = graph.resolve(&name) {
+                for dep in graph.direct_deps(&name).unwrap_or_default() {
+                    if graph.resolve(&dep).is_none() && excluded_names.contains(&dep) {
+                        flagged.push((ident.clone(), dep));
+                    }
+                }
+            }
+        }
+    }
+
+    println!("Visibility: public, {} private or hidden packages left out",
+             excluded.len());
+    if flagged.is_empty() {
+        return;
+    }
+
+    println!("Warning: {} dependencies of public packages are private or hidden:",
+             flagged.len());
+    for (ident, dep) in flagged.iter().take(MAX_LISTED) {
+        println!("  {} -> {}", ident, dep);
+    }
+    if flagged.len() > MAX_LISTED {
+        println!("  ... and {} more", flagged.len() - MAX_LISTED);
+    }
+}
+
+// Prints the packages of the channel graph that depend on packages which are
+// not in the channel, i.e. that have no latest package in the graph
+fn report_outside_channel(graph: &PackageGraph, channel: &str) {
+    const MAX_LISTED: usize = 10;
+
+    let mut outside = Vec::new();
+    for name in graph.node_names() {
+        if let Some(ident) = graph.resolve(&name) {
+            for dep in graph.direct_deps(&name).unwrap_or_default() {
+                if graph.resolve(&dep).is_none() {
+                    outside.push((ident.clone(), dep));
+                }
+            }
+        }
+    }
+
+    if outside.is_empty() {
+        return;
+    }
+
+    println!("{} dependencies of packages in {} are not in the channel:",
+             outside.len(),
+             channel);
+    for (ident, dep) in outside.iter().take(MAX_LISTED) {
+        println!("  {} -> {}", ident, dep);
+    }
+    if outside.len() > MAX_LISTED {
+        println!("  ... and {} more", outside.len() - MAX_LISTED);
+    }
+}
+
+// Parses an 'origin/channel' argument
+fn parse_channel(value: &str) -> Result<(String, String), String> {
+    let parts: Vec<&str> = value.split('/').collect();
+    if parts.len() == 2 && parts.iter().all(|p| !p.is_empty()) {
+        Ok((parts[0].to_string(), parts[1].to_string()))
+    } else {
+        Err(format!("Invalid channel {}, expected origin/channel", value))
+    }
+}
+
+fn parse_interval(value: &str) -> Result<Duration, String> {
+    match value.parse::<u64>() {
+        Ok(secs) if secs > 0 => Ok(Duration::from_secs(secs)),
+        _ => Err(format!("Invalid refresh interval {}, expected a number of seconds", value)),
+    }
+}
+
+fn parse_target(value: &str) -> Result<String, String> {
+    match PackageTarget::from_str(value) {
+        Ok(target) => Ok(target.to_string()),
+        Err(e) => Err(format!("Invalid target {}: {}", value, e)),
+    }
+}
+
+// Loads the graph from the cache file, returning it with the time the cache
+// was created
+fn load_cache(path: &str) -> (PackageGraph, i64) {
+    println!("Loading graph cache from {}... please wait.", path);
+
+    let start_time = PreciseTime::now();
+    let (graph, info) = match cache::load_cache(path) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            println!("Failed to load graph cache from {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+    let end_time = PreciseTime::now();
+
+    let stats = graph.stats();
+    println!("OK: {} nodes, {} edges ({} sec)",
+             stats.node_count,
+             stats.edge_count,
+             start_time.to(end_time));
+    println!("Cache created {} UTC from {}",
+             NaiveDateTime::from_timestamp(info.created_at, 0).format("%Y-%m-%d %H:%M:%S"),
+             info.source);
+
+    (graph, info.created_at)
+}
+
+// Identifies the database a graph cache was built from
+fn cache_source(config: &Config) -> String {
+    format!("{}@{}:{}/{}",
+            config.datastore.user,
+            config.datastore.host,
+            config.datastore.port,
+            config.datastore.database)
+}
+
+fn load_graph(path: &str) -> PackageGraph {
+    println!("Loading graph from {}... please wait.", path);
+
+    let start_time = PreciseTime::now();
+    let graph = match export::load_json(path) {
+        Ok(graph) => graph,
+        Err(e) => {
+            println!("Failed to load graph from {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+    let end_time = PreciseTime::now();
+
+    let stats = graph.stats();
+    println!("OK: {} nodes, {} edges ({} sec)",
+             stats.node_count,
+             stats.edge_count,
+             start_time.to(end_time));
+    println!("Offline mode: commands that need the database are not available");
+
+    graph
+}
+
+// Runs the command with the datastore, unless the graph was loaded from a file
+fn with_datastore<F>(datastore: &Option<Box<dyn PackageStore + Send>>, command: &str, f: F)
+    where F: FnOnce(&dyn PackageStore)
+{
+    match *datastore {
+        Some(ref datastore) => f(&**datastore),
+        None => println!("The {} command needs the database, which is not available in offline \
+                          mode\n",
+                         command),
+    }
+}
+
+fn do_help() {
+    println!("Commands:");
+    println!("  help                    Print this message");
+    println!("  stats   [--out <filename>]");
+    println!("                          Print graph statistics, or write them to the file as \
+              json");
+    println!("  top     [<count>] [<offset>]");
+    println!("                          Print nodes with the most reverse dependencies, \
+              starting at the offset");
+    println!("  filter  [<origin>|<origin/name>]");
+    println!("                          Filter outputs to the packages of the origin, or to the \
+              package");
+    println!("  max-results [<count>]   Show or set the number of results top, find and rdeps \
+              list without");
+    println!("                          a max, for the session");
+    println!("  exclude <name>|list|clear");
+    println!("                          Prune the package from traversals, or list/clear \
+              the excluded packages");
+    println!("  resolve <name>[@<version>] [-m]");
+    println!("                          Find the most recent version of the package \
+              'origin/name', or the");
+    println!("                          newest one whose version starts with the given \
+              components");
+    println!("                          ('=<version>' for an exact version). -m adds the \
+              target and");
+    println!("                          release time of the latest package");
+    println!("  resolve-file <input> <output>");
+    println!("                          Resolve each name in the input file, writing the latest \
+              idents and");
+    println!("                          their direct dependencies to the output file");
+    println!("  find    [-e|-p|-g] [-c] [-m] <term> [<max>]");
+    println!("                          Find packages that contain the search term, up to max \
+              items.");
+    println!("                          -e matches the whole name, -p its start, and -g a glob \
+              pattern");
+    println!("                          ('*', '?', '\\' to escape). -c makes the match case \
+              sensitive,");
+    println!("                          and -m adds the target and release time of each \
+              package");
+    println!("  rdeps   <name> [<max>] [--by-depth] [--total] [--runtime-only|--build-only]");
+    println!("                          Print the reverse dependencies for the package, up to \
+              max, with");
+    println!("                          their minimum depth from it, optionally grouped by \
+              depth, and");
+    println!("                          optionally following only one kind of dependency. \
+              With a");
+    println!("                          filter, --total counts the matches past those shown");
+    println!("  deps    <name>|<ident>  Print the runtime and build dependencies for the package");
+    println!("  dependents <name>|<ident> [<max>]");
+    println!("                          Print the direct dependents of the package, up to max");
+    println!("  job     <name>|<ident>  Print the build job for the package, or the latest \
+              job for the name");
+    println!("  bench   [<iterations>] [--json]");
+    println!("                          Time a fixed workload of graph operations, printing \
+              the median and");
+    println!("                          95th percentile of each, optionally as json");
+    println!("  watched                 Print the status of the watched packages of the config \
+              file");
+    println!("  memstats                Print the estimated memory of each graph and the \
+              memory of the process");
+    println!("  malformed               List the malformed idents the commands skipped in the \
+              session,");
+    println!("                          with the packages that referenced them");
+    println!("  skipped                 List the packages and dependencies left out of the \
+              graph, since");
+    println!("                          their idents could not be parsed");
+    println!("  check   <name>|<ident> [--out <filename>]");
+    println!("                          Validate the latest dependencies for the package, \
+              optionally");
+    println!("                          writing a json report (see README)");
+    println!("  export  <filename> [--format text|tsv|json|dot|csv|csv-nodes|graphml|plans]");
+    println!("                          Export data from graph to specified file. The text \
+              format lists");
+    println!("                          the latest idents, and the tsv format has \
+              origin,name,version,");
+    println!("                          release,target columns. The json format is an object \
+              with 'metadata'");
+    println!("                          (generated_at, node_count, edge_count, build_deps, \
+              filter),");
+    println!("                          'nodes' ({{ident, name, origin}}) and 'edges' \
+              ({{from, to}}, where");
+    println!("                          'from' depends on 'to'). The dot format is a GraphViz \
+              digraph");
+    println!("                          clustered by origin. The csv format has one \
+              from_ident,to_ident,");
+    println!("                          from_origin,to_origin row per edge, and csv-nodes one \
+              ident,origin,");
+    println!("                          name,version,rdep_count row per node. The graphml \
+              format has");
+    println!("                          ident, origin and rdep_count node attributes. The \
+              plans format");
+    println!("                          lists names (or idents, with --idents) under [origin] \
+              headers in");
+    println!("                          build order");
+    println!("  export  <filename> --diff <old_filename>");
+    println!("                          Export the latest packages added, removed and changed \
+              since a");
+    println!("                          previous text export");
+    println!("                          All exports are gzip-compressed when the file name \
+              ends in .gz");
+    println!("                          or when --compress is given");
+    println!("  report  <filename> [--format csv|json] [--top <count>]");
+    println!("                          Export a per-origin summary of package and edge counts, \
+              with the");
+    println!("                          top packages by direct dependents");
+    println!("  save-cache <filename>   Save the graph to a cache file for use with --cache");
+    println!("  load    <origin>        Add the packages of the origin to a graph started with \
+              --lazy");
+    println!("  remove  <name>|<ident>  Remove the package from the graph, printing the \
+              dependents left");
+    println!("                          missing a dependency (reload restores it)");
+    println!("  reload                  Rebuild the graph from the database");
+    println!("  compare-env [<config>]  Compare the latest packages with the database of the \
+              config file");
+    println!("                          (the --compare-db one by default), by origin");
+    println!("  update                  Add the packages created since the graph was built or \
+              updated");
+    println!("  builddeps [on|off]      Include the build dependencies in the graph, or print \
+              whether");
+    println!("                          they are included");
+    println!("  target  [<target>|all]  Switch to the graph of a loaded target, or rebuild the \
+              graph from");
+    println!("                          the packages for the target. Prints the target \
+              without one");
+    println!("  targets                 List the loaded targets with their node counts");
+    println!("  export-manifest <filename> [<origin>]");
+    println!("                          Export the latest packages in build order, with \
+              cycles listed last");
+    println!("  export-cycles <filename>");
+    println!("                          Export the dependency cycles, with their members and \
+              edges");
+    println!("  export-rdeps <name> <filename> [--force]");
+    println!("                          Export the reverse dependencies for the package, one \
+              ident per line");
+    println!("  export-dot <name> <depth> <filename>");
+    println!("                          Export the packages within depth hops of the package \
+              as a dot graph");
+    println!("                          The export commands write a bare file name to the \
+              export directory");
+    println!("                          of the config file, if set, and --force replaces an \
+              existing file");
+    println!("  coverage <filename>     Compare the graph against a package list file");
+    println!("  bottlenecks [<count>]   Print packages whose removal would partition the graph");
+    println!("  dups                    Print package names that exist in multiple origins");
+    println!("  plan    <name>...       Print a merged rebuild order for the packages, with \
+              its cost");
+    println!("  build-levels <name>... [--workers <n>]");
+    println!("                          Print the rebuild of the packages as levels that can \
+              be built in");
+    println!("                          parallel, with the cost and the estimated time of \
+              each level");
+    println!("  impact  <name>...       Print the number of packages to rebuild and their \
+              total cost");
+    println!("  costs   [<file>|clear]  Load rebuild costs from 'origin/name cost' lines, or \
+              show or clear them");
+    println!("  aliases [<file>|list]   Add aliases of renamed packages from 'old/name \
+              new/name' lines,");
+    println!("                          or list them");
+    println!("  fanout  <name> [<depth>]");
+    println!("                          Print the number of reverse dependencies at each \
+              depth");
+    println!("  validate                Check the graph for inconsistencies");
+    println!("  components [<min_size>]");
+    println!("                          Print the connected components, summarizing those \
+              below min_size");
+    println!("  pinned  [<origin>]      Print packages with deps pinned to specific releases");
+    println!("  verify  [<origin>] [--full]");
+    println!("                          Cross-check a sample (or all) of the graph edges \
+              against the datastore");
+    println!("  dbstats                 Print the number, rows and timing of the database \
+              queries run");
+    println!("                          since the graph was built");
+    println!("  verify-db               Compare the package names of each origin in the graph \
+              with the");
+    println!("                          database, for the target, channel and visibility of \
+              the graph");
+    println!("  age     <name>|<ident>  Print when the latest package and the latest packages \
+              of its");
+    println!("                          dependencies were created");
+    println!("  oldest  [<count>]       Print the latest packages that were created the \
+              longest ago");
+    println!("  outdated [<origin>]     Print packages built before the latest build of a \
+              dependency");
+    println!("  promote-sim <channel> <name>|<ident>...");
+    println!("                          Simulate promoting the packages to the channel");
+    println!("  exit                    Exit the application\n");
+    println!("Ctrl-C interrupts the top, rdeps and check commands, leaving the graph as it \
+              was.");
+    println!("At the prompt it clears the line, and Ctrl-D exits.\n");
+}
+
+fn do_stats(graph: &Graphs,
+            args: &[&str],
+            source: &str,
+            scope: &GraphScope,
+            refresh_interval: Option<Duration>) {
+    let stats = graph.stats();
+
+    if let Some(filename) = flag_value(args, "--out") {
+        match export::export_stats(&stats, filename, source, &graph.selected_scope(scope)) {
+            Ok(bytes) => println!("OK: stats ({}) written to {}\n", bytes, filename),
+            Err(e) => println!("Export to {} failed: {}\n", filename, e),
+        }
+        return;
+    }
+
+    println!("Target: {}",
+             scope.target.as_ref().map(String::as_str).unwrap_or("all"));
+    if let Some(ref target) = graph.target {
+        println!("Selected target: {} (1 of {} loaded)",
+                 target,
+                 graph.targets().len());
+    }
+    if let Some((ref origin, ref channel)) = scope.channel {
+        println!("Channel: {}/{}", origin, channel);
+    }
+    println!("Visibility: {}", scope.visibility);
+    if scope.latest_only {
+        println!("Packages: latest only");
+    }
+    println!("Graph built: {} ({} ago)",
+             format_timestamp(Some(graph.built_at)),
+             format_age(Utc::now().timestamp() - graph.built_at));
+    if let Some(interval) = refresh_interval {
+        println!("Auto-refresh: every {} sec", interval.as_secs());
+    }
+    println!("Node count: {}", stats.node_count);
+    println!("Edge count: {}", stats.edge_count);
+    println!("Connected components: {}", stats.connected_comp);
+    println!("Is cyclic: {}", stats.is_cyclic);
+    println!("Origins: {}", stats.origins.len());
+    println!("Dependencies per package: max {}, mean {:.2}, median {}",
+             stats.deps_degree.max,
+             stats.deps_degree.mean,
+             stats.deps_degree.median);
+    println!("Dependents per package: max {}, mean {:.2}, median {}",
+             stats.rdeps_degree.max,
+             stats.rdeps_degree.mean,
+             stats.rdeps_degree.median);
+}
+
+// Switches to the graphs of a target that is loaded, returning false if the
+// graph has to be rebuilt for the target instead
+fn do_select_target(graph: &mut Graphs, args: &[&str]) -> bool {
+    let target = match args.get(0) {
+        None | Some(&"all") => return false,
+        Some(value) => {
+            match parse_target(value) {
+                Ok(target) => target,
+                Err(e) => {
+                    println!("{}\n", e);
+                    return true;
+                }
+            }
+        }
+    };
+
+    if !graph.select_target(&target) {
+        return false;
+    }
+    println!("Selected target: {} ({} nodes)\n", target, graph.counts().0);
+    true
+}
+
+// Lists the targets that have graphs loaded, with their node counts, marking
+// the selected one
+fn do_targets(graph: &Graphs) {
+    let targets = graph.targets();
+    if targets.is_empty() {
+        println!("The graph is not split by target, it has the packages of every target it was \
+                  loaded with\n");
+        return;
+    }
+
+    for (target, node_count) in &targets {
+        let marker = if graph.target.as_ref() == Some(target) {
+            "*"
+        } else {
+            " "
+        };
+        println!("{} {} ({} nodes)", marker, target, node_count);
+    }
+    println!("\nOK: {} targets\n", targets.len());
+}
+
+// Rebuilds the graph from the packages for the target, or for all targets
+// with 'all', keeping the selected target if it is still loaded. The current
+// graph is kept if the rebuild fails.
+fn do_target(datastore: &dyn PackageStore,
+             graph: &mut Graphs,
+             watermark: &mut Option<i64>,
+             scope: &mut GraphScope,
+             args: &[&str],
+             threads: usize) {
+    let new_target = match args.get(0) {
+        None => {
+            println!("Target: {}",
+                     scope.target.as_ref().map(String::as_str).unwrap_or("all"));
+            if let Some(ref target) = graph.target {
+                println!("Selected target: {}", target);
+            }
+            println!();
+            return;
+        }
+        Some(&"all") => None,
+        Some(value) => {
+            match parse_target(value) {
+                Ok(new_target) => Some(new_target),
+                Err(e) => {
+                    println!("{}\n", e);
+                    return;
+                }
+            }
+        }
+    };
+
+    let new_scope = GraphScope { target: new_target,
+                                 ..scope.clone() };
+    datastore.reset_metrics();
+    match fetch_graph(datastore, &new_scope, threads, false) {
+        Ok((new_graph, new_watermark)) => {
+            reselect_target(graph, new_graph);
+            *watermark = Some(new_watermark);
+            *scope = new_scope;
+            println!();
+        }
+        Err(e) => println!("Failed to build graph, keeping the current one: {}\n", e),
+    }
+}
+
+// Extends a lazy graph with the packages of the origin, unless it is already
+// loaded. The origin is only recorded as loaded once all its packages are.
+fn do_load(datastore: &dyn PackageStore, graph: &mut Graphs, scope: &GraphScope, args: &[&str]) {
+    let origin = match args.get(0) {
+        Some(origin) => origin.to_lowercase(),
+        None => {
+            println!("Missing origin name\n");
+            return;
+        }
+    };
+    match graph.loaded_origins {
+        None => {
+            println!("The graph has all the origins, start with --lazy to load them one at a \
+                      time\n");
+            return;
+        }
+        Some(ref loaded) if loaded.contains(&origin) => {
+            println!("Origin {} is already loaded\n", origin);
+            return;
+        }
+        Some(_) => (),
+    }
+
+    let start_time = PreciseTime::now();
+    let before = graph.stats();
+    let mut packages = datastore.get_origin_graph_packages(scope, &origin);
+    for next in packages.by_ref() {
+        match next {
+            Ok(next) => {
+                graph.extend(&next);
+            }
+            Err(e) => {
+                println!("Failed to load origin {}, the graph may have some of its packages: \
+                          {}\n",
+                         origin, e);
+                return;
+            }
+        }
+    }
+    let after = graph.stats();
+    let end_time = PreciseTime::now();
+
+    if !packages.excluded().is_empty() {
+        println!("{} private or hidden packages left out",
+                 packages.excluded().len());
+    }
+
+    println!("OK: {} packages read, {:+} nodes, {:+} edges ({} sec)",
+             packages.rows_read(),
+             after.node_count as i64 - before.node_count as i64,
+             after.edge_count as i64 - before.edge_count as i64,
+             start_time.to(end_time));
+
+    if let Some(ref mut loaded) = graph.loaded_origins {
+        loaded.insert(origin);
+    }
+    let unloaded = graph.unloaded_origins(&graph.node_names());
+    if !unloaded.is_empty() {
+        println!("Dependencies on origins that are not loaded: {}",
+                 Vec::from_iter(unloaded).join(", "));
+    }
+    println!();
+}
+
+// Warns that the results of a command may be incomplete when the graph is
+// lazy and the command looked at packages of origins that are not loaded
+fn warn_if_incomplete(graph: &Graphs, command: &str, args: &[&str]) {
+    let loaded = match graph.loaded_origins {
+        Some(ref loaded) => loaded,
+        None => return,
+    };
+
+    match command {
+        "resolve" | "deps" | "check" | "rdeps" | "dependents" | "age" | "fanout" => {
+            let name = match args.get(0) {
+                Some(arg) => arg.to_lowercase().split('/').take(2).collect::<Vec<_>>().join("/"),
+                None => return,
+            };
+            let mut names = graph.deps_closure(&name, EdgeFilter::All).unwrap_or_default();
+            names.push(name);
+            let unloaded = graph.unloaded_origins(&names);
+            if !unloaded.is_empty() {
+                println!("Warning: the results may be incomplete, origins {} are not loaded\n",
+                         Vec::from_iter(unloaded).join(", "));
+            }
+            if command == "rdeps" || command == "dependents" {
+                println!("Warning: only the dependents in the loaded origins ({}) are included\n",
+                         loaded_list(loaded));
+            }
+        }
+        "top" | "find" | "stats" | "oldest" | "bottlenecks" | "components" | "export" => {
+            println!("Warning: only the loaded origins ({}) are in the graph\n",
+                     loaded_list(loaded));
+        }
+        _ => (),
+    }
+}
+
+fn loaded_list(loaded: &BTreeSet<String>) -> String {
+    if loaded.is_empty() {
+        "none".to_string()
+    } else {
+        Vec::from_iter(loaded.iter().cloned()).join(", ")
+    }
+}
+
+// Rebuilds the graph from the database, keeping the current one if that fails
+fn do_reload(datastore: &dyn PackageStore,
+             graph: &mut Graphs,
+             watermark: &mut Option<i64>,
+             scope: &GraphScope,
+             threads: usize) {
+    datastore.reset_metrics();
+    match fetch_graph(datastore, scope, threads, false) {
+        Ok((new_graph, new_watermark)) => {
+            reselect_target(graph, new_graph);
+            *watermark = Some(new_watermark);
+            println!();
+        }
+        Err(e) => println!("Failed to build graph, keeping the current one: {}\n", e),
+    }
+}
+
+// Replaces the graph with a rebuilt one, selecting the same target as before
+// if the rebuilt graph has it, and keeping the set of dependencies in use and
+// the aliases
+fn reselect_target(graph: &mut Graphs, new_graph: Graphs) {
+    let aliases = graph.aliases();
+    let previous = mem::replace(graph, new_graph);
+    graph.set_build_deps(previous.use_build_deps);
+    if let Some(target) = previous.target {
+        if graph.target.as_ref() != Some(&target) && graph.select_target(&target) {
+            println!("Selected target: {}", target);
+        }
+    }
+    apply_aliases(graph, aliases.iter().map(|(old, new)| (old.as_str(), new.as_str())));
+}
+
+// Adds the aliases to the graph, skipping those it already has, and prints
+// what each one did
+fn apply_aliases<'a, I>(graph: &mut Graphs, aliases: I)
+    where I: IntoIterator<Item = (&'a str, &'a str)>
+{
+    for (old, new) in aliases {
+        if graph.alias_of(old).as_ref().map(String::as_str) == Some(new) {
+            continue;
+        }
+        match graph.add_alias(old, new) {
+            Ok((applied, rejected)) => {
+                println!("Alias {} -> {}: {} dependents moved to {}",
+                         old,
+                         new,
+                         applied.moved.len(),
+                         new);
+                if !applied.cyclic.is_empty() {
+                    println!("  Left on {}, since {} depends on them: {}",
+                             old,
+                             new,
+                             applied.cyclic.join(", "));
+                }
+                for (target, e) in rejected {
+                    println!("  Not added for {}: {}", target, e);
+                }
+            }
+            Err(e) => println!("Alias {} -> {} not added: {}", old, new, e),
+        }
+    }
+}
+
+// Lists the aliases of the graph, or adds those of the file
+fn do_aliases(graph: &mut Graphs, args: &[&str]) {
+    match args.get(0) {
+        None | Some(&"list") => {
+            let aliases = graph.aliases();
+            if aliases.is_empty() {
+                println!("No aliases");
+            } else {
+                println!("Aliases: {}", aliases.len());
+                for (old, new) in aliases {
+                    println!("  {} -> {}", old, new);
+                }
+            }
+        }
+        Some(filename) => {
+            match Aliases::load(filename) {
+                Ok(aliases) => {
+                    println!("OK: {} aliases read from {}", aliases.len(), filename);
+                    apply_aliases(graph, aliases.iter());
+                }
+                Err(e) => println!("Reading aliases from {} failed: {}", filename, e),
+            }
+        }
+    }
+
+    println!();
+}
+
+// Rebuilds the graph on a background thread every interval, for the scope
+// the commands last used. Each rebuilt graph, or the failure to build it, is
+// published for the commands to swap in between two commands, so that a
+// command only ever sees the graph it started with. Only the last one
+// published is kept.
+struct Refresher {
+    interval:  Duration,
+    scope:     Arc<Mutex<GraphScope>>,
+    published: Arc<Mutex<Option<Refresh>>>,
+}
+
+// A graph rebuilt by the refresher, with its watermark, or why the rebuild
+// failed, along with the scope it was built for
+struct Refresh {
+    scope: GraphScope,
+    built: Result<(Graphs, i64), String>,
+}
+
+impl Refresher {
+    fn spawn(datastore: Box<dyn PackageStore + Send>,
+             scope: &GraphScope,
+             interval: Duration,
+             threads: usize)
+             -> Self {
+        let refresher = Refresher { interval,
+                                    scope: Arc::new(Mutex::new(scope.clone())),
+                                    published: Arc::new(Mutex::new(None)) };
+        let scope = Arc::clone(&refresher.scope);
+        let published = Arc::clone(&refresher.published);
+        thread::spawn(move || {
+            loop {
+                thread::sleep(interval);
+                let scope = scope.lock().expect("Refresh scope mutex is poisoned!").clone();
+                let built =
+                    fetch_graph(&*datastore, &scope, threads, true).map_err(|e| e.to_string());
+                *published.lock().expect("Refresh mutex is poisoned!") =
+                    Some(Refresh { scope, built });
+            }
+        });
+        refresher
+    }
+
+    // Makes the next rebuilds use the scope, after the target was switched
+    fn set_scope(&self, scope: &GraphScope) {
+        *self.scope.lock().expect("Refresh scope mutex is poisoned!") = scope.clone();
+    }
+
+    fn take(&self) -> Option<Refresh> {
+        self.published.lock().expect("Refresh mutex is poisoned!").take()
+    }
+}
+
+// Swaps in the graph the refresher rebuilt since the last command, keeping
+// the current graph if the rebuild failed. A graph built for another scope,
+// from before the target was switched, is dropped.
+fn swap_refreshed(refresher: &Refresher,
+                  graph: &mut Graphs,
+                  watermark: &mut Option<i64>,
+                  scope: &GraphScope) {
+    let refresh = match refresher.take() {
+        Some(refresh) => refresh,
+        None => return,
+    };
+    match refresh.built {
+        Ok(_) if refresh.scope != *scope => (),
+        Ok((new_graph, new_watermark)) => {
+            let (nodes, edges) = new_graph.counts();
+            println!("Auto-refresh: graph built {} swapped in, {:+} nodes, {:+} edges",
+                     format_timestamp(Some(new_graph.built_at)),
+                     nodes as i64 - graph.counts().0 as i64,
+                     edges as i64 - graph.counts().1 as i64);
+            reselect_target(graph, new_graph);
+            *watermark = Some(new_watermark);
+            println!();
+        }
+        Err(e) => {
+            println!("Auto-refresh failed, keeping the graph built {}: {}\n",
+                     format_timestamp(Some(graph.built_at)),
+                     e)
+        }
+    }
+}
+
+// Extends the graph with the packages created since the watermark, and
+// advances the watermark past them
+fn do_update(datastore: &dyn PackageStore,
+             graph: &mut Graphs,
+             watermark: &mut Option<i64>,
+             scope: &GraphScope) {
+    let after = match *watermark {
+        Some(after) => after,
+        None if graph.loaded_origins.is_some() => {
+            println!("The update command is not available for a lazy graph, reload builds the \
+                      full graph\n");
+            return;
+        }
+        None => {
+            println!("The graph was not built from the database, reload it first\n");
+            return;
+        }
+    };
+
+    let start_time = PreciseTime::now();
+    let before = graph.stats();
+    let mut packages = datastore.get_job_graph_packages_after(scope, after);
+    let (mut added, mut updated) = (0, 0);
+    let mut changes = Vec::new();
+    let mut failure = None;
+
+    for next in packages.by_ref() {
+        let next = match next {
+            Ok(next) => next,
+            Err(e) => {
+                failure = Some(e);
+                break;
+            }
+        };
+
+        // The packages of the other targets go to their graphs unreported
+        let delta = match graph.extend(&next) {
+            Some(delta) => delta,
+            None => continue,
+        };
+        if delta.latest_changed() {
+            updated += 1;
+        } else if delta.new_latest.is_some() {
+            added += 1;
+        }
+        changes.extend(describe_delta(&delta));
+    }
+
+    *watermark = Some(packages.watermark());
+    let after = graph.stats();
+    let end_time = PreciseTime::now();
+
+    match failure {
+        Some(e) => println!("Failed to fetch new packages: {}", e),
+        None => graph.built_at = Utc::now().timestamp(),
+    }
+
+    if !packages.excluded().is_empty() {
+        println!("{} private or hidden packages left out",
+                 packages.excluded().len());
+    }
+
+    println!("OK: {} new packages, {} new names, {} latest updated, {:+} nodes, {:+} edges ({} \
+              sec)\n",
+             packages.rows_read(),
+             added,
+             updated,
+             after.node_count as i64 - before.node_count as i64,
+             after.edge_count as i64 - before.edge_count as i64,
+             start_time.to(end_time));
+
+    for change in &changes {
+        println!("{}", change);
+    }
+    if !changes.is_empty() {
+        println!();
+    }
+}
+
+// The lines describing what extending the graph with a package changed, or
+// none if it was older than the latest of its name
+fn describe_delta(delta: &ExtendDelta) -> Vec<String> {
+    let mut lines = Vec::new();
+    let ident = match delta.new_latest {
+        Some(ref ident) => ident,
+        None => return lines,
+    };
+
+    match delta.previous_latest {
+        Some(ref previous) if delta.latest_changed() => {
+            lines.push(format!("~ {} (was {})", ident, previous))
+        }
+        Some(_) => lines.push(format!("= {} (rebuilt the same ident)", ident)),
+        None if delta.new_node => lines.push(format!("+ {} (new node)", ident)),
+        None => lines.push(format!("+ {} (first package of the name)", ident)),
+    }
+    for name in &delta.new_dep_nodes {
+        lines.push(format!("    + node {} (dependency only)", name));
+    }
+    for (pkg, dep) in &delta.added_edges {
+        lines.push(format!("    + edge {} -> {}", pkg, dep));
+    }
+    for (pkg, dep) in &delta.removed_edges {
+        lines.push(format!("    - edge {} -> {}", pkg, dep));
+    }
+    for (pkg, dep) in &delta.cyclic_edges {
+        lines.push(format!("    ! edge {} -> {} left out, it would create a cycle", pkg, dep));
+    }
+    lines
+}
+
+// Prints a page of the ranking by reverse dependency count, numbering the
+// packages by their rank in the whole ranking
+fn do_top(graph: &PackageGraph,
+          args: &[&str],
+          default_max: usize,
+          cancel: &Cancel)
+          -> Result<(), Cancelled> {
+    const USAGE: &str = "Usage: top [<count>] [<offset>], with a count of at least 1\n";
+
+    let (count, offset) = match (args.get(0).map(|x| parse_max(x)),
+                                 args.get(1).map(|x| x.parse::<usize>()))
+    {
+        (None, _) => (default_max, 0),
+        (Some(Some(count)), None) => (count, 0),
+        (Some(Some(count)), Some(Ok(offset))) => (count, offset),
+        _ => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+
+    let start_time = PreciseTime::now();
+    let top = graph.top_page(offset, count, EdgeFilter::All, cancel)?;
+    let end_time = PreciseTime::now();
+
+    println!("OK: {} items of {} ranked, showing up to {} ({} sec)\n",
+             top.len(),
+             graph.counts().0,
+             count,
+             start_time.to(end_time));
+
+    for (rank, (name, count)) in top.into_iter().enumerate() {
+        println!("{}. {}: {}", offset + rank + 1, name, count);
+    }
+    println!();
+    Ok(())
+}
+
+fn do_find(graph: &PackageGraph, args: &[&str], default_max: usize) {
+    let mut mode = SearchMode::Substring;
+    let mut case_sensitive = false;
+    let mut with_metadata = false;
+    let mut positional = Vec::new();
+    for arg in args {
+        match *arg {
+            "-e" => mode = SearchMode::Exact,
+            "-p" => mode = SearchMode::Prefix,
+            "-g" => mode = SearchMode::Glob,
+            "-c" => case_sensitive = true,
+            "-m" => with_metadata = true,
+            _ => positional.push(*arg),
+        }
+    }
+
+    let usage = "Usage: find [-e|-p|-g] [-c] [-m] <term> [<max>], with a max of at least 1\n";
+    // Quotes are kept by the command line, so globs can be written as 'core/*'
+    let phrase = match positional.get(0) {
+        Some(phrase) => phrase.trim_matches(|c| c == '\'' || c == '"'),
+        None => {
+            println!("Missing search term\n{}", usage);
+            return;
+        }
+    };
+    let max = match positional.get(1).map(|x| parse_max(x)) {
+        None => default_max,
+        Some(Some(max)) => max,
+        Some(None) => {
+            println!("{}", usage);
+            return;
+        }
+    };
+
+    let start_time = PreciseTime::now();
+    let v = graph.search_with(phrase, mode, case_sensitive);
+    let end_time = PreciseTime::now();
+
+    println!("OK: {} items, showing up to {} ({} sec)\n",
+             v.len(),
+             max,
+             start_time.to(end_time));
+
+    if v.is_empty() {
+        println!("No matching packages found")
+    } else {
+        for s in v.iter().take(max) {
+            match graph.metadata(s) {
+                Some(ref metadata) if with_metadata => {
+                    println!("{} ({})", s, describe_metadata(metadata))
+                }
+                _ => println!("{}", s),
+            }
+        }
+        if v.len() > max {
+            println!("... {} more", v.len() - max);
+        }
+    }
+
+    println!();
+}
+
+// Describes the target and the release time of the latest package of a name
+fn describe_metadata(metadata: &NodeMetadata) -> String {
+    let target = metadata.target.as_deref().unwrap_or("unknown target");
+    match metadata.released {
+        Some(secs) => {
+            format!("{}, released {}",
+                    target,
+                    NaiveDateTime::from_timestamp(secs, 0).format("%Y-%m-%d %H:%M:%S UTC"))
+        }
+        None => format!("{}, release time unknown", target),
+    }
+}
+
+// Resolves 'origin/name' to its latest ident, or the newest ident whose version
+// satisfies the requirement of 'origin/name@<version>' (or '=<version>' for an
+// exact match) or 'origin/name/<version>'. -m adds the target and the release
+// time of the latest package.
+fn do_resolve(graph: &PackageGraph, args: &[&str]) {
+    let with_metadata = args.contains(&"-m");
+    let arg = match args.iter().find(|arg| **arg != "-m") {
+        Some(arg) => arg.to_lowercase(),
+        None => {
+            println!("Missing package name\n");
+            return;
+        }
+    };
+    let arg = arg.as_str();
+    let parts: Vec<&str> = arg.splitn(2, '@').collect();
+    let (name, req) = match (parts.len(), arg.split('/').count()) {
+        (2, _) => (parts[0].to_string(), Some(parts[1])),
+        (_, 3) => {
+            match short_name(arg) {
+                Ok(name) => (name, arg.rsplit('/').next()),
+                Err(e) => {
+                    println!("{}\n", e);
+                    return;
+                }
+            }
+        }
+        (_, 4) => {
+            match lookup_name(graph, arg, true) {
+                Some(name) => (name, None),
+                None => return,
+            }
+        }
+        _ => (arg.to_string(), None),
+    };
+    let name = match graph.alias_of(&name) {
+        Some(new_name) => {
+            println!("Following the alias of {}, which was renamed to {}",
+                     name, new_name);
+            new_name
+        }
+        None => name,
+    };
+    let req = match req.map(str::parse::<VersionReq>) {
+        None => None,
+        Some(Ok(req)) => Some(req),
+        Some(Err(e)) => {
+            println!("{}\n", e);
+            return;
+        }
+    };
+
+    let start_time = PreciseTime::now();
+    let result = match req {
+        Some(ref req) => graph.resolve_version(&name, req),
+        None => {
+            match graph.resolve(&name) {
+                Some(ident) => VersionMatch::Found(ident),
+                None => VersionMatch::Unknown,
+            }
+        }
+    };
+    let end_time = PreciseTime::now();
+
+    println!("OK: ({} sec)\n", start_time.to(end_time));
+
+    match result {
+        VersionMatch::Found(s) => {
+            println!("{}", s);
+            // The other releases of the latest version are rebuilds of it
+            match graph.releases(&name) {
+                Some(ref releases) if req.is_none() && releases.len() > 1 => {
+                    println!("{} releases of this version: {}",
+                             releases.len(),
+                             releases.join(", "))
+                }
+                _ => (),
+            }
+            match (graph.resolve(&name), graph.metadata(&name)) {
+                (Some(latest), Some(ref metadata)) if with_metadata => {
+                    println!("Latest package {}: {}", latest, describe_metadata(metadata))
+                }
+                _ => (),
+            }
+        }
+        VersionMatch::NoMatch(versions) => {
+            println!("No version of {} satisfies the requirement, the graph has: {}",
+                     name,
+                     versions.join(", "))
+        }
+        VersionMatch::Unknown => {
+            let suggested = suggestions(graph, &name);
+            println!("{}", error::Error::NotInGraph(name, suggested))
+        }
+    }
+
+    println!();
+}
+
+fn do_exclude(excludes: &mut HashSet<String>, arg: &str) {
+    match arg {
+        "list" => {
+            let mut names: Vec<&String> = excludes.iter().collect();
+            names.sort();
+            println!("Excluded packages: {}", names.len());
+            for name in names {
+                println!("  {}", name);
+            }
+        }
+        "clear" => {
+            excludes.clear();
+            println!("Removed all excluded packages");
+        }
+        name => {
+            if name.split('/').count() == 2 {
+                excludes.insert(name.to_string());
+                println!("Excluding: {}", name);
+            } else {
+                println!("Excluded packages must be in 'origin/name' format");
+            }
+        }
+    }
+
+    println!();
+}
+
+fn do_rdeps(graph: &Graphs,
+            args: &[&str],
+            filter: &str,
+            excludes: &HashSet<String>,
+            default_max: usize,
+            limits: &LimitsCfg,
+            cancel: &Cancel)
+            -> Result<(), Cancelled> {
+    const USAGE: &str = "Usage: rdeps <name> [<max>] [--by-depth] [--total] \
+                         [--runtime-only|--build-only], with a max of at least 1\n";
+
+    let by_depth = args.contains(&"--by-depth");
+    let count_total = args.contains(&"--total");
+    let edge_filter = match (args.contains(&"--runtime-only"), args.contains(&"--build-only")) {
+        (false, false) => EdgeFilter::All,
+        (true, false) => EdgeFilter::RuntimeOnly,
+        (false, true) => EdgeFilter::BuildOnly,
+        (true, true) => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+    let flags = ["--by-depth", "--total", "--runtime-only", "--build-only"];
+    let args: Vec<&str> = args.iter().cloned().filter(|x| !flags.contains(x)).collect();
+    let max = match args.get(1).map(|x| parse_max(x)) {
+        None => default_max,
+        Some(Some(max)) => max,
+        Some(None) => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+    let name = match args.get(0) {
+        Some(name) => {
+            match lookup_name(graph, name, true) {
+                Some(name) => name,
+                None => return Ok(()),
+            }
+        }
+        None => {
+            println!("Missing package name\n");
+            return Ok(());
+        }
+    };
+
+    // Only the graph with the build dependencies knows the kind of each edge
+    let typed = if edge_filter == EdgeFilter::All {
+        &**graph
+    } else {
+        match graph.with_build_deps() {
+            Some(typed) => typed,
+            None => {
+                println!("Only one set of dependencies is available for a graph loaded from a \
+                          file\n");
+                return Ok(());
+            }
+        }
+    };
+
+    let start_time = PreciseTime::now();
+
+    match typed.rdeps_walk(&name, excludes, edge_filter, limits.max_traversal_nodes, cancel)? {
+        Some(walk) => {
+            // Sorting by depth needs every match, but otherwise the matches are
+            // only read up to the rows shown, and counted past them when there
+            // is a filter only if the total is asked for
+            let matches = |rdep: &RdepRef| matches_filter(rdep.name, filter);
+            let limit = limits.max_output_rows;
+            let rows = if limit != 0 { max.min(limit) } else { max };
+            let mut listed: Vec<RdepRef> = if by_depth {
+                walk.iter().filter(matches).collect()
+            } else {
+                walk.iter().filter(matches).take(rows.saturating_add(1)).collect()
+            };
+            let total = if by_depth || listed.len() <= rows {
+                Some(listed.len())
+            } else if filter.is_empty() {
+                Some(walk.len())
+            } else if count_total {
+                cancel.check()?;
+                Some(walk.iter().filter(matches).count())
+            } else {
+                None
+            };
+            let end_time = PreciseTime::now();
+
+            let items = match total {
+                Some(total) => format!("{} items", total),
+                None => format!("more than {} items", rows),
+            };
+            if excludes.is_empty() {
+                println!("OK: {}, showing up to {} ({} sec)\n",
+                         items,
+                         max,
+                         start_time.to(end_time));
+            } else {
+                println!("OK: {}, {} pruned by exclude list, showing up to {} ({} sec)\n",
+                         items,
+                         walk.pruned,
+                         max,
+                         start_time.to(end_time));
+            }
+
+            if !filter.is_empty() {
+                println!("Results filtered by: {}", filter);
+            }
+            match edge_filter {
+                EdgeFilter::All => (),
+                EdgeFilter::RuntimeOnly => println!("Following runtime dependencies only"),
+                EdgeFilter::BuildOnly => println!("Following build dependencies only"),
+            }
+            if walk.truncated {
+                print_limit_reached("max_traversal_nodes",
+                                    limits.max_traversal_nodes,
+                                    "the nearest reverse dependencies are listed, by depth");
+            }
+
+            let max = match total {
+                Some(total) => output_rows(max, total, limits),
+                None if rows < max => {
+                    print_limit_reached("max_output_rows",
+                                        limit,
+                                        &format!("showing the first {} rows", limit));
+                    rows
+                }
+                None => max,
+            };
+            if by_depth {
+                // A stable sort keeps the build order within each depth
+                listed.sort_by_key(|x| x.depth);
+            }
+            listed.truncate(max);
+
+            let mut depth = 0;
+            for rdep in listed {
+                cancel.check()?;
+                if by_depth && rdep.depth != depth {
+                    depth = rdep.depth;
+                    println!("Depth {}:", depth);
+                }
+                match graph.followed_alias(rdep.name, &name) {
+                    Some(ref old) if rdep.depth == 1 => {
+                        println!("{:>5}  {} ({}, depends on {} by its old name)",
+                                 rdep.depth, rdep.name, rdep.ident, old)
+                    }
+                    _ => println!("{:>5}  {} ({})", rdep.depth, rdep.name, rdep.ident),
+                }
+            }
+
+            match total {
+                Some(total) if total > max => println!("... {} more", total - max),
+                Some(_) => (),
+                None => println!("... and more, which --total counts"),
+            }
+        }
+        None => println!("No entries found"),
+    }
+
+    println!();
+    Ok(())
+}
+
+// The number of rows a listing prints, at most max_output_rows whatever the
+// max given, saying so when that leaves some of the total out
+fn output_rows(max: usize, total: usize, limits: &LimitsCfg) -> usize {
+    let limit = limits.max_output_rows;
+    if limit != 0 && max > limit && total > limit {
+        print_limit_reached("max_output_rows",
+                            limit,
+                            &format!("showing {} of {} rows", limit, total));
+        return limit;
+    }
+    max
+}
+
+fn print_limit_reached(limit: &str, value: usize, truncated: &str) {
+    println!("Stopped at the {} limit of {}: {}. Raise {} in the [limits] section of the \
+              config file to go further.",
+             limit, value, truncated, limit);
+}
+
+// Prints when the latest package of the name was created, along with the
+// creation times of the latest packages of its direct dependencies, flagging
+// the ones created after it
+fn do_age(graph: &PackageGraph, args: &[&str]) {
+    let name = match args.get(0) {
+        Some(name) => {
+            match lookup_name(graph, name, true) {
+                Some(name) => name,
+                None => return,
+            }
+        }
+        None => {
+            println!("Missing package name\n");
+            return;
+        }
+    };
+
+    let ident = match graph.resolve(&name) {
+        Some(ident) => ident,
+        None => {
+            println!("No matching package found\n");
+            return;
+        }
+    };
+
+    let created_at = graph.created(&name);
+    println!("{}: created {}\n", ident, format_timestamp(created_at));
+
+    let deps = graph.direct_deps(&name).unwrap_or_default();
+    let mut newer = 0;
+    println!("Dependencies:");
+    for dep in &deps {
+        let dep_created_at = graph.created(dep);
+        let flag = match (created_at, dep_created_at) {
+            (Some(pkg), Some(dep)) if dep > pkg => {
+                newer += 1;
+                "  NEWER"
+            }
+            _ => "",
+        };
+        println!("  {}: created {}{}",
+                 graph.resolve(dep).unwrap_or_else(|| dep.clone()),
+                 format_timestamp(dep_created_at),
+                 flag);
+    }
+
+    println!("\n{} of {} dependencies were created after the package\n",
+             newer,
+             deps.len());
+}
+
+// Shows the number of results top, find and rdeps list when no max is given,
+// or sets it for the rest of the session
+fn do_max_results(max_results: &mut usize, args: &[&str]) {
+    match args.get(0).map(|x| x.parse::<usize>()) {
+        None => println!("Default max results: {}\n", max_results),
+        Some(Ok(max)) => {
+            match config::check_max_results(max) {
+                Ok(max) => {
+                    *max_results = max;
+                    println!("New default max results: {}\n", max);
+                }
+                Err(e) => println!("{}\n", e),
+            }
+        }
+        Some(Err(_)) => println!("Usage: max-results [<count>]\n"),
+    }
+}
+
+// Prints the latest packages matching the filter that were created the
+// longest ago
+fn do_oldest(graph: &PackageGraph, args: &[&str], filter: &str) {
+    let count = match args.get(0).map(|x| x.parse::<usize>()) {
+        None => 10,
+        Some(Ok(count)) => count,
+        Some(Err(_)) => {
+            println!("Usage: oldest [<count>]\n");
+            return;
+        }
+    };
+
+    let start_time = PreciseTime::now();
+    let oldest = graph.oldest(filter, count);
+    let end_time = PreciseTime::now();
+
+    println!("OK: {} items ({} sec)\n", oldest.len(), start_time.to(end_time));
+
+    if !filter.is_empty() {
+        println!("Results filtered by: {}\n", filter);
+    }
+
+    if oldest.is_empty() && graph.oldest("", 1).is_empty() {
+        println!("No creation times are known, they are only read when the graph is built from \
+                  the database");
+    }
+
+    for (ident, created_at) in oldest {
+        println!("{}  {}", format_timestamp(Some(created_at)), ident);
+    }
+
+    println!();
+}
+
+fn do_bottlenecks(graph: &PackageGraph, args: &[&str]) {
+    let count = match args.get(0).map(|x| x.parse::<usize>()) {
+        None => 10,
+        Some(Ok(count)) => count,
+        Some(Err(_)) => {
+            println!("Usage: bottlenecks [<count>]\n");
+            return;
+        }
+    };
+
+    let start_time = PreciseTime::now();
+    let mut bottlenecks = graph.bottlenecks();
+    let end_time = PreciseTime::now();
+
+    println!("OK: {} articulation points ({} sec)\n",
+             bottlenecks.len(),
+             start_time.to(end_time));
+
+    bottlenecks.truncate(count);
+    for (name, cut_off) in bottlenecks {
+        println!("{}: {} packages cut off", name, cut_off);
+    }
+    println!();
+}
+
+fn do_dups(graph: &PackageGraph, filter: &str) {
+    let start_time = PreciseTime::now();
+    let dups: Vec<(String, Vec<(String, usize)>)> =
+        graph.duplicates()
+             .into_iter()
+             .filter(|&(_, ref copies)| copies.iter().any(|c| matches_filter(&c.0, filter)))
+             .collect();
+    let end_time = PreciseTime::now();
+
+    println!("OK: {} items ({} sec)\n", dups.len(), start_time.to(end_time));
+
+    if !filter.is_empty() {
+        println!("Results filtered by: {}\n", filter);
+    }
+
+    for (name, copies) in dups {
+        println!("{}", name);
+        for (copy, rdep_count) in copies {
+            println!("  {} ({} direct rdeps)", copy, rdep_count);
+        }
+    }
+    println!();
+}
+
+// Returns the names that are in the graph, listing the others
+fn known_names<'a>(graph: &PackageGraph, names: &'a [String]) -> Vec<&'a str> {
+    let (known, unknown): (Vec<&str>, Vec<&str>) =
+        names.iter()
+             .map(|x| x.as_str())
+             .partition(|x| graph.resolve(x).is_some());
+
+    for name in &unknown {
+        println!("Package not found: {}", name);
+    }
+    known
+}
+
+fn print_cost_summary(summary: &CostSummary, count: usize) {
+    println!("Explicit costs: {} of {} packages, the others cost {}",
+             summary.explicit,
+             count,
+             costs::DEFAULT_COST);
+}
+
+fn do_plan(graph: &PackageGraph, costs: &Costs, excludes: &HashSet<String>, args: &[&str]) {
+    if args.is_empty() {
+        println!("Missing package name\n");
+        return;
+    }
+
+    let start_time = PreciseTime::now();
+    let names: Vec<String> = args.iter().map(|x| x.to_lowercase()).collect();
+    let known = known_names(graph, &names);
+    if known.is_empty() {
+        println!();
+        return;
+    }
+
+    let (plan, pruned) = graph.plan(&known, excludes).unwrap();
+    let count: usize = plan.iter().map(|group| group.len()).sum();
+    let cycles = plan.iter().filter(|group| group.len() > 1).count();
+    let summary = costs.summary(&plan.concat());
+    let end_time = PreciseTime::now();
+
+    if excludes.is_empty() {
+        println!("OK: {} packages, {} cycles, cost {} ({} sec)",
+                 count,
+                 cycles,
+                 summary.total,
+                 start_time.to(end_time));
+    } else {
+        println!("OK: {} packages, {} cycles, {} pruned by exclude list, cost {} ({} sec)",
+                 count,
+                 cycles,
+                 pruned,
+                 summary.total,
+                 start_time.to(end_time));
+    }
+    print_cost_summary(&summary, count);
+    println!();
+
+    for (i, group) in plan.iter().enumerate() {
+        if group.len() == 1 {
+            println!("{}. {}", i + 1, group[0]);
+        } else {
+            println!("{}. cycle: {}", i + 1, group.join(", "));
+        }
+    }
+
+    println!();
+}
+
+// Prints the levels of the rebuild of the packages, with the time each level
+// takes on the given number of workers. A level starts once the one before it
+// is done, so the estimate is the sum of the times of the levels.
+fn do_build_levels(graph: &PackageGraph,
+                   costs: &Costs,
+                   excludes: &HashSet<String>,
+                   args: &[&str]) {
+    const USAGE: &str = "Usage: build-levels <name>... [--workers <n>]\n";
+
+    let mut workers = 1;
+    let mut names = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if *arg == "--workers" {
+            match args.next().map(|x| x.parse::<usize>()) {
+                Some(Ok(n)) if n > 0 => workers = n,
+                _ => {
+                    println!("{}", USAGE);
+                    return;
+                }
+            }
+        } else {
+            names.push(arg.to_lowercase());
+        }
+    }
+    if names.is_empty() {
+        println!("Missing package name\n{}", USAGE);
+        return;
+    }
+
+    let start_time = PreciseTime::now();
+    let known = known_names(graph, &names);
+    if known.is_empty() {
+        println!();
+        return;
+    }
+
+    let (levels, pruned) = graph.build_levels(&known, excludes).unwrap();
+    let group_cost = |group: &[String]| costs.summary(group).total;
+    let times: Vec<u64> =
+        levels.iter()
+              .map(|level| {
+                  let jobs: Vec<u64> = level.iter().map(|group| group_cost(group)).collect();
+                  costs::schedule(&jobs, workers)
+              })
+              .collect();
+    let members: Vec<String> = levels.concat().concat();
+    let summary = costs.summary(&members);
+    let end_time = PreciseTime::now();
+
+    if excludes.is_empty() {
+        println!("OK: {} levels, {} packages, cost {} ({} sec)",
+                 levels.len(),
+                 members.len(),
+                 summary.total,
+                 start_time.to(end_time));
+    } else {
+        println!("OK: {} levels, {} packages, {} pruned by exclude list, cost {} ({} sec)",
+                 levels.len(),
+                 members.len(),
+                 pruned,
+                 summary.total,
+                 start_time.to(end_time));
+    }
+    print_cost_summary(&summary, members.len());
+    println!("Estimated time with {} workers: {}\n",
+             workers,
+             times.iter().sum::<u64>());
+
+    for (i, (level, time)) in levels.iter().zip(&times).enumerate() {
+        let level_members = level.concat();
+        println!("Level {}: {} packages, cost {}, time {}",
+                 i + 1,
+                 level_members.len(),
+                 costs.summary(&level_members).total,
+                 time);
+        for group in level {
+            if group.len() == 1 {
+                println!("  {} ({})", group[0], group_cost(group));
+            } else {
+                println!("  cycle: {} ({})", group.join(", "), group_cost(group));
+            }
+        }
+    }
+
+    println!();
+}
+
+fn do_impact(graph: &PackageGraph, costs: &Costs, excludes: &HashSet<String>, args: &[&str]) {
+    if args.is_empty() {
+        println!("Missing package name\n");
+        return;
+    }
+
+    let start_time = PreciseTime::now();
+    let names: Vec<String> = args.iter().map(|x| x.to_lowercase()).collect();
+    let known = known_names(graph, &names);
+    if known.is_empty() {
+        println!();
+        return;
+    }
+
+    let (plan, pruned) = graph.plan(&known, excludes).unwrap();
+    let members = plan.concat();
+    let summary = costs.summary(&members);
+    let end_time = PreciseTime::now();
+
+    if excludes.is_empty() {
+        println!("OK: {} packages to rebuild, estimated cost {} ({} sec)",
+                 members.len(),
+                 summary.total,
+                 start_time.to(end_time));
+    } else {
+        println!("OK: {} packages to rebuild, {} pruned by exclude list, estimated \
+                  cost {} ({} sec)",
+                 members.len(),
+                 pruned,
+                 summary.total,
+                 start_time.to(end_time));
+    }
+    print_cost_summary(&summary, members.len());
+    println!();
+}
+
+fn do_costs(costs: &mut Costs, args: &[&str]) {
+    match args.get(0) {
+        None => {
+            match costs.source {
+                Some(ref source) => {
+                    println!("{} costs read from {}, the others cost {}",
+                             costs.len(),
+                             source,
+                             costs::DEFAULT_COST)
+                }
+                None => println!("No costs loaded, every package costs {}", costs::DEFAULT_COST),
+            }
+        }
+        Some(&"clear") => {
+            *costs = Costs::default();
+            println!("Cleared the costs, every package costs {}", costs::DEFAULT_COST);
+        }
+        Some(filename) => {
+            let start_time = PreciseTime::now();
+            match Costs::load(filename) {
+                Ok(loaded) => {
+                    let end_time = PreciseTime::now();
+                    println!("OK: {} costs read from {} ({} sec)",
+                             loaded.len(),
+                             filename,
+                             start_time.to(end_time));
+                    *costs = loaded;
+                }
+                Err(e) => println!("Reading costs from {} failed: {}", filename, e),
+            }
+        }
+    }
+
+    println!();
+}
+
+fn do_dependents(graph: &PackageGraph, args: &[&str], filter: &str, limits: &LimitsCfg) {
+    let max = match args.get(1).map(|x| parse_max(x)) {
+        None => 10,
+        Some(Some(max)) => max,
+        Some(None) => {
+            println!("Usage: dependents <name>|<ident> [<max>], with a max of at least 1\n");
+            return;
+        }
+    };
+
+    let name = match args.get(0) {
+        Some(name) => {
+            match lookup_name(graph, name, true) {
+                Some(name) => name,
+                None => return,
+            }
+        }
+        None => {
+            println!("Missing package name\n");
+            return;
+        }
+    };
+
+    let start_time = PreciseTime::now();
+
+    match graph.dependents(&name) {
+        Some(dependents) => {
+            let end_time = PreciseTime::now();
+            let filtered: Vec<(String, String)> =
+                dependents.into_iter()
+                          .filter(|&(ref x, _)| matches_filter(x, filter))
+                          .collect();
+
+            println!("OK: {} items ({} sec)\n",
+                     filtered.len(),
+                     start_time.to(end_time));
+
+            if !filter.is_empty() {
+                println!("Results filtered by: {}", filter);
+            }
+
+            let max = output_rows(max, filtered.len(), limits);
+            for (name, ident) in filtered.iter().take(max) {
+                println!("{} ({})", name, ident);
+            }
+
+            if filtered.len() > max {
+                println!("... {} more", filtered.len() - max);
+            }
+        }
+        None => println!("No entries found"),
+    }
+
+    println!();
+}
+
+// Removes the package from the in-memory graph only, to see what retiring
+// it would leave behind
+fn do_remove(graph: &mut Graphs, args: &[&str]) {
+    let name = match args.get(0) {
+        Some(name) if name.contains('/') => name.to_lowercase(),
+        _ => {
+            println!("Missing package name\n");
+            return;
+        }
+    };
+
+    let start_time = PreciseTime::now();
+
+    match graph.remove(&name) {
+        Some(removed) => {
+            let end_time = PreciseTime::now();
+            println!("OK: removed {} and {} edges ({} sec)\n",
+                     name,
+                     removed.edge_count,
+                     start_time.to(end_time));
+
+            if removed.dangling.is_empty() {
+                println!("No dependents are missing a dependency");
+            } else {
+                println!("{} dependents are now missing a dependency:",
+                         removed.dangling.len());
+                for (name, ident) in removed.dangling {
+                    println!("{} ({})", name, ident);
+                }
+            }
+            println!("\nUse 'reload' to restore the graph from the database");
+        }
+        None => println!("No matching package in the graph"),
+    }
+
+    println!();
+}
+
+fn do_fanout(graph: &PackageGraph, args: &[&str]) {
+    let max_depth = match args.get(1).map(|x| x.parse::<usize>()) {
+        None => 0,
+        Some(Ok(max_depth)) => max_depth,
+        Some(Err(_)) => {
+            println!("Usage: fanout <name> [<max_depth>]\n");
+            return;
+        }
+    };
+    let name = match args.get(0) {
+        Some(name) => {
+            match lookup_name(graph, name, true) {
+                Some(name) => name,
+                None => return,
+            }
+        }
+        None => {
+            println!("Missing package name\n");
+            return;
+        }
+    };
+
+    let start_time = PreciseTime::now();
+
+    match graph.fanout(&name, max_depth) {
+        Some(levels) => {
+            let end_time = PreciseTime::now();
+            println!("OK: {} levels ({} sec)\n",
+                     levels.len(),
+                     start_time.to(end_time));
+
+            let mut total = 0;
+            for (depth, count) in levels.iter().enumerate() {
+                total += count;
+                println!("Depth {}: {} ({} total)", depth + 1, count, total);
+            }
+        }
+        None => println!("No entries found"),
+    }
+
+    println!();
+}
+
+// Checks the graph for inconsistencies, listing each kind that was found
+fn do_validate(graph: &PackageGraph) {
+    const MAX_LISTED: usize = 10;
+
+    let start_time = PreciseTime::now();
+    let report = graph.validate();
+    let end_time = PreciseTime::now();
+
+    if report.is_valid() {
+        println!("OK: the graph is consistent ({} sec)\n", start_time.to(end_time));
+        return;
+    }
 
-    let mut cl = Copperline::new();
+    let edge = |&(ref pkg, ref dep): &(String, String)| format!("{} -> {}", pkg, dep);
+    let node = |index: &usize| format!("#{}", index);
+    let sections: Vec<(&str, Vec<String>)> =
+        vec![("Packages depending on themselves", report.self_loops.clone()),
+             ("Duplicate edges", report.duplicate_edges.iter().map(edge).collect()),
+             ("Names whose node is not in the graph", report.missing_nodes.clone()),
+             ("Unindexed nodes", report.unindexed_nodes.iter().map(node).collect()),
+             ("Latest idents not in the graph", report.stale_latest.clone()),];
+
+    let total: usize = sections.iter().map(|(_, items)| items.len()).sum();
+    println!("Found {} problems ({} sec)\n", total, start_time.to(end_time));
+    for (title, items) in sections.iter().filter(|(_, items)| !items.is_empty()) {
+        println!("{}: {}", title, items.len());
+        for item in items.iter().take(MAX_LISTED) {
+            println!("  {}", item);
+        }
+        if items.len() > MAX_LISTED {
+            println!("  ... {} more", items.len() - MAX_LISTED);
+        }
+    }
+    println!();
+}
+
+fn do_components(graph: &PackageGraph, args: &[&str]) {
+    let min_size = match args.get(0).map(|x| x.parse::<usize>()) {
+        None => 1,
+        Some(Ok(min_size)) => min_size,
+        Some(Err(_)) => {
+            println!("Usage: components [<min_size>]\n");
+            return;
+        }
+    };
+
+    let start_time = PreciseTime::now();
+    let components = graph.components();
+    let end_time = PreciseTime::now();
+
+    println!("OK: {} components ({} sec)\n",
+             components.len(),
+             start_time.to(end_time));
+
+    let mut small_count = 0;
+    let mut small_total = 0;
+
+    for (i, members) in components.iter().enumerate() {
+        if i == 0 {
+            println!("1. main component: {} packages", members.len());
+            continue;
+        }
+
+        if members.len() < min_size {
+            small_count += 1;
+            small_total += members.len();
+            continue;
+        }
+
+        // Show the members with the most reverse dependencies as representatives
+        let mut ranked: Vec<(&String, usize)> =
+            members.iter()
+                   .map(|x| (x, graph.fanout(x, 0).unwrap_or_default().iter().sum()))
+                   .collect();
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+        let reps: Vec<String> = ranked.iter()
+                                      .take(3)
+                                      .map(|&(name, count)| format!("{} ({})", name, count))
+                                      .collect();
+
+        println!("{}. {} packages: {}", i + 1, members.len(), reps.join(", "));
+    }
+
+    if small_count > 0 {
+        println!("Components smaller than {}: {} ({} packages)",
+                 min_size, small_count, small_total);
+    }
+
+    println!();
+}
 
-    println!("Connecting to {}", config.datastore.database);
+const VERIFY_SAMPLE_SIZE: usize = 100;
+const FETCH_BATCH_SIZE: usize = 100;
 
-    let datastore = DataStore::new(&config);
-    datastore.setup().unwrap();
+// Fetches the package records for the idents from the datastore in batches,
+// calling f for each one. Returns the number of packages that could not be
+// fetched.
+fn fetch_batched<F>(datastore: &dyn PackageStore, idents: &[String], mut f: F) -> usize
+    where F: FnMut(originsrv::OriginPackage)
+{
+    let mut failures = 0;
 
-    println!("Building graph... please wait.");
+    for batch in idents.chunks(FETCH_BATCH_SIZE) {
+        match datastore.get_job_graph_packages_by_idents(batch) {
+            Ok(packages) => {
+                failures += batch.len() - packages.len();
+                for package in packages {
+                    f(package);
+                }
+            }
+            Err(e) => {
+                println!("Failed to fetch {} packages: {}", batch.len(), e);
+                failures += batch.len();
+            }
+        }
+    }
+
+    failures
+}
+
+// Builds a graph from the database of the config file, with the same scope as
+// the current graph, and compares their latest packages. Only the latest
+// idents of the other graph are kept, and only while comparing.
+fn do_compare_env(graph: &PackageGraph, scope: &GraphScope, config_path: &str, source: &str) {
+    let other = match Config::from_file(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("Failed to read {}: {}\n", config_path, e);
+            return;
+        }
+    };
+    let other_source = cache_source(&other);
+
+    println!("Connecting to {}", other_source);
+    let start_time = PreciseTime::now();
+    let other_latest = match DataStore::new(&other).and_then(|ds| fetch_latest(&ds, scope)) {
+        Ok(latest) => latest,
+        Err(e) => {
+            println!("Failed to build the graph of {}: {}\n", other_source, e);
+            return;
+        }
+    };
+
+    let diffs = compare::compare_latest(&compare::latest_by_name(graph), &other_latest);
+    let end_time = PreciseTime::now();
 
+    println!("A: {}", source);
+    println!("B: {} ({} names)\n", other_source, other_latest.len());
+
+    for (origin, diff) in &diffs {
+        println!("{}: {} only in A, {} only in B, {} differ",
+                 origin,
+                 diff.only_in_a.len(),
+                 diff.only_in_b.len(),
+                 diff.changed.len());
+        for ident in &diff.only_in_a {
+            println!("  A only: {}", ident);
+        }
+        for ident in &diff.only_in_b {
+            println!("  B only: {}", ident);
+        }
+        for (a, b) in &diff.changed {
+            println!("  differ: {} -> {}", a, b);
+        }
+    }
+
+    println!("\nOK: {} differences in {} origins ({} sec)\n",
+             diffs.values().map(compare::OriginDiff::count).sum::<usize>(),
+             diffs.len(),
+             start_time.to(end_time));
+}
+
+// Builds a graph of the runtime dependencies from the packages in the scope,
+// returning the latest ident of each name. The graph itself is dropped.
+fn fetch_latest(datastore: &dyn PackageStore,
+                scope: &GraphScope)
+                -> error::Result<BTreeMap<String, String>> {
     let mut graph = PackageGraph::new();
-    let packages = datastore.get_job_graph_packages().unwrap();
+    for next in datastore.get_job_graph_packages(scope) {
+        graph.extend(&next?.package, false);
+    }
+    Ok(compare::latest_by_name(&graph))
+}
+
+// Compares the number of names of each origin that have a latest package in
+// the graph with the number of distinct names in the database, counting the
+// database packages with the same target, origin, channel and visibility
+// restrictions the graph was built with
+fn do_verify_db(datastore: &dyn PackageStore, graph: &PackageGraph, scope: &GraphScope) {
+    let start_time = PreciseTime::now();
+
+    let db_counts = match datastore.get_graph_package_counts(scope) {
+        Ok(counts) => counts,
+        Err(e) => {
+            println!("Failed to count the database packages: {}\n", e);
+            return;
+        }
+    };
+
+    // Origin -> (database packages, database names, graph names)
+    let mut rows: BTreeMap<String, (i64, i64, i64)> = BTreeMap::new();
+    for counts in db_counts {
+        rows.insert(counts.origin, (counts.packages, counts.names, 0));
+    }
+    for ident in graph.latest() {
+        let origin = ident.split('/').next().unwrap_or_default().to_string();
+        rows.entry(origin).or_insert((0, 0, 0)).2 += 1;
+    }
+    let end_time = PreciseTime::now();
+
+    println!("Comparing with the database for {}\n", scope);
+    println!("{:<24} {:>12} {:>10} {:>12}",
+             "Origin", "DB packages", "DB names", "Graph names");
+
+    let mut mismatches = 0;
+    let mut totals = (0, 0, 0);
+    for (origin, &(packages, names, graph_names)) in &rows {
+        let flag = if names == graph_names {
+            ""
+        } else {
+            mismatches += 1;
+            "  MISMATCH"
+        };
+        println!("{:<24} {:>12} {:>10} {:>12}{}",
+                 origin, packages, names, graph_names, flag);
+        totals = (totals.0 + packages, totals.1 + names, totals.2 + graph_names);
+    }
+    println!("{:<24} {:>12} {:>10} {:>12}",
+             "Total", totals.0, totals.1, totals.2);
+
+    let dangling = graph.node_names().len() as i64 - totals.2;
+    println!("\n{} names in the graph are only referenced as dependencies", dangling);
+    println!("OK: {} origins, {} mismatches ({} sec)\n",
+             rows.len(),
+             mismatches,
+             start_time.to(end_time));
+}
+
+// Prints the metrics of the database queries run since the graph was built,
+// by kind of query, so slow startups and commands can be attributed
+fn do_dbstats(datastore: &dyn PackageStore) {
+    let metrics = datastore.query_metrics();
+    if metrics.is_empty() {
+        println!("No database queries have been run since the graph was built\n");
+        return;
+    }
+
+    println!("{:<40} {:>8} {:>8} {:>10} {:>10} {:>10}",
+             "Query", "Count", "Failed", "Rows", "Total (s)", "Max (ms)");
+    let mut total = QueryStats::default();
+    for (what, stats) in &metrics {
+        println!("{:<40} {:>8} {:>8} {:>10} {:>10.3} {:>10}",
+                 what,
+                 stats.queries,
+                 stats.failures,
+                 stats.rows,
+                 stats.elapsed.as_secs_f64(),
+                 stats.slowest.as_millis());
+        total.queries += stats.queries;
+        total.failures += stats.failures;
+        total.rows += stats.rows;
+        total.elapsed += stats.elapsed;
+        total.slowest = cmp::max(total.slowest, stats.slowest);
+    }
+    println!("{:<40} {:>8} {:>8} {:>10} {:>10.3} {:>10}\n",
+             "Total",
+             total.queries,
+             total.failures,
+             total.rows,
+             total.elapsed.as_secs_f64(),
+             total.slowest.as_millis());
+}
+
+fn do_verify(datastore: &dyn PackageStore,
+             graph: &PackageGraph,
+             args: &[&str],
+             use_build_deps: bool,
+             malformed: &mut MalformedIdents) {
+    let full = args.contains(&"--full");
+    let origin = args.iter()
+                     .find(|x| !x.starts_with("--"))
+                     .map(|x| x.to_lowercase())
+                     .unwrap_or_default();
+
     let start_time = PreciseTime::now();
-    let (ncount, ecount) = graph.build(packages.into_iter(), feat::is_enabled(feat::BuildDeps));
+    let prefix = format!("{}/", origin);
+
+    let mut latest: Vec<String> = graph.latest()
+                                       .into_iter()
+                                       .filter(|x| origin.is_empty() || x.starts_with(&prefix))
+                                       .collect();
+
+    let total = latest.len();
+    if !full && total > VERIFY_SAMPLE_SIZE {
+        let step = total / VERIFY_SAMPLE_SIZE;
+        latest = latest.into_iter()
+                       .step_by(step)
+                       .take(VERIFY_SAMPLE_SIZE)
+                       .collect();
+    }
+
+    println!("Verifying {} of {} packages\n", latest.len(), total);
+
+    let mut checked = 0;
+    let mut mismatches = 0;
+
+    let failures = fetch_batched(datastore, &latest, |package| {
+        let ident = package.get_ident().to_string();
+        let name = match short_name(&ident) {
+            Ok(name) => name,
+            Err(e) => {
+                println!("Skipped a package, {}", e);
+                return;
+            }
+        };
+        let mut recorded = Vec::new();
+        for dep in package.get_deps()
+                          .iter()
+                          .chain(package.get_build_deps().iter().filter(|_| use_build_deps))
+        {
+            let dep = dep.to_string();
+            match short_name(&dep) {
+                Ok(dep_name) => recorded.push(dep_name),
+                Err(_) => malformed.report(&dep, &ident),
+            }
+        }
+        recorded.sort();
+        recorded.dedup();
+
+        let edges = graph.direct_deps(&name).unwrap_or_default();
+        let missing: Vec<&String> = recorded.iter().filter(|x| !edges.contains(x)).collect();
+        let extra: Vec<&String> = edges.iter().filter(|x| !recorded.contains(x)).collect();
+
+        checked += 1;
+        if missing.is_empty() && extra.is_empty() {
+            return;
+        }
+
+        mismatches += 1;
+        println!("Mismatch: {}", ident);
+        for name in missing {
+            println!("  missing edge: {}", name);
+        }
+        for name in extra {
+            println!("  extra edge: {}", name);
+        }
+    });
+
     let end_time = PreciseTime::now();
+    println!("\nOK: {} checked, {} mismatches, {} fetch failures ({} sec)\n",
+             checked,
+             mismatches,
+             failures,
+             start_time.to(end_time));
+}
 
-    println!("OK: {} nodes, {} edges ({} sec)",
-             ncount,
-             ecount,
+fn do_pinned(datastore: &dyn PackageStore,
+             graph: &PackageGraph,
+             args: &[&str],
+             malformed: &mut MalformedIdents) {
+    let start_time = PreciseTime::now();
+    let origin = args.get(0).map(|x| x.to_lowercase()).unwrap_or_default();
+    let prefix = format!("{}/", origin);
+
+    let latest: Vec<String> = graph.latest()
+                                       .into_iter()
+                                       .filter(|x| origin.is_empty() || x.starts_with(&prefix))
+                                       .collect();
+
+    let mut pinned_count = 0;
+    let mut stale_count = 0;
+
+    let failures = fetch_batched(datastore, &latest, |package| {
+        let pins: Vec<&originsrv::OriginPackageIdent> =
+            package.get_deps()
+                   .iter()
+                   .filter(|dep| !dep.get_version().is_empty() && !dep.get_release().is_empty())
+                   .collect();
+
+        if pins.is_empty() {
+            return;
+        }
+
+        pinned_count += 1;
+        let ident = package.get_ident().to_string();
+        println!("{}", ident);
+
+        for pin in pins {
+            let pin = pin.to_string();
+            let name = match short_name(&pin) {
+                Ok(name) => name,
+                Err(_) => {
+                    malformed.report(&pin, &ident);
+                    continue;
+                }
+            };
+            match graph.resolve(&name) {
+                Some(ref latest) if *latest == pin => println!("  {}", pin),
+                Some(latest) => {
+                    stale_count += 1;
+                    println!("  {} (latest: {}) *", pin, latest);
+                }
+                None => {
+                    stale_count += 1;
+                    println!("  {} (latest: not in graph) *", pin);
+                }
+            }
+        }
+    });
+
+    let end_time = PreciseTime::now();
+    println!("\nOK: {} packages with pinned deps, {} pins differ from latest, {} fetch \
+              failures ({} sec)\n",
+             pinned_count,
+             stale_count,
+             failures,
              start_time.to(end_time));
+}
 
-    println!("\nAvailable commands: help, stats, top, find, resolve, filter, rdeps, deps, check, \
-              exit\n",);
+// Runs the bench workload, printing the timings as a table or as json for
+// tracking them over time
+fn do_bench(graph: &PackageGraph, args: &[&str]) {
+    let json = args.contains(&"--json");
+    let args: Vec<&str> = args.iter().cloned().filter(|x| *x != "--json").collect();
+    let iterations = match args.get(0).map(|x| x.parse::<usize>()) {
+        None => bench::DEFAULT_ITERATIONS,
+        Some(Ok(iterations)) if iterations > 0 => iterations,
+        _ => {
+            println!("Usage: bench [<iterations>] [--json]\n");
+            return;
+        }
+    };
+
+    if !json {
+        println!("Running the bench workload with {} iterations... please wait.",
+                 iterations);
+    }
+    let report = bench::run(graph, iterations);
+    if json {
+        match serde_json::to_string_pretty(&report) {
+            Ok(json) => println!("{}\n", json),
+            Err(e) => println!("Serializing the bench results failed: {}\n", e),
+        }
+        return;
+    }
+
+    println!("Graph: {} nodes, {} edges\n", report.node_count, report.edge_count);
+    println!("{:<12} {:>8} {:>12} {:>12}", "Operation", "Runs", "Median (ms)", "p95 (ms)");
+    for op in &report.operations {
+        println!("{:<12} {:>8} {:>12.3} {:>12.3}",
+                 op.name, op.runs, op.median_ms, op.p95_ms);
+    }
+    println!("\nScore: {:.3} (sum of the medians in ms, lower is better)\n",
+             report.score);
+}
+
+// Prints the counts and the estimated memory of each graph, and of all of them
+// together, in which the strings the graphs share are only counted once,
+// followed by the resident memory of the process
+fn do_memstats(graph: &Graphs) {
+    let all = graph.all();
+    let width = all.iter().map(|(label, _)| label.len()).max().unwrap_or(0).max(10);
+    println!("{:<width$} {:>9} {:>9} {:>11} {:>11} {:>11} {:>11} {:>11}",
+             "Graph",
+             "Nodes",
+             "Edges",
+             "Strings",
+             "Graph",
+             "Indexes",
+             "Cache",
+             "Total",
+             width = width);
+    let row = |label: &str, stats: &MemoryStats| {
+        println!("{:<width$} {:>9} {:>9} {:>11} {:>11} {:>11} {:>11} {:>11}",
+                 label,
+                 stats.node_count,
+                 stats.edge_count,
+                 format_bytes(stats.string_bytes as u64),
+                 format_bytes(stats.graph_bytes as u64),
+                 format_bytes(stats.index_bytes as u64),
+                 format_bytes(stats.cache_bytes as u64),
+                 format_bytes(stats.total_bytes() as u64),
+                 width = width)
+    };
+    for (label, graph) in &all {
+        row(label, &graph.memory_stats());
+    }
+    let graphs: Vec<&PackageGraph> = all.iter().map(|(_, graph)| *graph).collect();
+    row("All graphs", &MemoryStats::of(&graphs));
+
+    match memstats::process_memory() {
+        Some(memory) => {
+            println!("\nProcess RSS: {} (peak {})\n",
+                     format_bytes(memory.rss_bytes),
+                     format_bytes(memory.peak_bytes))
+        }
+        None => println!("\nProcess RSS: unknown on this platform\n"),
+    }
+}
+
+// Prints a line for each watched package of the config file with its latest
+// ident, its number of direct dependents, and the runtime dependencies of
+// the latest package that are no longer the latest of their names. A
+// watched package that was renamed is shown under its new name.
+fn do_watched(graph: &PackageGraph, watched: &[String], malformed: &mut MalformedIdents) {
+    if watched.is_empty() {
+        println!("No watched packages, list them in watched_packages in the config file\n");
+        return;
+    }
+
+    let width = watched.iter().map(String::len).max().unwrap_or(0);
+    let mut unknown = 0;
+    println!("{:<width$}  {:<48} {:>8}  Deps not latest",
+             "Package",
+             "Latest",
+             "Rdeps",
+             width = width);
+    for name in watched {
+        let name = name.to_lowercase();
+        let (name, renamed) = match graph.alias_of(&name) {
+            Some(new_name) => (new_name, format!(" (renamed from {})", name)),
+            None => (name, String::new()),
+        };
+        let latest = match graph.resolve(&name) {
+            Some(latest) => latest,
+            None => {
+                unknown += 1;
+                println!("{:<width$}  NOT IN GRAPH{}", name, renamed, width = width);
+                continue;
+            }
+        };
+
+        let stale = match graph.deps_of(&name) {
+            Some(deps) => {
+                let mut stale = Vec::new();
+                for dep in &deps.deps {
+                    let dep_name = match short_name(dep) {
+                        Ok(dep_name) => dep_name,
+                        Err(_) => {
+                            malformed.report(dep, &latest);
+                            continue;
+                        }
+                    };
+                    let new_name = graph.alias_of(&dep_name).unwrap_or_else(|| dep_name.clone());
+                    if graph.resolve(&new_name).map_or(false, |l| l != *dep) {
+                        stale.push(dep_name);
+                    }
+                }
+                if stale.is_empty() {
+                    String::from("none")
+                } else {
+                    format!("{} ({})", stale.len(), stale.join(", "))
+                }
+            }
+            None => String::from("unknown"),
+        };
+        println!("{:<width$}  {:<48} {:>8}  {}{}",
+                 name,
+                 latest,
+                 graph.dependent_count(&name).unwrap_or(0),
+                 stale,
+                 renamed,
+                 width = width);
+    }
+
+    println!("\nOK: {} watched packages, {} not in the graph\n", watched.len(), unknown);
+}
+
+fn format_age(secs: i64) -> String {
+    let days = secs / 86_400;
+    let hours = (secs % 86_400) / 3600;
+    let minutes = (secs % 3600) / 60;
+
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+fn do_outdated(graph: &PackageGraph, origin: &str, filter: &str) {
+    let start_time = PreciseTime::now();
+    let outdated: Vec<_> = graph.outdated(origin)
+                                .into_iter()
+                                .filter(|x| matches_filter(&x.ident, filter))
+                                .collect();
+    let end_time = PreciseTime::now();
+
+    println!("OK: {} items ({} sec)\n",
+             outdated.len(),
+             start_time.to(end_time));
+
+    if !filter.is_empty() {
+        println!("Results filtered by: {}", filter);
+    }
+
+    for entry in outdated {
+        println!("{} ({} behind {})",
+                 entry.ident,
+                 format_age(entry.behind_secs),
+                 entry.dep_ident);
+    }
+
+    println!();
+}
+
+// Maps a name or ident argument to the short name the graph is looked up by,
+// noting the version or ident given and whether it is the latest of its name.
+// With follows_latest the command shows what the graph has for the latest
+// package, so the note is a warning when the ident given is an older one.
+fn lookup_name(graph: &PackageGraph, arg: &str, follows_latest: bool) -> Option<String> {
+    let lookup = match graph.lookup_name(&arg.to_lowercase()) {
+        Some(lookup) => lookup,
+        None => {
+            println!("Invalid package name {}, expected origin/name[/version[/release]]\n",
+                     arg);
+            return None;
+        }
+    };
+
+    if follows_latest {
+        if let Some(new_name) = graph.alias_of(&lookup.name) {
+            println!("Following the alias of {}, which was renamed to {}",
+                     lookup.name, new_name);
+            return Some(new_name);
+        }
+    }
+
+    if let (Some(ref given), Some(ref latest)) = (&lookup.given, &lookup.latest) {
+        if lookup.is_latest() {
+            println!("Given {}, which is the latest package ({})", given, latest);
+        } else if follows_latest {
+            println!("Warning: {} is not the latest package of {}, the results are for {}",
+                     given, lookup.name, latest);
+        } else {
+            println!("Note: {} is not the latest package of {}, which is {}",
+                     given, lookup.name, latest);
+        }
+    }
+    Some(lookup.name)
+}
+
+// The graph lists the dependencies of the latest package of each name, so
+// the database is only read for older packages. Without the database, an
+// older package gets the dependencies of the latest one from the graph.
+fn do_deps(datastore: Option<&dyn PackageStore>, graph: &PackageGraph, name: &str, filter: &str) {
+    let short_name = match lookup_name(graph, name, datastore.is_none()) {
+        Some(short_name) => short_name,
+        None => return,
+    };
+    let start_time = PreciseTime::now();
+    let ident = match resolve_name(graph, name) {
+        Ok(ident) => ident,
+        Err(e) => {
+            println!("{}\n", e);
+            return;
+        }
+    };
+
+    match (graph.deps_of(name), datastore) {
+        (Some(deps), _) => print_deps(&ident, &deps, filter, start_time),
+        (None, Some(datastore)) => {
+            match datastore.find_job_graph_package(&ident) {
+                Ok(package) => {
+                    let to_strings = |deps: &[originsrv::OriginPackageIdent]| {
+                        deps.iter().map(|dep| dep.to_string()).collect()
+                    };
+                    let deps = PackageDeps { ident:      package.get_ident().to_string(),
+                                             deps:       to_strings(package.get_deps()),
+                                             build_deps: to_strings(package.get_build_deps()), };
+                    print_deps(&ident, &deps, filter, start_time)
+                }
+                Err(error::Error::UnknownJobGraphPackage) => {
+                    println!("No matching package found for {}", ident)
+                }
+                Err(e) => println!("Failed to fetch {}: {}", ident, e),
+            }
+        }
+        (None, None) => print_graph_deps(graph, &short_name, filter, start_time),
+    }
+
+    println!();
+}
+
+fn print_deps(ident: &str, deps: &PackageDeps, filter: &str, start_time: PreciseTime) {
+    let end_time = PreciseTime::now();
+    if deps.ident != ident {
+        println!("Resolved {} to {}", ident, deps.ident);
+    }
+    println!("Dependencies for: {}", deps.ident);
+    println!("OK: {} runtime, {} build items ({} sec)\n",
+             deps.deps.len(),
+             deps.build_deps.len(),
+             start_time.to(end_time));
+
+    if !filter.is_empty() {
+        println!("Results filtered by: {}\n", filter);
+    }
+
+    println!("Runtime dependencies:");
+    for dep in deps.deps.iter().filter(|dep| matches_filter(dep, filter)) {
+        println!("  {}", dep)
+    }
+
+    println!("Build dependencies:");
+    for dep in deps.build_deps.iter().filter(|dep| matches_filter(dep, filter)) {
+        println!("  {}", dep)
+    }
+}
+
+// Prints the build job that produced the package with the fully-qualified
+// ident, or the most recent job for the 'origin/name' project
+fn do_job(datastore: &dyn PackageStore, graph: &PackageGraph, args: &[&str]) {
+    let name = match args.get(0) {
+        Some(name) => name.to_lowercase(),
+        None => {
+            println!("Missing package name\n");
+            return;
+        }
+    };
+
+    let fully_qualified = name.split('/').count() == 4;
+    let result = if fully_qualified {
+        datastore.get_job_for_package(&name)
+    } else if name.split('/').count() == 2 {
+        datastore.get_latest_job_for_project(&name)
+    } else {
+        println!("Expected a name in origin/name format or a fully qualified ident\n");
+        return;
+    };
+
+    match result {
+        Ok(Some(job)) => {
+            println!("Job: {}", job.id);
+            println!("State: {}", job.state);
+            println!("Package: {}",
+                     job.package_ident.as_ref().map(String::as_str).unwrap_or("-"));
+            println!("Target: {}", job.target);
+            println!("Worker: {}", job.worker.as_ref().map(String::as_str).unwrap_or("-"));
+            println!("Started: {}", format_timestamp(job.started_at));
+            println!("Finished: {}", format_timestamp(job.finished_at));
+            if let (Some(started), Some(finished)) = (job.started_at, job.finished_at) {
+                let secs = finished - started;
+                println!("Duration: {}m {}s", secs / 60, secs % 60);
+            }
+        }
+        Ok(None) if fully_qualified => {
+            match datastore.get_job_graph_package(&name) {
+                Ok(_) => println!("{} has no job record, it was uploaded rather than built", name),
+                Err(error::Error::UnknownJobGraphPackage) => {
+                    println!("No matching package found")
+                }
+                Err(e) => println!("Failed to fetch {}: {}", name, e),
+            }
+        }
+        Ok(None) => {
+            if graph.resolve(&name).is_some() {
+                println!("No jobs found for {}, its packages were uploaded rather than built",
+                         name)
+            } else {
+                println!("No jobs found for {}", name)
+            }
+        }
+        Err(e) => println!("Failed to fetch the job for {}: {}", name, e),
+    }
+
+    println!();
+}
+
+fn format_timestamp(secs: Option<i64>) -> String {
+    match secs {
+        Some(secs) => {
+            format!("{}",
+                    NaiveDateTime::from_timestamp(secs, 0).format("%Y-%m-%d %H:%M:%S UTC"))
+        }
+        None => "-".to_string(),
+    }
+}
+
+// Prints the forward dependencies recorded in the graph edges, with the
+// latest ident of each, used when the graph does not list the idents of the
+// dependencies and there is no datastore to read the package record from, as
+// for a graph loaded from a file that does not list them
+fn print_graph_deps(graph: &PackageGraph, name: &str, filter: &str, start_time: PreciseTime) {
+    let ident = match resolve_name(graph, name) {
+        Ok(ident) => ident,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    println!("Dependencies for: {}", ident);
+
+    match graph.direct_deps(name) {
+        Some(deps) => {
+            let end_time = PreciseTime::now();
+            println!("OK: {} items ({} sec)\n", deps.len(), start_time.to(end_time));
+
+            if !filter.is_empty() {
+                println!("Results filtered by: {}\n", filter);
+            }
+
+            for dep in deps {
+                let dep_ident = graph.resolve(&dep).unwrap_or(dep);
+                if matches_filter(&dep_ident, filter) {
+                    println!("{}", dep_ident)
+                }
+            }
+        }
+        None => println!("No matching package found"),
+    }
+}
+
+// Lists the malformed idents met in the session, with the packages whose
+// records referenced each
+fn do_malformed(malformed: &MalformedIdents) {
+    println!("OK: {} malformed idents skipped in this session\n", malformed.len());
+    for (ident, referenced_by) in malformed.iter() {
+        let referenced_by: Vec<&str> = referenced_by.iter().map(String::as_str).collect();
+        println!("'{}', referenced by {}", ident, referenced_by.join(", "));
+    }
+    if !malformed.is_empty() {
+        println!();
+    }
+}
+
+// Lists the packages and dependencies the graph was built and extended
+// without, with the reason each was left out
+fn do_skipped(graph: &PackageGraph) {
+    let skipped = graph.skipped();
+    println!("OK: {} packages or dependencies skipped building the graph\n", skipped.len());
+    for package in skipped {
+        println!("{}: {}", package.ident, package.reason);
+    }
+    if !skipped.is_empty() {
+        println!();
+    }
+}
+
+fn do_check(datastore: &dyn PackageStore,
+            graph: &PackageGraph,
+            args: &[&str],
+            filter: &str,
+            limits: &LimitsCfg,
+            malformed: &mut MalformedIdents,
+            cancel: &Cancel)
+            -> Result<(), Cancelled> {
+    let name = match args.get(0) {
+        Some(name) if !name.starts_with("--") => name.to_lowercase(),
+        _ => {
+            println!("Missing package name\n");
+            return Ok(());
+        }
+    };
+    // The package checked is the one given, which the database has
+    if lookup_name(graph, &name, false).is_none() {
+        return Ok(());
+    }
 
-    let mut filter = String::from("");
-    let mut done = false;
+    let start_time = PreciseTime::now();
 
-    while !done {
-        let line = cl.read_line_utf8("command> ").ok();
-        if line.is_none() {
-            continue;
-        }
-        let cmd = line.expect("Could not get line");
-        cl.add_history(cmd.clone());
+    match check::check(datastore, graph, &name, filter, limits, cancel) {
+        Ok(Some(report)) => {
+            if report.package != name {
+                println!("Resolved {} to {}\n", name, report.package);
+            }
 
-        let v: Vec<&str> = cmd.trim_end().split_whitespace().collect();
+            if !filter.is_empty() {
+                println!("Checks filtered by: {}\n", filter);
+            }
 
-        if !v.is_empty() {
-            match v[0].to_lowercase().as_str() {
-                "help" => do_help(),
-                "stats" => do_stats(&graph),
-                "top" => {
-                    let count = if v.len() < 2 {
-                        10
-                    } else {
-                        v[1].parse::<usize>().unwrap()
-                    };
-                    do_top(&graph, count);
-                }
-                "filter" => {
-                    if v.len() < 2 {
-                        filter = String::from("");
-                        println!("Removed filter\n");
-                    } else {
-                        filter = String::from(v[1]);
-                        println!("New filter: {}\n", filter);
-                    }
-                }
-                "find" => {
-                    if v.len() < 2 {
-                        println!("Missing search term\n")
-                    } else {
-                        let max = if v.len() > 2 {
-                            v[2].parse::<usize>().unwrap()
-                        } else {
-                            10
-                        };
-                        do_find(&graph, v[1].to_lowercase().as_str(), max)
-                    }
-                }
-                "resolve" => {
-                    if v.len() < 2 {
-                        println!("Missing package name\n")
-                    } else {
-                        do_resolve(&graph, v[1].to_lowercase().as_str())
+            println!("Dependecy version updates:");
+            for dep in &report.dependencies {
+                match dep.alias {
+                    Some(ref old) => {
+                        println!("{} -> {} (following the alias of {})",
+                                 dep.ident, dep.latest, old)
                     }
+                    None => println!("{} -> {}", dep.ident, dep.latest),
                 }
-                "rdeps" => {
-                    if v.len() < 2 {
-                        println!("Missing package name\n")
-                    } else {
-                        let max = if v.len() > 2 {
-                            v[2].parse::<usize>().unwrap()
-                        } else {
-                            10
-                        };
-                        do_rdeps(&graph, v[1].to_lowercase().as_str(), &filter, max)
-                    }
+            }
+
+            println!();
+
+            for conflict in &report.conflicts {
+                println!("Conflict: {} reached at {} versions",
+                         conflict.name,
+                         conflict.reached.len());
+                for reached in &conflict.reached {
+                    println!("  {} via {}", reached.ident, reached.path.join(" -> "));
                 }
-                "deps" => {
-                    if v.len() < 2 {
-                        println!("Missing package name\n")
+            }
+
+            for ident in &report.missing {
+                println!("No matching package found for {}", ident);
+            }
+
+            for dep in &report.malformed {
+                malformed.report(&dep.ident, &dep.referenced_by);
+            }
+
+            for cycle in &report.cycles {
+                println!("Dependency cycle, followed once: {}", cycle.join(", "));
+            }
+
+            if let Some(ref truncated) = report.truncated {
+                if !truncated.depth_limited.is_empty() {
+                    let unchecked = format!("the dependencies of {} packages were not checked",
+                                            truncated.depth_limited.len());
+                    if truncated.max_depth == limits.max_check_depth {
+                        print_limit_reached("max_check_depth", limits.max_check_depth, &unchecked);
                     } else {
-                        do_deps(&datastore, &graph, v[1].to_lowercase().as_str(), &filter)
+                        println!("Max depth of {} reached: {}.", truncated.max_depth, unchecked);
                     }
                 }
-                "check" => {
-                    if v.len() < 2 {
-                        println!("Missing package name\n")
-                    } else {
-                        do_check(&datastore, &graph, v[1].to_lowercase().as_str(), &filter)
-                    }
+                if truncated.node_limited {
+                    print_limit_reached("max_traversal_nodes",
+                                        limits.max_traversal_nodes,
+                                        "the rest of the dependencies were not checked");
                 }
-                "export" => {
-                    if v.len() < 2 {
-                        println!("Missing file name\n")
-                    } else {
-                        do_export(&graph, v[1].to_lowercase().as_str(), &filter)
-                    }
+            }
+
+            println!("\nConflicts: {}, missing: {}, cycles: {}",
+                     report.conflicts.len(),
+                     report.missing.len(),
+                     report.cycles.len());
+            println!("Database queries: {}", report.queries);
+
+            if let Some(filename) = flag_value(args, "--out") {
+                match write_check_report(&report, filename) {
+                    Ok(()) => println!("\nReport written to {}", filename),
+                    Err(e) => println!("\nWriting report to {} failed: {}", filename, e),
                 }
-                "exit" => done = true,
-                _ => println!("Unknown command\n"),
             }
         }
+        Ok(None) => println!("No matching package found"),
+        Err(error::Error::Interrupted) => return Err(Cancelled),
+        Err(e @ error::Error::NotInGraph(..)) => println!("{}", e),
+        Err(e) => println!("Check failed: {}", e),
     }
+
+    let end_time = PreciseTime::now();
+    println!("\nTime: {} sec\n", start_time.to(end_time));
+    Ok(())
 }
 
-fn do_help() {
-    println!("Commands:");
-    println!("  help                    Print this message");
-    println!("  stats                   Print graph statistics");
-    println!("  top     [<count>]       Print nodes with the most reverse dependencies");
-    println!("  filter  [<origin>]      Filter outputs to the specified origin");
-    println!("  resolve <name>          Find the most recent version of the package 'origin/name'");
-    println!("  find    <term> [<max>]  Find packages that match the search term, up to max items");
-    println!("  rdeps   <name> [<max>]  Print the reverse dependencies for the package, up to max");
-    println!("  deps    <name>|<ident>  Print the forward dependencies for the package");
-    println!("  check   <name>|<ident>  Validate the latest dependencies for the package");
-    println!("  export  <filename>      Export data from graph to specified file");
-    println!("  exit                    Exit the application\n");
+fn write_check_report(report: &check::CheckReport, filename: &str) -> error::Result<()> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    serde_json::to_writer_pretty(&mut writer, report)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
 }
 
-fn do_stats(graph: &PackageGraph) {
-    let stats = graph.stats();
+fn do_export(graph: &PackageGraph,
+             args: &[&str],
+             filter: &str,
+             mode: GraphMode,
+             export_cfg: &ExportCfg) {
+    if args.is_empty() {
+        println!("Missing file name\n");
+        return;
+    }
 
-    println!("Node count: {}", stats.node_count);
-    println!("Edge count: {}", stats.edge_count);
-    println!("Connected components: {}", stats.connected_comp);
-    println!("Is cyclic: {}", stats.is_cyclic);
+    // The format is checked before the path, which may move an existing file
+    let default_format = export_cfg.default_format.as_ref().map(String::as_str);
+    let format = match flag_value(args, "--format").or(default_format)
+                                                   .map(ExportFormat::from_str)
+    {
+        None => ExportFormat::Text,
+        Some(Ok(format)) => format,
+        Some(Err(e)) => {
+            println!("{}\n", e);
+            return;
+        }
+    };
+
+    let filename = match export_path(export_cfg, args[0], args) {
+        Some(filename) => filename,
+        None => return,
+    };
+    if let Some(old_filename) = flag_value(args, "--diff") {
+        do_export_diff(graph, &filename, old_filename, filter, args.contains(&"--compress"));
+        return;
+    }
+
+    if !filter.is_empty() {
+        println!("Export filtered by: {}\n", filter);
+    }
+
+    let compress = args.contains(&"--compress");
+    let start_time = PreciseTime::now();
+    match export::export(graph,
+                         &filename,
+                         format,
+                         filter,
+                         mode,
+                         args.contains(&"--idents"),
+                         compress)
+    {
+        Ok((nodes, edges, bytes)) => {
+            let end_time = PreciseTime::now();
+            match format {
+                ExportFormat::Plans => {
+                    println!("OK: {} packages, {} external dependencies ({}) written to {} ({} \
+                              sec)\n",
+                             nodes,
+                             edges,
+                             bytes,
+                             filename,
+                             start_time.to(end_time))
+                }
+                ExportFormat::Csv | ExportFormat::CsvNodes | ExportFormat::Tsv => {
+                    println!("OK: {} rows ({}) written to {} ({} sec)\n",
+                             nodes + edges,
+                             bytes,
+                             filename,
+                             start_time.to(end_time))
+                }
+                _ => {
+                    println!("OK: {} nodes, {} edges ({}) written to {} ({} sec)\n",
+                             nodes,
+                             edges,
+                             bytes,
+                             filename,
+                             start_time.to(end_time))
+                }
+            }
+        }
+        Err(e) => println!("Export to {} failed: {}\n", filename, e),
+    }
 }
 
-fn do_top(graph: &PackageGraph, count: usize) {
+fn do_export_diff(graph: &PackageGraph,
+                  filename: &str,
+                  old_filename: &str,
+                  filter: &str,
+                  compress: bool) {
+    if !filter.is_empty() {
+        println!("Diff filtered by: {}\n", filter);
+    }
+
     let start_time = PreciseTime::now();
-    let top = graph.top(count);
-    let end_time = PreciseTime::now();
+    match export::export_diff(graph, filename, old_filename, filter, compress) {
+        Ok((diff, bytes)) => {
+            let end_time = PreciseTime::now();
+            for (line, entry) in &diff.malformed {
+                println!("{}:{}: malformed ident '{}'", old_filename, line, entry);
+            }
+            println!("OK: {} added, {} removed, {} changed ({}) written to {} ({} sec)\n",
+                     diff.added.len(),
+                     diff.removed.len(),
+                     diff.changed.len(),
+                     bytes,
+                     filename,
+                     start_time.to(end_time));
+        }
+        Err(e) => println!("Export to {} failed: {}\n", filename, e),
+    }
+}
 
-    println!("OK: {} items ({} sec)\n",
-             top.len(),
-             start_time.to(end_time));
+fn do_export_dot(graph: &PackageGraph, args: &[&str], export_cfg: &ExportCfg) {
+    let depth = args.get(1).and_then(|x| x.parse::<usize>().ok());
+    let (name, depth, filename) = match (args.get(0), depth, args.get(2)) {
+        (Some(name), Some(depth), Some(filename)) => (name.to_lowercase(), depth, filename),
+        _ => {
+            println!("Usage: export-dot <name> <depth> <filename> [--force] [--compress]\n");
+            return;
+        }
+    };
+    let compress = args[3..].contains(&"--compress");
+    let filename = match export_path(export_cfg, filename, &args[3..]) {
+        Some(filename) => filename,
+        None => return,
+    };
 
-    for (name, count) in top {
-        println!("{}: {}", name, count);
+    let start_time = PreciseTime::now();
+    match export::export_dot_neighborhood(graph, &name, depth, &filename, compress) {
+        Ok(Some((nodes, edges, bytes))) => {
+            let end_time = PreciseTime::now();
+            println!("OK: {} nodes, {} edges ({}) written to {} ({} sec)\n",
+                     nodes,
+                     edges,
+                     bytes,
+                     filename,
+                     start_time.to(end_time));
+        }
+        Ok(None) => println!("No entries found\n"),
+        Err(e) => println!("Export to {} failed: {}\n", filename, e),
     }
-    println!();
 }
 
-fn do_find(graph: &PackageGraph, phrase: &str, max: usize) {
+fn do_resolve_file(graph: &PackageGraph, args: &[&str]) {
+    let (input, output) = match (args.get(0), args.get(1)) {
+        (Some(input), Some(output)) => (input, output),
+        _ => {
+            println!("Usage: resolve-file <input> <output>\n");
+            return;
+        }
+    };
+
     let start_time = PreciseTime::now();
-    let mut v = graph.search(phrase);
-    let end_time = PreciseTime::now();
+    match export::resolve_file(graph, input, output) {
+        Ok(totals) => {
+            let end_time = PreciseTime::now();
+            println!("OK: {} resolved, {} not found, {} malformed, written to {} ({} sec)\n",
+                     totals.resolved,
+                     totals.not_found,
+                     totals.malformed,
+                     output,
+                     start_time.to(end_time));
+        }
+        Err(e) => println!("Resolving {} failed: {}\n", input, e),
+    }
+}
 
-    println!("OK: {} items ({} sec)\n", v.len(), start_time.to(end_time));
+fn do_report(graph: &PackageGraph, args: &[&str], export_cfg: &ExportCfg) {
+    let filename = match args.get(0) {
+        Some(filename) => filename,
+        None => {
+            println!("Missing file name\n");
+            return;
+        }
+    };
 
-    if v.is_empty() {
-        println!("No matching packages found")
-    } else {
-        if v.len() > max {
-            v.drain(max..);
+    let format = match flag_value(args, "--format").map(ExportFormat::from_str) {
+        None => ExportFormat::Csv,
+        Some(Ok(format @ ExportFormat::Csv)) | Some(Ok(format @ ExportFormat::Json)) => format,
+        Some(_) => {
+            println!("Usage: report <filename> [--format csv|json] [--top <count>] \
+                      [--compress]\n");
+            return;
         }
-        for s in v {
-            println!("{}", s);
+    };
+
+    let top = match flag_value(args, "--top").map(|x| x.parse::<usize>()) {
+        None => 10,
+        Some(Ok(top)) => top,
+        Some(Err(_)) => {
+            println!("Usage: report <filename> [--format csv|json] [--top <count>] \
+                      [--compress]\n");
+            return;
         }
-    }
+    };
 
-    println!();
+    let compress = args.contains(&"--compress");
+    let filename = match export_path(export_cfg, filename, args) {
+        Some(filename) => filename,
+        None => return,
+    };
+    let start_time = PreciseTime::now();
+    match export::export_report(graph, &filename, format, top, compress) {
+        Ok((count, bytes)) => {
+            let end_time = PreciseTime::now();
+            println!("OK: {} origins ({}) written to {} ({} sec)\n",
+                     count,
+                     bytes,
+                     filename,
+                     start_time.to(end_time));
+        }
+        Err(e) => println!("Report to {} failed: {}\n", filename, e),
+    }
 }
 
-fn do_resolve(graph: &PackageGraph, name: &str) {
+fn do_save_cache(graph: &PackageGraph, args: &[&str], source: &str) {
+    let filename = match args.get(0) {
+        Some(filename) => filename,
+        None => {
+            println!("Missing file name\n");
+            return;
+        }
+    };
+
     let start_time = PreciseTime::now();
-    let result = graph.resolve(name);
-    let end_time = PreciseTime::now();
+    match cache::save_cache(graph, filename, source) {
+        Ok((nodes, edges)) => {
+            let end_time = PreciseTime::now();
+            println!("OK: {} nodes, {} edges written to {} ({} sec)\n",
+                     nodes,
+                     edges,
+                     filename,
+                     start_time.to(end_time));
+        }
+        Err(e) => println!("Saving cache to {} failed: {}\n", filename, e),
+    }
+}
 
-    println!("OK: ({} sec)\n", start_time.to(end_time));
+fn do_export_manifest(graph: &PackageGraph, args: &[&str], export_cfg: &ExportCfg) {
+    let filename = match args.get(0) {
+        Some(filename) => filename,
+        None => {
+            println!("Missing file name\n");
+            return;
+        }
+    };
+    let origin = args.get(1)
+                     .filter(|x| !x.starts_with("--"))
+                     .map(|x| x.to_lowercase())
+                     .unwrap_or_default();
+    let compress = args.contains(&"--compress");
+    let filename = match export_path(export_cfg, filename, args) {
+        Some(filename) => filename,
+        None => return,
+    };
 
-    match result {
-        Some(s) => println!("{}", s),
-        None => println!("No matching packages found"),
+    let start_time = PreciseTime::now();
+    match export::export_manifest(graph, &filename, &origin, compress) {
+        Ok((ordered, trapped, bytes)) => {
+            let end_time = PreciseTime::now();
+            println!("OK: {} packages, {} in cycles ({}), written to {} ({} sec)\n",
+                     ordered + trapped,
+                     trapped,
+                     bytes,
+                     filename,
+                     start_time.to(end_time));
+        }
+        Err(e) => println!("Export to {} failed: {}\n", filename, e),
     }
-
-    println!();
 }
 
-fn do_rdeps(graph: &PackageGraph, name: &str, filter: &str, max: usize) {
-    let start_time = PreciseTime::now();
+fn do_export_cycles(graph: &PackageGraph, args: &[&str], export_cfg: &ExportCfg) {
+    let filename = match args.get(0) {
+        Some(filename) => filename,
+        None => {
+            println!("Missing file name\n");
+            return;
+        }
+    };
+    let compress = args.contains(&"--compress");
+    let filename = match export_path(export_cfg, filename, args) {
+        Some(filename) => filename,
+        None => return,
+    };
 
-    match graph.rdeps(name) {
-        Some(rdeps) => {
+    let start_time = PreciseTime::now();
+    match export::export_cycles(graph, &filename, compress) {
+        Ok((cycles, trapped, bytes)) => {
             let end_time = PreciseTime::now();
-            let mut filtered: Vec<(String, String)> =
-                rdeps.into_iter()
-                     .filter(|&(ref x, _)| x.starts_with(filter))
-                     .collect();
-
-            println!("OK: {} items ({} sec)\n",
-                     filtered.len(),
+            println!("OK: {} cycles, {} packages in cycles ({}), written to {} ({} sec)\n",
+                     cycles,
+                     trapped,
+                     bytes,
+                     filename,
                      start_time.to(end_time));
+        }
+        Err(e) => println!("Export to {} failed: {}\n", filename, e),
+    }
+}
 
-            if filtered.len() > max {
-                filtered.drain(max..);
-            }
+fn do_export_rdeps(graph: &PackageGraph, args: &[&str], filter: &str, export_cfg: &ExportCfg) {
+    let (name, filename) = match (args.get(0), args.get(1)) {
+        (Some(name), Some(filename)) => (name.to_lowercase(), filename),
+        _ => {
+            println!("Usage: export-rdeps <name> <filename> [--force] [--compress]\n");
+            return;
+        }
+    };
+    let force = args[2..].contains(&"--force");
+    let compress = args[2..].contains(&"--compress");
+    let filename = match export_path(export_cfg, filename, &args[2..]) {
+        Some(filename) => filename,
+        None => return,
+    };
 
-            if !filter.is_empty() {
-                println!("Results filtered by: {}", filter);
-            }
+    if !filter.is_empty() {
+        println!("Export filtered by: {}\n", filter);
+    }
 
-            for (s1, s2) in filtered {
-                println!("{} ({})", s1, s2);
-            }
+    let start_time = PreciseTime::now();
+    match export::export_rdeps(graph, &name, &filename, filter, force, compress) {
+        Ok(Some((count, bytes))) => {
+            let end_time = PreciseTime::now();
+            println!("OK: {} reverse dependencies of {} ({}) written to {} ({} sec)\n",
+                     count,
+                     name,
+                     bytes,
+                     filename,
+                     start_time.to(end_time));
         }
-        None => println!("No entries found"),
+        Ok(None) => println!("Package {} not found in the graph, nothing exported\n", name),
+        Err(e) => println!("Export to {} failed: {}\n", filename, e),
     }
-
-    println!();
 }
 
-fn resolve_name(graph: &PackageGraph, name: &str) -> String {
-    let parts: Vec<&str> = name.split('/').collect();
-    if parts.len() == 2 {
-        match graph.resolve(name) {
-            Some(s) => s,
-            None => String::from(name),
+// The absolute path the export command writes the file to, as the export
+// defaults of the config file make it, or None after printing why the file
+// cannot be written. --force replaces an existing file whatever the config
+// says.
+fn export_path(export_cfg: &ExportCfg, filename: &str, args: &[&str]) -> Option<String> {
+    match export::output_path(export_cfg, filename, args.contains(&"--force")) {
+        Ok(path) => Some(path),
+        Err(e) => {
+            println!("Export to {} failed: {}\n", filename, e);
+            None
         }
-    } else {
-        String::from(name)
     }
 }
 
-fn do_deps(datastore: &DataStore, graph: &PackageGraph, name: &str, filter: &str) {
+// Returns the value following the flag in the command arguments, if any
+fn flag_value<'a>(args: &[&'a str], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|x| *x == flag)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
+
+// Parses a package list entry, which is either 'origin/name' or a
+// fully-qualified (or versioned) package ident
+fn parse_list_entry(entry: &str) -> Option<PackageIdent> {
+    let parts: Vec<&str> = entry.split('/').collect();
+    if parts.len() < 2 || parts.len() > 4 || parts.iter().any(|p| p.is_empty()) {
+        return None;
+    }
+    PackageIdent::from_str(entry).ok()
+}
+
+fn do_coverage(graph: &PackageGraph, filename: &str, filter: &str) {
     let start_time = PreciseTime::now();
-    let ident = resolve_name(graph, name);
 
-    println!("Dependencies for: {}", ident);
+    let file = match File::open(filename) {
+        Ok(file) => file,
+        Err(e) => {
+            println!("Unable to open {}: {}\n", filename, e);
+            return;
+        }
+    };
 
-    match datastore.get_job_graph_package(&ident) {
-        Ok(package) => {
-            let end_time = PreciseTime::now();
-            println!("OK: {} items ({} sec)\n",
-                     package.get_deps().len(),
-                     start_time.to(end_time));
+    let mut listed = HashSet::new();
+    let mut present = 0;
+    let mut missing = Vec::new();
+    let mut newer = Vec::new();
+    let mut older = Vec::new();
+    let mut malformed = Vec::new();
 
-            if !filter.is_empty() {
-                println!("Results filtered by: {}\n", filter);
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                println!("Error reading {} at line {}: {}\n", filename, index + 1, e);
+                return;
+            }
+        };
+
+        let entry = line.trim();
+        if entry.is_empty() {
+            continue;
+        }
+
+        let ident = match parse_list_entry(entry) {
+            Some(ident) => ident,
+            None => {
+                malformed.push((index + 1, entry.to_string()));
+                continue;
             }
+        };
+
+        let name = format!("{}/{}", ident.origin, ident.name);
+        listed.insert(name.clone());
 
-            for dep in package.get_deps() {
-                if dep.to_string().starts_with(filter) {
-                    println!("{}", dep)
+        match graph.resolve(&name) {
+            Some(latest) => {
+                present += 1;
+                if ident.version.is_some() {
+                    let latest_ident = PackageIdent::from_str(&latest).unwrap();
+                    match compare_idents(&latest_ident, &ident) {
+                        Ordering::Greater => newer.push((entry.to_string(), latest)),
+                        Ordering::Less => older.push((entry.to_string(), latest)),
+                        Ordering::Equal => (),
+                    }
                 }
             }
+            None => missing.push(entry.to_string()),
+        }
+    }
+
+    // The latest idents are those of the names in the graph, so they have one
+    let unlisted: Vec<String> = graph.latest()
+                                         .iter()
+                                         .filter(|x| matches_filter(x, filter))
+                                         .filter_map(|x| short_name(x).ok())
+                                         .filter(|x| !listed.contains(x))
+                                         .collect();
+
+    let end_time = PreciseTime::now();
+
+    println!("OK: {} entries, {} malformed ({} sec)\n",
+             present + missing.len(),
+             malformed.len(),
+             start_time.to(end_time));
+
+    println!("Present in graph: {}", present);
+
+    println!("Missing from graph: {}", missing.len());
+    for entry in missing {
+        println!("  {}", entry);
+    }
+
+    println!("Graph latest is newer: {}", newer.len());
+    for (entry, latest) in newer {
+        println!("  {} -> {}", entry, latest);
+    }
+
+    println!("Graph latest is older: {}", older.len());
+    for (entry, latest) in older {
+        println!("  {} -> {}", entry, latest);
+    }
+
+    if !filter.is_empty() {
+        println!("Unlisted results filtered by: {}", filter);
+    }
+
+    println!("In graph but not in list: {}", unlisted.len());
+    for name in unlisted {
+        println!("  {}", name);
+    }
+
+    if !malformed.is_empty() {
+        println!("Malformed lines: {}", malformed.len());
+        for (line_num, entry) in malformed {
+            println!("  line {}: {}", line_num, entry);
         }
-        Err(_) => println!("No matching package found"),
     }
 
     println!();
 }
 
-fn short_name(ident: &str) -> String {
-    let parts: Vec<&str> = ident.split('/').collect();
-    assert!(parts.len() >= 2);
-    format!("{}/{}", parts[0], parts[1])
+// Records the ident in the map if it is newer than the one already present
+// for the same 'origin/name'
+fn insert_newest(map: &mut HashMap<String, PackageIdent>, ident: &str) {
+    let ident = match PackageIdent::from_str(ident) {
+        Ok(ident) => ident,
+        Err(_) => return,
+    };
+    let name = format!("{}/{}", ident.origin, ident.name);
+
+    let is_newer = match map.get(&name) {
+        Some(existing) => compare_idents(&ident, existing) == Ordering::Greater,
+        None => true,
+    };
+
+    if is_newer {
+        map.insert(name, ident);
+    }
 }
 
-fn do_check(datastore: &DataStore, graph: &PackageGraph, name: &str, filter: &str) {
+fn do_promote_sim(datastore: &dyn PackageStore,
+                  graph: &PackageGraph,
+                  args: &[&str],
+                  malformed: &mut MalformedIdents) {
+    if args.len() < 2 {
+        println!("Missing channel or package names\n");
+        return;
+    }
+
+    let channel = args[0];
+    let names: Vec<String> = args[1..].iter().map(|x| x.to_lowercase()).collect();
     let start_time = PreciseTime::now();
-    let mut deps_map = HashMap::new();
-    let mut new_deps = Vec::new();
-    let ident = resolve_name(graph, name);
 
-    match datastore.get_job_graph_package(&ident) {
-        Ok(package) => {
-            if !filter.is_empty() {
-                println!("Checks filtered by: {}\n", filter);
-            }
+    let channel_idents = match datastore.get_channel_packages(channel) {
+        Ok(idents) => idents,
+        Err(e) => {
+            println!("Unable to retrieve packages in channel {}: {}\n", channel, e);
+            return;
+        }
+    };
 
-            println!("Dependecy version updates:");
-            for dep in package.get_deps() {
-                if dep.to_string().starts_with(filter) {
-                    let dep_name = short_name(&dep.to_string());
-                    let dep_latest = resolve_name(graph, &dep_name);
-                    deps_map.insert(dep_name, dep_latest.clone());
-                    new_deps.push(dep_latest.clone());
-                    println!("{} -> {}", dep, dep_latest);
-                }
-            }
+    let mut breaks = Vec::new();
+    let mut promotions = Vec::new();
+    for name in &names {
+        match resolve_name(graph, name) {
+            Ok(ident) => promotions.push(ident),
+            Err(e) => breaks.push(e.to_string()),
+        }
+    }
 
-            println!();
+    // What the channel will serve for each package once the promotion lands
+    let mut available = HashMap::new();
+    for ident in channel_idents.iter().chain(promotions.iter()) {
+        insert_newest(&mut available, ident);
+    }
+
+    println!("Simulating promotion of {} packages to '{}' ({} packages in channel)\n",
+             promotions.len(),
+             channel,
+             channel_idents.len());
+
+    let mut fine = Vec::new();
 
-            for new_dep in new_deps {
-                check_package(datastore, &mut deps_map, &new_dep, filter);
+    for ident in promotions {
+        let package = match datastore.get_job_graph_package(&ident) {
+            Ok(package) => package,
+            Err(_) => {
+                breaks.push(format!("{}: no matching package found", ident));
+                continue;
+            }
+        };
+
+        // The exact versions the package was built against
+        let mut pinned = HashMap::new();
+        for dep in package.get_tdeps() {
+            let dep = dep.to_string();
+            match short_name(&dep) {
+                Ok(name) => {
+                    pinned.insert(name, dep);
+                }
+                Err(_) => malformed.report(&dep, &ident),
             }
         }
-        Err(_) => println!("No matching package found"),
-    }
 
-    let end_time = PreciseTime::now();
-    println!("\nTime: {} sec\n", start_time.to(end_time));
-}
+        let mut closure: BTreeSet<String> = pinned.keys().cloned().collect();
+        if let Ok(name) = short_name(&ident) {
+            closure.extend(graph.deps_closure(&name, EdgeFilter::All).unwrap_or_default());
+        }
 
-fn check_package(datastore: &DataStore,
-                 deps_map: &mut HashMap<String, String>,
-                 ident: &str,
-                 filter: &str) {
-    match datastore.get_job_graph_package(ident) {
-        Ok(package) => {
-            for dep in package.get_deps() {
-                if dep.to_string().starts_with(filter) {
-                    let name = short_name(&dep.to_string());
-                    {
-                        let entry = deps_map.entry(name).or_insert_with(|| dep.to_string());
-                        if *entry != dep.to_string() {
-                            println!("Conflict: {}", ident);
-                            println!("  {}", *entry);
-                            println!("  {}", dep);
+        let mut problems = Vec::new();
+        for dep in closure {
+            match available.get(&dep) {
+                Some(served) => {
+                    if let Some(required) = pinned.get(&dep) {
+                        if *required != served.to_string() {
+                            problems.push(format!("{}: requires {}, '{}' would serve {}",
+                                                  ident, required, channel, served));
                         }
                     }
-                    check_package(datastore, deps_map, &dep.to_string(), filter);
                 }
+                None => problems.push(format!("{}: {} is missing from '{}'", ident, dep, channel)),
             }
         }
-        Err(_) => println!("No matching package found for {}", ident),
-    };
-}
 
-fn do_export(graph: &PackageGraph, filename: &str, filter: &str) {
-    let start_time = PreciseTime::now();
-    let latest = graph.latest();
+        if problems.is_empty() {
+            fine.push(ident);
+        } else {
+            breaks.extend(problems);
+        }
+    }
+
+    println!("Would break: {}", breaks.len());
+    for s in breaks {
+        println!("  {}", s);
+    }
+
+    println!("Fine: {}", fine.len());
+    for s in fine {
+        println!("  {}", s);
+    }
+
     let end_time = PreciseTime::now();
     println!("\nTime: {} sec\n", start_time.to(end_time));
+}
 
-    let mut file = File::create(filename).expect("Failed to initialize file");
+// Enables the feature flags of the config file and of the --feature flags,
+// warning about the unknown ones and printing the ones enabled. With
+// --list-features, prints the feature flags and exits instead.
+fn enable_features(config: &Config, matches: &ArgMatches) {
+    let features: BTreeMap<_, _> = BTreeMap::from_iter(vec![("BUILDDEPS", feat::BuildDeps)]);
+    let names: Vec<&str> = features.keys().cloned().collect();
 
-    if !filter.is_empty() {
-        println!("Checks filtered by: {}\n", filter);
+    if matches.is_present("list-features") {
+        feat::enable(feat::List);
     }
-
-    for ident in latest {
-        if ident.starts_with(filter) {
-            file.write_fmt(format_args!("{}\n", ident)).unwrap();
-        }
+    if feat::is_enabled(feat::List) {
+        println!("Listing possible feature flags: {}", names.join(", "));
+        println!("Enable features by populating 'features_enabled' in config, or with \
+                  --feature <NAME>");
+        process::exit(0);
     }
-}
 
-fn enable_features(config: &Config) {
-    let features: HashMap<_, _> = HashMap::from_iter(vec![("BUILDDEPS", feat::BuildDeps)]);
     let features_enabled = config.features_enabled
                                  .split(',')
-                                 .map(|f| f.trim().to_uppercase());
+                                 .chain(matches.values_of("feature").into_iter().flatten())
+                                 .map(|f| f.trim().to_uppercase())
+                                 .filter(|f| !f.is_empty());
 
+    let mut enabled = BTreeSet::new();
     for key in features_enabled {
-        if features.contains_key(key.as_str()) {
-            info!("Enabling feature: {}", key);
-            feat::enable(features[key.as_str()]);
+        match features.get(key.as_str()) {
+            Some(&feature) => {
+                info!("Enabling feature: {}", key);
+                feat::enable(feature);
+                enabled.insert(key);
+            }
+            None => {
+                println!("Unknown feature flag {}, ignored. Feature flags: {}",
+                         key,
+                         names.join(", "))
+            }
         }
     }
 
-    if feat::is_enabled(feat::List) {
-        println!("Listing possible feature flags: {:?}", features.keys());
-        println!("Enable features by populating 'features_enabled' in config");
-    }
+    println!("Features enabled: {}",
+             if enabled.is_empty() {
+                 "none".to_string()
+             } else {
+                 Vec::from_iter(enabled).join(", ")
+             });
 }
 
 features! {
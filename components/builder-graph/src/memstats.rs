@@ -0,0 +1,80 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The memory of the process as the kernel counts it, shown by memstats next
+// to the estimates of the graphs: the resident set now and at its peak, which
+// is usually reached while building. Only known where /proc is, on Linux.
+
+use std::fs;
+
+#[derive(Debug, PartialEq)]
+pub struct ProcessMemory {
+    pub rss_bytes:  u64,
+    pub peak_bytes: u64,
+}
+
+pub fn process_memory() -> Option<ProcessMemory> {
+    parse_status(&fs::read_to_string("/proc/self/status").ok()?)
+}
+
+// The fields of /proc/self/status are given in kB, as in 'VmRSS:   1234 kB'
+fn parse_status(status: &str) -> Option<ProcessMemory> {
+    let bytes = |field: &str| {
+        status.lines()
+              .find(|line| line.starts_with(field))
+              .and_then(|line| line.split_whitespace().nth(1))
+              .and_then(|kb| kb.parse::<u64>().ok())
+              .map(|kb| kb * 1024)
+    };
+    Some(ProcessMemory { rss_bytes:  bytes("VmRSS:")?,
+                         peak_bytes: bytes("VmHWM:")?, })
+}
+
+// The bytes in the largest binary unit they make at least one of
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_fields_are_read_in_bytes() {
+        let status = "Name:\tbldr-graph\nVmPeak:\t  900000 kB\nVmHWM:\t    4096 kB\nVmRSS:\t    \
+                      2048 kB\nThreads:\t1\n";
+        assert_eq!(parse_status(status),
+                   Some(ProcessMemory { rss_bytes:  2048 * 1024,
+                                        peak_bytes: 4096 * 1024, }));
+        assert_eq!(parse_status("Name:\tbldr-graph\nVmRSS:\t2048 kB\n"), None);
+    }
+
+    #[test]
+    fn bytes_are_shown_in_binary_units() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
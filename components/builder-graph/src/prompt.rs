@@ -0,0 +1,81 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The prompt shown before each command, rendered from the prompt of the
+// config file every time it is shown, so that it follows the filter, the
+// target and the graph as they change. The tokens are %d for the database
+// name (or the file the graph was loaded from), %f for the filter, %n for the
+// node count, %t for the target and %% for a percent sign. Any other token is
+// shown as written.
+
+use std::fmt::Write;
+
+pub struct PromptValues<'a> {
+    pub database:   &'a str,
+    pub filter:     &'a str,
+    pub node_count: usize,
+    pub target:     Option<&'a str>,
+}
+
+pub fn render(template: &str, values: &PromptValues) -> String {
+    let mut prompt = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            prompt.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('d') => prompt.push_str(values.database),
+            Some('f') => prompt.push_str(values.filter),
+            Some('n') => write!(prompt, "{}", values.node_count).unwrap(),
+            Some('t') => prompt.push_str(values.target.unwrap_or("all targets")),
+            Some('%') => prompt.push('%'),
+            Some(other) => {
+                prompt.push('%');
+                prompt.push(other);
+            }
+            None => prompt.push('%'),
+        }
+    }
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values() -> PromptValues<'static> {
+        PromptValues { database:   "builder",
+                       filter:     "core",
+                       node_count: 1224,
+                       target:     Some("x86_64-linux"), }
+    }
+
+    #[test]
+    fn tokens_are_substituted() {
+        assert_eq!(render("command> ", &values()), "command> ");
+        assert_eq!(render("%d:%t [%f] %n> ", &values()),
+                   "builder:x86_64-linux [core] 1224> ");
+        assert_eq!(render("100%% %t> ", &PromptValues { target: None,
+                                                       ..values() }),
+                   "100% all targets> ");
+    }
+
+    #[test]
+    fn unknown_tokens_are_shown_as_written() {
+        assert_eq!(render("%x %d %", &values()), "%x builder %");
+        assert_eq!(render("%é>", &values()), "%é>");
+    }
+}
@@ -0,0 +1,90 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Runs the tool with a script of commands piped in, which it has to run and
+// then exit on, rather than waiting for more at the prompt.
+
+use std::{env,
+          io::Write,
+          path::PathBuf,
+          process::{Command,
+                    Stdio},
+          thread,
+          time::{Duration,
+                 Instant}};
+
+// How long the tool gets to run the script before it is taken to be stuck
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+// The tool, which cargo builds next to the tests
+fn bldr_graph() -> PathBuf {
+    let mut path = env::current_exe().unwrap();
+    path.pop();
+    if path.ends_with("deps") {
+        path.pop();
+    }
+    path.join(format!("bldr-graph{}", env::consts::EXE_SUFFIX))
+}
+
+// Runs the script with the fixture graph, checking that the tool exits
+// cleanly once it is done, and returns what it printed
+fn run_script(script: &str) -> String {
+    let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/graph-packages.json");
+    let mut child = Command::new(bldr_graph()).args(&["--no-rc", "--fixture", fixture])
+                                              .stdin(Stdio::piped())
+                                              .stdout(Stdio::piped())
+                                              .spawn()
+                                              .unwrap();
+    child.stdin
+         .take()
+         .unwrap()
+         .write_all(script.as_bytes())
+         .unwrap();
+
+    let started = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        if started.elapsed() > TIMEOUT {
+            child.kill().unwrap();
+            panic!("bldr-graph did not exit once its input ended");
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "bldr-graph exited with {}", output.status);
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn piped_commands_run_until_the_input_ends() {
+    let stdout = run_script("stats\nno-such-command\nfilter core\nstats\n");
+    assert_eq!(stdout.matches("Unknown command").count(), 1, "{}", stdout);
+}
+
+#[test]
+fn bad_max_arguments_print_the_usage() {
+    let cases = [("top abc", "Usage: top "),
+                 ("find ssl -1", "Usage: find "),
+                 ("rdeps core/openssl 99999999999999999999999", "Usage: rdeps "),
+                 ("dependents core/glibc 0", "Usage: dependents ")];
+    for &(command, usage) in &cases {
+        let stdout = run_script(&format!("{}\nstats\n", command));
+        let usage_at = stdout.find(usage)
+                             .unwrap_or_else(|| panic!("no usage for {}: {}", command, stdout));
+        assert!(stdout[usage_at..].contains("Node count: "),
+                "stats did not run after {}: {}",
+                command,
+                stdout);
+    }
+}